@@ -1,3 +1,5 @@
+use std::error::Error;
+
 use manifold::nn::types::{GradientRetention, Manifold};
 use manifold::nn::DNN;
 use manifold::optimizers::MiniBatchGradientDescent;
@@ -5,6 +7,7 @@ use manifold::util::as_tensor;
 use manifold::Activations;
 use manifold::Losses;
 use manifold::Substrate;
+use ndarray::Array2;
 
 use rand::{prelude::*, thread_rng};
 
@@ -22,7 +25,7 @@ fn gen_training_data() -> (Vec<f64>, Vec<f64>) {
     (data.0.clone(), data.1.clone())
 }
 
-fn main() {
+fn main() -> Result<(), Box<dyn Error>> {
     let (mut x, mut y) = (vec![], vec![]);
     for _ in 0..5000 {
         let (_x, _y) = gen_training_data();
@@ -43,10 +46,10 @@ fn main() {
     nn.set_hidden_activation(Activations::Relu)
         .set_loss(Losses::MeanSquaredError)
         .set_gradient_retention(GradientRetention::Roll)
-        .weave()
-        .gather();
+        .weave()?
+        .gather()?;
 
-    let threes = as_tensor(x, y);
+    let threes = as_tensor(x, y)?;
 
     let mut trainer = MiniBatchGradientDescent::new(&mut nn);
     trainer
@@ -55,6 +58,18 @@ fn main() {
         .set_epochs(1000)
         .set_sample_size(100)
         .verbose()
-        .train(&threes.0, &threes.1)
+        .train(&threes.0, &threes.1)?
         .loss_graph();
+
+    let test = Array2::from_shape_vec((tx.len(), 2), tx.concat()).unwrap();
+    let classes = nn.predict_class(test.view())?;
+    let correct = classes
+        .iter()
+        .zip(ty.iter())
+        .filter(|(class, target)| target[**class] == 1.)
+        .count();
+
+    println!("Test accuracy {}/{}", correct, ty.len());
+
+    Ok(())
 }
@@ -37,6 +37,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             patience: 300,
             min_delta: 0.001,
             early_stopping: false,
+            ..Hyper::new()
         });
 
     let manifolds = neat.sift()?;
@@ -0,0 +1,88 @@
+use std::error::Error;
+use std::time::Instant;
+
+use manifold::nn::types::{GradientRetention, Manifold};
+use manifold::nn::DNN;
+use manifold::optimizers::{Hogwild, MiniBatchGradientDescent};
+use manifold::util::as_tensor;
+use manifold::Activations;
+use manifold::Losses;
+use manifold::Substrate;
+
+use rand::{prelude::*, thread_rng};
+
+fn gen_training_data() -> (Vec<f64>, Vec<f64>) {
+    let mut rng = thread_rng();
+
+    let mutator = |x: f64| x.exp() * x.powi(3);
+
+    let num = rng.gen_range(0.0..1.0);
+
+    (vec![num], vec![mutator(num)])
+}
+
+fn tail_loss(losses: &[f64]) -> f64 {
+    let tail = &losses[losses.len().saturating_sub(50)..];
+    tail.iter().sum::<f64>() / tail.len() as f64
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let (mut x, mut y) = (vec![], vec![]);
+    for _ in 0..5000 {
+        let (_x, _y) = gen_training_data();
+        x.push(_x);
+        y.push(_y);
+    }
+
+    let threes = as_tensor(x, y)?;
+    let epochs = 2000;
+
+    let substrate = Substrate::new(10000, 0.0..1.0).share();
+
+    let mut nn = DNN::new(substrate, 1, 1, vec![32, 32]);
+    nn.set_hidden_activation(Activations::Relu)
+        .set_loss(Losses::MeanSquaredError)
+        .set_gradient_retention(GradientRetention::Roll)
+        .weave()?
+        .gather()?;
+
+    let mut hogwild_nn = nn.clone();
+
+    let start = Instant::now();
+    let mut trainer = MiniBatchGradientDescent::new(&mut nn);
+    trainer
+        .set_learning_rate(0.1)
+        .set_decay(0.999)
+        .set_epochs(epochs)
+        .set_sample_size(100)
+        .train(&threes.0, &threes.1)?;
+    let mbgd_elapsed = start.elapsed();
+    let mbgd_loss = tail_loss(&trainer.losses);
+
+    let start = Instant::now();
+    let mut hogwild = Hogwild::new(&mut hogwild_nn);
+    hogwild
+        .set_learning_rate(0.1)
+        .set_decay(0.999)
+        .set_epochs(epochs)
+        .set_sample_size(100)
+        .train(&threes.0, &threes.1)?;
+    let hogwild_elapsed = start.elapsed();
+    let hogwild_loss = tail_loss(&hogwild.losses);
+
+    println!("{} updates of batch 100", epochs);
+    println!(
+        "MiniBatchGradientDescent: {:?} ({:.1} updates/s), tail loss {}",
+        mbgd_elapsed,
+        epochs as f64 / mbgd_elapsed.as_secs_f64(),
+        mbgd_loss
+    );
+    println!(
+        "Hogwild:                  {:?} ({:.1} updates/s), tail loss {}",
+        hogwild_elapsed,
+        epochs as f64 / hogwild_elapsed.as_secs_f64(),
+        hogwild_loss
+    );
+
+    Ok(())
+}
@@ -1,3 +1,5 @@
+use std::error::Error;
+
 use manifold::nn::types::{GradientRetention, Manifold};
 use manifold::nn::DNN;
 use manifold::optimizers::MiniBatchGradientDescent;
@@ -19,7 +21,7 @@ fn gen_training_data() -> (Vec<f64>, Vec<f64>) {
     (vec![num], vec![mutator(num)])
 }
 
-fn main() {
+fn main() -> Result<(), Box<dyn Error>> {
     let (mut x, mut y) = (vec![], vec![]);
     for _ in 0..5000 {
         let (_x, _y) = gen_training_data();
@@ -40,10 +42,10 @@ fn main() {
     nn.set_hidden_activation(Activations::Relu)
         .set_loss(Losses::MeanSquaredError)
         .set_gradient_retention(GradientRetention::Roll)
-        .weave()
-        .gather();
+        .weave()?
+        .gather()?;
 
-    let threes = as_tensor(x, y);
+    let threes = as_tensor(x, y)?;
 
     let mut trainer = MiniBatchGradientDescent::new(&mut nn);
     trainer
@@ -52,6 +54,8 @@ fn main() {
         .set_epochs(1000)
         .set_sample_size(100)
         .verbose()
-        .train(&threes.0, &threes.1)
+        .train(&threes.0, &threes.1)?
         .loss_graph();
+
+    Ok(())
 }
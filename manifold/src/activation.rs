@@ -1,11 +1,11 @@
 use std::fmt::Debug;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use ndarray::{Array2, Axis};
 use ndarray_stats::QuantileExt;
 use serde::{Deserialize, Serialize};
 
-pub trait Activation {
+pub trait Activation: Send + Sync {
     fn a(&self, x: Array2<f64>) -> Array2<f64>;
     fn d(&self, x: Array2<f64>) -> Array2<f64>;
 }
@@ -19,8 +19,8 @@ impl Debug for dyn Activation {
 pub struct Relu;
 
 impl Relu {
-    pub fn new() -> Rc<Relu> {
-        Rc::new(Relu)
+    pub fn new() -> Arc<Relu> {
+        Arc::new(Relu)
     }
 }
 
@@ -47,8 +47,8 @@ impl Activation for Relu {
 pub struct Softmax;
 
 impl Softmax {
-    pub fn _new() -> Rc<Softmax> {
-        Rc::new(Softmax)
+    pub fn _new() -> Arc<Softmax> {
+        Arc::new(Softmax)
     }
 }
 
@@ -78,8 +78,8 @@ impl Activation for Softmax {
 pub struct Identity;
 
 impl Identity {
-    pub fn new() -> Rc<Identity> {
-        Rc::new(Identity)
+    pub fn new() -> Arc<Identity> {
+        Arc::new(Identity)
     }
 }
 
@@ -89,7 +89,43 @@ impl Activation for Identity {
     }
 
     fn d(&self, x: Array2<f64>) -> Array2<f64> {
-        x
+        x.mapv_into(|_| 1.)
+    }
+}
+
+pub struct Tanh;
+
+impl Tanh {
+    pub fn new() -> Arc<Tanh> {
+        Arc::new(Tanh)
+    }
+}
+
+impl Activation for Tanh {
+    fn a(&self, x: Array2<f64>) -> Array2<f64> {
+        x.mapv(f64::tanh)
+    }
+
+    fn d(&self, x: Array2<f64>) -> Array2<f64> {
+        x.mapv(|v| 1. - v.tanh().powi(2))
+    }
+}
+
+pub struct Sigmoid;
+
+impl Sigmoid {
+    pub fn new() -> Arc<Sigmoid> {
+        Arc::new(Sigmoid)
+    }
+}
+
+impl Activation for Sigmoid {
+    fn a(&self, x: Array2<f64>) -> Array2<f64> {
+        x.mapv(|v| 1. / (1. + (-v).exp()))
+    }
+
+    fn d(&self, x: Array2<f64>) -> Array2<f64> {
+        self.a(x).mapv(|s| s * (1. - s))
     }
 }
 
@@ -97,13 +133,17 @@ impl Activation for Identity {
 pub enum Activations {
     Relu,
     Identity,
+    Tanh,
+    Sigmoid,
 }
 
 impl Activations {
-    pub fn wake(&self) -> Rc<dyn Activation> {
+    pub fn wake(&self) -> Arc<dyn Activation> {
         match self {
             Activations::Identity => Identity::new(),
             Activations::Relu => Relu::new(),
+            Activations::Tanh => Tanh::new(),
+            Activations::Sigmoid => Sigmoid::new(),
         }
     }
 }
@@ -0,0 +1,121 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use ndarray::{Array, Dimension, IntoDimension};
+
+use crate::substrate::Substrate;
+
+#[derive(Debug)]
+pub enum ManifoldError {
+    ShapeMismatch {
+        expected: Vec<usize>,
+        found: Vec<usize>,
+    },
+    SubstrateIndex {
+        index: usize,
+        size: usize,
+    },
+    Unwoven,
+    EmptyLayer(String),
+    EmptySubstrate,
+    InvalidLayer(String),
+    Deserialization(String),
+    InvalidSearch(String),
+    InvalidHyper(String),
+    WorkerPanicked(String),
+}
+
+impl Display for ManifoldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifoldError::ShapeMismatch { expected, found } => {
+                write!(
+                    f,
+                    "Shape mismatch, expected {:?} found {:?}",
+                    expected, found
+                )
+            }
+            ManifoldError::SubstrateIndex { index, size } => write!(
+                f,
+                "Tried to access Substrate[{}] from Substrate[0..{}]",
+                index, size
+            ),
+            ManifoldError::Unwoven => write!(f, "Network has not been woven"),
+            ManifoldError::EmptyLayer(layer) => write!(f, "{} has no units", layer),
+            ManifoldError::EmptySubstrate => {
+                write!(f, "Substrate is empty, links have nothing to point at")
+            }
+            ManifoldError::InvalidLayer(e) => write!(f, "Invalid layer: {}", e),
+            ManifoldError::Deserialization(e) => write!(f, "Failed to deserialize: {}", e),
+            ManifoldError::InvalidSearch(e) => write!(f, "Invalid search space: {}", e),
+            ManifoldError::InvalidHyper(e) => write!(f, "Invalid hyperparameter: {}", e),
+            ManifoldError::WorkerPanicked(e) => write!(f, "Worker panicked: {}", e),
+        }
+    }
+}
+
+impl Error for ManifoldError {}
+
+impl From<bincode::Error> for ManifoldError {
+    fn from(e: bincode::Error) -> ManifoldError {
+        ManifoldError::Deserialization(e.to_string())
+    }
+}
+
+// What a thread panicked with, when it panicked with a message.
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => panic
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "no message".to_string()),
+    }
+}
+
+// into_shape that reports both shapes instead of ndarray's bare ShapeError.
+pub(crate) fn reshape<D: Dimension, E: IntoDimension>(
+    x: Array<f64, D>,
+    shape: E,
+) -> Result<Array<f64, E::Dim>, ManifoldError> {
+    let found = x.shape().to_vec();
+    let shape = shape.into_dimension();
+    let expected = shape.slice().to_vec();
+
+    x.into_shape(shape)
+        .map_err(|_| ManifoldError::ShapeMismatch { expected, found })
+}
+
+pub(crate) fn expect_shape(expected: &[usize], found: &[usize]) -> Result<(), ManifoldError> {
+    if expected != found {
+        return Err(ManifoldError::ShapeMismatch {
+            expected: expected.to_vec(),
+            found: found.to_vec(),
+        });
+    }
+    Ok(())
+}
+
+// Layer sizes of a network from input to output, checked before weaving.
+// Networks with isolated weights have no substrate to check.
+pub(crate) fn expect_definition(
+    substrate: Option<&Substrate>,
+    d_in: usize,
+    hidden: &[usize],
+    d_out: usize,
+) -> Result<(), ManifoldError> {
+    if substrate.is_some_and(|s| s.size == 0) {
+        return Err(ManifoldError::EmptySubstrate);
+    }
+    if d_in == 0 {
+        return Err(ManifoldError::EmptyLayer("Input".to_string()));
+    }
+    if let Some(i) = hidden.iter().position(|size| *size == 0) {
+        return Err(ManifoldError::EmptyLayer(format!("Hidden layer {}", i)));
+    }
+    if d_out == 0 {
+        return Err(ManifoldError::EmptyLayer("Output".to_string()));
+    }
+    Ok(())
+}
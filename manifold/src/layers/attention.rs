@@ -0,0 +1,655 @@
+use std::mem::size_of;
+use std::ops::Range;
+
+use ndarray::{
+    s, Array1, Array2, Array3, ArrayView1, ArrayView2, ArrayViewMut1, ArrayViewMut2, Axis,
+};
+
+use serde::{self, Deserialize, Serialize};
+
+use crate::activation::Activations;
+use crate::error::{expect_shape, reshape, ManifoldError};
+use crate::substrate::Substrate;
+
+use super::norm::{layer_norm, unlayer_norm};
+use super::storage::{Init, Linked, Storage};
+use super::types::{Layer, LayerSummary, Layers};
+use super::{Weighted, Weights};
+
+// What multi-head attention over one batch left behind for backward.
+#[derive(Debug, Clone, Default)]
+struct Attended {
+    x: Array2<f64>,
+    q: Array2<f64>,
+    k: Array2<f64>,
+    v: Array2<f64>,
+    // Attention weights, one (length, length) matrix per sample and head.
+    a: Vec<Array2<f64>>,
+    o: Array2<f64>,
+}
+
+// Scaled dot product attention with `heads` heads over (length, features)
+// samples, projecting to `width` columns and back. Rows of every matrix are
+// the batch's positions one sample after another.
+//
+// Weights are column blocks [Wq, Wk, Wv, Wo^T] of a (features, 4 * width)
+// view, biases [bq, bk, bv, bo] of a 3 * width + features view.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+struct MultiHead {
+    length: usize,
+    features: usize,
+    width: usize,
+    heads: usize,
+}
+
+impl MultiHead {
+    fn new(
+        kind: Layers,
+        heads: usize,
+        input: &[usize],
+        width: Option<usize>,
+    ) -> Result<MultiHead, ManifoldError> {
+        // A flat input is a sequence of one position.
+        let (length, features) = match input {
+            [features] => (1, *features),
+            [length, features] => (*length, *features),
+            _ => {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "{} expects (length, features) input, found {:?}",
+                    kind.name(),
+                    input
+                )))
+            }
+        };
+
+        let width = width.unwrap_or(features);
+        if heads == 0 || !width.is_multiple_of(heads) {
+            return Err(ManifoldError::InvalidLayer(format!(
+                "{} width {} does not split into {} heads",
+                kind.name(),
+                width,
+                heads
+            )));
+        }
+
+        Ok(MultiHead {
+            length,
+            features,
+            width,
+            heads,
+        })
+    }
+
+    fn columns(&self) -> usize {
+        4 * self.width
+    }
+
+    fn biases(&self) -> usize {
+        3 * self.width + self.features
+    }
+
+    // Columns of the query, key, value and output projections with the
+    // fans of each.
+    fn blocks(&self) -> Vec<(Range<usize>, usize, usize)> {
+        let width = self.width;
+        let mut blocks = (0..3)
+            .map(|i| (i * width..(i + 1) * width, self.features, width))
+            .collect::<Vec<_>>();
+        blocks.push((3 * width..4 * width, width, self.features));
+        blocks
+    }
+
+    // Rows and columns of head `h` of sample `n`.
+    fn block(&self, n: usize, h: usize) -> (Range<usize>, Range<usize>) {
+        let depth = self.width / self.heads;
+        (
+            n * self.length..(n + 1) * self.length,
+            h * depth..(h + 1) * depth,
+        )
+    }
+
+    fn attend(
+        &self,
+        x: &Array2<f64>,
+        w: ArrayView2<f64>,
+        b: ArrayView1<f64>,
+    ) -> (Array2<f64>, Attended) {
+        let width = self.width;
+        let batch = x.nrows() / self.length;
+        let scale = ((width / self.heads) as f64).sqrt();
+
+        let project = |i: usize| {
+            x.dot(&w.slice(s![.., i * width..(i + 1) * width]))
+                + b.slice(s![i * width..(i + 1) * width])
+        };
+        let (q, k, v) = (project(0), project(1), project(2));
+
+        let mut a = Vec::with_capacity(batch * self.heads);
+        let mut o = Array2::zeros(q.dim());
+
+        for n in 0..batch {
+            for h in 0..self.heads {
+                let (rows, cols) = self.block(n, h);
+                let scores = q
+                    .slice(s![rows.clone(), cols.clone()])
+                    .dot(&k.slice(s![rows.clone(), cols.clone()]).t())
+                    / scale;
+                let weights = softmax(scores);
+
+                o.slice_mut(s![rows.clone(), cols.clone()])
+                    .assign(&weights.dot(&v.slice(s![rows, cols])));
+                a.push(weights);
+            }
+        }
+
+        let y = o.dot(&w.slice(s![.., 3 * width..]).t()) + b.slice(s![3 * width..]);
+        let attended = Attended {
+            x: x.clone(),
+            q,
+            k,
+            v,
+            a,
+            o,
+        };
+        (y, attended)
+    }
+
+    // Adds weight gradients into grad_w and grad_b, returns the input's.
+    fn unattend(
+        &self,
+        cache: &Attended,
+        dy: &Array2<f64>,
+        w: ArrayView2<f64>,
+        mut grad_w: ArrayViewMut2<f64>,
+        mut grad_b: ArrayViewMut1<f64>,
+    ) -> Array2<f64> {
+        let width = self.width;
+        let batch = dy.nrows() / self.length;
+        let scale = ((width / self.heads) as f64).sqrt();
+
+        grad_w
+            .slice_mut(s![.., 3 * width..])
+            .scaled_add(1., &dy.t().dot(&cache.o));
+        grad_b
+            .slice_mut(s![3 * width..])
+            .scaled_add(1., &dy.sum_axis(Axis(0)));
+        let d_o = dy.dot(&w.slice(s![.., 3 * width..]));
+
+        let mut dq = Array2::zeros(cache.q.dim());
+        let mut dk = Array2::zeros(cache.k.dim());
+        let mut dv = Array2::zeros(cache.v.dim());
+
+        for n in 0..batch {
+            for h in 0..self.heads {
+                let (rows, cols) = self.block(n, h);
+                let a = &cache.a[n * self.heads + h];
+                let d_oh = d_o.slice(s![rows.clone(), cols.clone()]);
+
+                let da = d_oh.dot(&cache.v.slice(s![rows.clone(), cols.clone()]).t());
+                let ds = a * &(&da - &(&da * a).sum_axis(Axis(1)).insert_axis(Axis(1))) / scale;
+
+                dv.slice_mut(s![rows.clone(), cols.clone()])
+                    .assign(&a.t().dot(&d_oh));
+                dq.slice_mut(s![rows.clone(), cols.clone()])
+                    .assign(&ds.dot(&cache.k.slice(s![rows.clone(), cols.clone()])));
+                dk.slice_mut(s![rows.clone(), cols.clone()])
+                    .assign(&ds.t().dot(&cache.q.slice(s![rows, cols])));
+            }
+        }
+
+        let mut dx = Array2::zeros(cache.x.dim());
+        for (i, d) in [dq, dk, dv].iter().enumerate() {
+            let block = i * width..(i + 1) * width;
+            grad_w
+                .slice_mut(s![.., block.clone()])
+                .scaled_add(1., &cache.x.t().dot(d));
+            grad_b
+                .slice_mut(s![block.clone()])
+                .scaled_add(1., &d.sum_axis(Axis(0)));
+            dx += &d.dot(&w.slice(s![.., block]).t());
+        }
+
+        dx
+    }
+}
+
+fn softmax(x: Array2<f64>) -> Array2<f64> {
+    let max = x.fold_axis(Axis(1), f64::NEG_INFINITY, |m, v| m.max(*v));
+    let exps = (x - &max.insert_axis(Axis(1))).mapv(f64::exp);
+    let sums = exps.sum_axis(Axis(1)).insert_axis(Axis(1));
+    exps / &sums
+}
+
+/// Multi-head self-attention over (length, features) inputs. `size` is the
+/// query, key and value width, split evenly between heads, and the output
+/// keeps the input's features. Q, K, V and output projections are one
+/// (features, 4 * size) matrix of links.
+///
+/// Attention has no activation of its own, the layer's is ignored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(bound = "")]
+pub struct Attention<S: Storage = Linked> {
+    kind: Layers,
+    heads: MultiHead,
+    pub weights: Weights<S>,
+    #[serde(skip)]
+    cache: Attended,
+    #[serde(skip)]
+    mask: Option<Array3<f64>>,
+}
+
+impl<S: Storage> Attention<S> {
+    pub fn new(
+        kind: Layers,
+        pool_size: usize,
+        input: &[usize],
+        size: usize,
+    ) -> Result<Attention<S>, ManifoldError> {
+        let heads = match kind {
+            Layers::Attention { heads } => MultiHead::new(kind, heads, input, Some(size))?,
+            _ => {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "{} is not attention",
+                    kind.name()
+                )))
+            }
+        };
+
+        let w_shape = (heads.features, heads.columns());
+
+        Ok(Attention {
+            kind,
+            heads,
+            weights: Weights::new(pool_size, w_shape, heads.biases()),
+            cache: Attended::default(),
+            mask: None,
+        })
+    }
+
+    pub fn output_shape(&self) -> Vec<usize> {
+        vec![self.heads.length, self.heads.features]
+    }
+
+    fn attend(&self, x: Array3<f64>) -> Result<(Array3<f64>, Attended), ManifoldError> {
+        let (batch, length, features) = x.dim();
+        expect_shape(
+            &[self.heads.length, self.heads.features],
+            &[length, features],
+        )?;
+
+        let rows = reshape(x, (batch * length, features))?;
+        let (y, attended) = self
+            .heads
+            .attend(&rows, self.weights.w.view(), self.weights.b.view());
+        Ok((reshape(y, (batch, length, features))?, attended))
+    }
+}
+
+impl<S: Storage> Layer for Attention<S> {
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let (mut y, attended) = self.attend(x)?;
+
+        self.mask = self.weights.dropout_mask(y.dim());
+        if let Some(mask) = &self.mask {
+            y *= mask;
+        }
+
+        self.cache = attended;
+        Ok(y)
+    }
+
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        Ok(self.attend(x)?.0)
+    }
+
+    fn backward(&mut self, mut grad_output: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let rows = self.cache.x.nrows();
+        let (length, features) = (self.heads.length, self.heads.features);
+        let batch = rows / length;
+        expect_shape(&[batch, length, features], grad_output.shape())?;
+
+        if let Some(mask) = &self.mask {
+            grad_output *= mask;
+        }
+
+        let mut grad_w = Array2::zeros(self.weights.w.dim());
+        let mut grad_b = Array1::zeros(self.weights.b.dim());
+        let dx = self.heads.unattend(
+            &self.cache,
+            &reshape(grad_output, (rows, features))?,
+            self.weights.w.view(),
+            grad_w.view_mut(),
+            grad_b.view_mut(),
+        );
+
+        self.weights.descend(&grad_w, &grad_b, batch);
+
+        reshape(dx, (batch, length, features))
+    }
+
+    fn weights(&self) -> Option<&dyn Weighted> {
+        Some(&self.weights)
+    }
+
+    fn weights_mut(&mut self) -> Option<&mut dyn Weighted> {
+        Some(&mut self.weights)
+    }
+
+    // Every projection drawn with its own fans rather than the four as one.
+    fn initialize(&mut self, substrate: &Substrate, init: Init) -> Result<(), ManifoldError> {
+        let blocks = self.heads.blocks();
+        self.weights.initialize_blocks(substrate, init, &blocks)
+    }
+
+    fn kind(&self) -> Layers {
+        self.kind
+    }
+
+    fn describe(&self) -> LayerSummary {
+        let (links, distinct, bytes) = self.weights.footprint();
+        let cache = &self.cache;
+        let cached =
+            cache.x.len() + cache.q.len() * 4 + cache.a.iter().map(|a| a.len()).sum::<usize>();
+
+        LayerSummary {
+            kind: self.kind,
+            input: self.output_shape(),
+            output: self.output_shape(),
+            activation: Activations::Identity,
+            links,
+            distinct,
+            bytes: bytes + cached * size_of::<f64>(),
+        }
+    }
+}
+
+// What an encoder block left behind for backward.
+#[derive(Debug, Clone, Default)]
+struct Encoded {
+    attended: Attended,
+    xhat_1: Array2<f64>,
+    inv_std_1: Array1<f64>,
+    h_1: Array2<f64>,
+    z_1: Array2<f64>,
+    a_1: Array2<f64>,
+    xhat_2: Array2<f64>,
+    inv_std_2: Array1<f64>,
+}
+
+/// Transformer encoder block over (length, features) inputs, post norm:
+///
+/// h = LayerNorm(x + Attention(x)), y = LayerNorm(h + W2 f(W1 h))
+///
+/// `size` is the feed forward width and `activation` its nonlinearity, the
+/// attention width is the input's features. Every weight, layer norm gains
+/// included, is a column of one (features, 4 * features + 2 * size + 2)
+/// matrix of links: [Wq, Wk, Wv, Wo^T, W1, W2^T, gain 1, gain 2]. Biases
+/// are [bq, bk, bv, bo, b1, b2, shift 1, shift 2].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(bound = "")]
+pub struct Encoder<S: Storage = Linked> {
+    kind: Layers,
+    heads: MultiHead,
+    hidden: usize,
+    pub weights: Weights<S>,
+    pub activation: Activations,
+    #[serde(skip)]
+    cache: Encoded,
+    #[serde(skip)]
+    mask: Option<Array3<f64>>,
+}
+
+impl<S: Storage> Encoder<S> {
+    pub fn new(
+        kind: Layers,
+        pool_size: usize,
+        input: &[usize],
+        size: usize,
+        activation: Activations,
+    ) -> Result<Encoder<S>, ManifoldError> {
+        let heads = match kind {
+            Layers::Encoder { heads } => MultiHead::new(kind, heads, input, None)?,
+            _ => {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "{} is not an encoder",
+                    kind.name()
+                )))
+            }
+        };
+
+        let features = heads.features;
+        let w_shape = (features, heads.columns() + 2 * size + 2);
+        let b_shape = heads.biases() + size + 3 * features;
+
+        Ok(Encoder {
+            kind,
+            heads,
+            hidden: size,
+            weights: Weights::new(pool_size, w_shape, b_shape),
+            activation,
+            cache: Encoded::default(),
+            mask: None,
+        })
+    }
+
+    pub fn output_shape(&self) -> Vec<usize> {
+        vec![self.heads.length, self.heads.features]
+    }
+
+    // Column and bias offsets of the feed forward and norm blocks.
+    fn offsets(&self) -> (usize, usize) {
+        (self.heads.columns(), self.heads.biases())
+    }
+
+    fn encode(&self, x: Array3<f64>) -> Result<(Array3<f64>, Encoded), ManifoldError> {
+        let (batch, length, features) = x.dim();
+        expect_shape(
+            &[self.heads.length, self.heads.features],
+            &[length, features],
+        )?;
+
+        let (c, o) = self.offsets();
+        let f = self.hidden;
+        let w = &self.weights.w;
+        let b = &self.weights.b;
+        let activ = self.activation.wake();
+
+        let x = reshape(x, (batch * length, features))?;
+        let (attention, attended) = self
+            .heads
+            .attend(&x, w.slice(s![.., ..c]), b.slice(s![..o]));
+
+        let (h_1, xhat_1, inv_std_1) = layer_norm(
+            &(&x + &attention),
+            w.column(c + 2 * f),
+            b.slice(s![o + f + features..o + f + 2 * features]),
+        );
+
+        let z_1 = h_1.dot(&w.slice(s![.., c..c + f])) + b.slice(s![o..o + f]);
+        let a_1 = activ.a(z_1.clone());
+        let z_2 =
+            a_1.dot(&w.slice(s![.., c + f..c + 2 * f]).t()) + b.slice(s![o + f..o + f + features]);
+
+        let (y, xhat_2, inv_std_2) = layer_norm(
+            &(&h_1 + &z_2),
+            w.column(c + 2 * f + 1),
+            b.slice(s![o + f + 2 * features..]),
+        );
+
+        let encoded = Encoded {
+            attended,
+            xhat_1,
+            inv_std_1,
+            h_1,
+            z_1,
+            a_1,
+            xhat_2,
+            inv_std_2,
+        };
+        Ok((reshape(y, (batch, length, features))?, encoded))
+    }
+}
+
+impl<S: Storage> Layer for Encoder<S> {
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let (mut y, encoded) = self.encode(x)?;
+
+        self.mask = self.weights.dropout_mask(y.dim());
+        if let Some(mask) = &self.mask {
+            y *= mask;
+        }
+
+        self.cache = encoded;
+        Ok(y)
+    }
+
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        Ok(self.encode(x)?.0)
+    }
+
+    fn backward(&mut self, mut grad_output: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let rows = self.cache.h_1.nrows();
+        let (length, features) = (self.heads.length, self.heads.features);
+        let batch = rows / length;
+        expect_shape(&[batch, length, features], grad_output.shape())?;
+
+        if let Some(mask) = &self.mask {
+            grad_output *= mask;
+        }
+
+        let (c, o) = self.offsets();
+        let f = self.hidden;
+        let w = &self.weights.w;
+        let cache = &self.cache;
+        let activ = self.activation.wake();
+
+        let mut grad_w = Array2::zeros(w.dim());
+        let mut grad_b = Array1::zeros(self.weights.b.dim());
+
+        // Second norm, the feed forward block and its residual.
+        let (d_r2, d_gain, d_shift) = unlayer_norm(
+            &reshape(grad_output, (rows, features))?,
+            w.column(c + 2 * f + 1),
+            &cache.xhat_2,
+            &cache.inv_std_2,
+        );
+        grad_w.column_mut(c + 2 * f + 1).assign(&d_gain);
+        grad_b
+            .slice_mut(s![o + f + 2 * features..])
+            .assign(&d_shift);
+
+        grad_w
+            .slice_mut(s![.., c + f..c + 2 * f])
+            .assign(&d_r2.t().dot(&cache.a_1));
+        grad_b
+            .slice_mut(s![o + f..o + f + features])
+            .assign(&d_r2.sum_axis(Axis(0)));
+
+        let d_z1 = d_r2.dot(&w.slice(s![.., c + f..c + 2 * f])) * activ.d(cache.z_1.clone());
+        grad_w
+            .slice_mut(s![.., c..c + f])
+            .assign(&cache.h_1.t().dot(&d_z1));
+        grad_b
+            .slice_mut(s![o..o + f])
+            .assign(&d_z1.sum_axis(Axis(0)));
+        let d_h1 = d_r2 + d_z1.dot(&w.slice(s![.., c..c + f]).t());
+
+        // First norm, attention and its residual.
+        let (d_r1, d_gain, d_shift) =
+            unlayer_norm(&d_h1, w.column(c + 2 * f), &cache.xhat_1, &cache.inv_std_1);
+        grad_w.column_mut(c + 2 * f).assign(&d_gain);
+        grad_b
+            .slice_mut(s![o + f + features..o + f + 2 * features])
+            .assign(&d_shift);
+
+        let dx = &d_r1
+            + &self.heads.unattend(
+                &cache.attended,
+                &d_r1,
+                w.slice(s![.., ..c]),
+                grad_w.slice_mut(s![.., ..c]),
+                grad_b.slice_mut(s![..o]),
+            );
+
+        self.weights.descend(&grad_w, &grad_b, batch);
+
+        reshape(dx, (batch, length, features))
+    }
+
+    fn weights(&self) -> Option<&dyn Weighted> {
+        Some(&self.weights)
+    }
+
+    fn weights_mut(&mut self) -> Option<&mut dyn Weighted> {
+        Some(&mut self.weights)
+    }
+
+    // Projections and the feed forward block are drawn with fans of their
+    // own, the second feed forward matrix being stored transposed. The two
+    // norms' gains start at one.
+    fn initialize(&mut self, substrate: &Substrate, init: Init) -> Result<(), ManifoldError> {
+        let (c, _) = self.offsets();
+        let (f, features) = (self.hidden, self.heads.features);
+
+        let mut blocks = self.heads.blocks();
+        blocks.push((c..c + f, features, f));
+        blocks.push((c + f..c + 2 * f, f, features));
+        self.weights.initialize_blocks(substrate, init, &blocks)
+    }
+
+    fn kind(&self) -> Layers {
+        self.kind
+    }
+
+    fn describe(&self) -> LayerSummary {
+        let (links, distinct, bytes) = self.weights.footprint();
+        let cache = &self.cache;
+        let cached = cache.attended.x.len()
+            + cache.attended.q.len() * 4
+            + cache.attended.a.iter().map(|a| a.len()).sum::<usize>()
+            + cache.xhat_1.len() * 3
+            + cache.z_1.len() * 2;
+
+        LayerSummary {
+            kind: self.kind,
+            input: self.output_shape(),
+            output: self.output_shape(),
+            activation: self.activation,
+            links,
+            distinct,
+            bytes: bytes + cached * size_of::<f64>(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array3;
+    use ndarray_rand::rand_distr::StandardNormal;
+    use ndarray_rand::RandomExt;
+
+    use crate::activation::Activations;
+    use crate::layers::gradcheck::{wake, worst_error};
+    use crate::layers::types::Layers;
+
+    #[test]
+    fn attention_gradients_match_finite_differences() {
+        let mut attention = wake(
+            Layers::Attention { heads: 2 },
+            &[4, 6],
+            4,
+            Activations::Identity,
+        );
+        let x = Array3::random((2, 4, 6), StandardNormal);
+
+        assert!(worst_error(attention.as_mut(), &x, true) < 1e-6);
+    }
+
+    #[test]
+    fn encoder_gradients_match_finite_differences() {
+        let mut encoder = wake(Layers::Encoder { heads: 2 }, &[4, 6], 8, Activations::Tanh);
+        let x = Array3::random((2, 4, 6), StandardNormal);
+
+        assert!(worst_error(encoder.as_mut(), &x, true) < 1e-6);
+    }
+}
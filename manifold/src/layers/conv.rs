@@ -0,0 +1,393 @@
+use std::mem::size_of;
+
+use ndarray::{s, Array2, Array3, Axis};
+
+use serde::{self, Deserialize, Serialize};
+
+use crate::activation::Activations;
+use crate::error::{expect_shape, reshape, ManifoldError};
+
+use super::storage::{Linked, Storage};
+use super::types::{Layer, LayerSummary, Layers};
+use super::{Weighted, Weights};
+
+// Sliding window over a (height, width) grid of channel vectors. Conv1D is
+// the same window over a grid one row high.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub(super) struct Geometry {
+    pub(super) input: (usize, usize),
+    pub(super) channels: usize,
+    pub(super) kernel: (usize, usize),
+    stride: (usize, usize),
+    padding: (usize, usize),
+    dilation: (usize, usize),
+    pub(super) output: (usize, usize),
+}
+
+impl Geometry {
+    pub(super) fn new(layer: Layers, input: &[usize]) -> Result<Geometry, ManifoldError> {
+        let (kernel, stride, padding, dilation) = match layer {
+            Layers::Conv1D {
+                kernel,
+                stride,
+                padding,
+                dilation,
+            } => ((1, kernel), (1, stride), (0, padding), (1, dilation)),
+            Layers::Conv2D {
+                kernel,
+                stride,
+                padding,
+                dilation,
+            } => (kernel, stride, padding, dilation),
+            Layers::MaxPool1D { pool, stride } | Layers::AvgPool1D { pool, stride } => {
+                ((1, pool), (1, stride), (0, 0), (1, 1))
+            }
+            Layers::MaxPool2D { pool, stride } | Layers::AvgPool2D { pool, stride } => {
+                (pool, stride, (0, 0), (1, 1))
+            }
+            _ => {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "{} has no window",
+                    layer.name()
+                )))
+            }
+        };
+
+        let (grid, channels) = match (one_dimensional(layer), input) {
+            (true, [length, channels]) => ((1, *length), *channels),
+            (false, [height, width, channels]) => ((*height, *width), *channels),
+            (true, _) => {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "{} expects (length, channels) input, found {:?}",
+                    layer.name(),
+                    input
+                )))
+            }
+            (false, _) => {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "{} expects (height, width, channels) input, found {:?}",
+                    layer.name(),
+                    input
+                )))
+            }
+        };
+
+        if [
+            kernel.0, kernel.1, stride.0, stride.1, dilation.0, dilation.1,
+        ]
+        .contains(&0)
+        {
+            return Err(ManifoldError::InvalidLayer(format!(
+                "{:?} needs non zero kernel, stride and dilation",
+                layer
+            )));
+        }
+
+        let span = |size: usize, k: usize, s: usize, p: usize, d: usize| {
+            (size + 2 * p)
+                .checked_sub(d * (k - 1) + 1)
+                .map(|free| free / s + 1)
+        };
+
+        let output = match (
+            span(grid.0, kernel.0, stride.0, padding.0, dilation.0),
+            span(grid.1, kernel.1, stride.1, padding.1, dilation.1),
+        ) {
+            (Some(h), Some(w)) => (h, w),
+            _ => {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "{:?} window is larger than its {:?} input",
+                    layer, input
+                )))
+            }
+        };
+
+        Ok(Geometry {
+            input: grid,
+            channels,
+            kernel,
+            stride,
+            padding,
+            dilation,
+            output,
+        })
+    }
+
+    pub(super) fn positions(&self) -> usize {
+        self.input.0 * self.input.1
+    }
+
+    fn patch(&self) -> usize {
+        self.kernel.0 * self.kernel.1 * self.channels
+    }
+
+    // Input position a kernel tap lands on, None when it falls in padding.
+    pub(super) fn tap(&self, out: (usize, usize), k: (usize, usize)) -> Option<usize> {
+        let y = (out.0 * self.stride.0 + k.0 * self.dilation.0).checked_sub(self.padding.0)?;
+        let x = (out.1 * self.stride.1 + k.1 * self.dilation.1).checked_sub(self.padding.1)?;
+
+        if y >= self.input.0 || x >= self.input.1 {
+            return None;
+        }
+        Some(y * self.input.1 + x)
+    }
+
+    // Unroll every window of x (batch, positions, channels) into one row.
+    fn im2col(&self, x: &Array3<f64>) -> Result<Array2<f64>, ManifoldError> {
+        let (batch, positions, channels) = x.dim();
+        expect_shape(&[self.positions(), self.channels], &[positions, channels])?;
+
+        let (oh, ow) = self.output;
+        let mut cols = Array2::zeros((batch * oh * ow, self.patch()));
+
+        for n in 0..batch {
+            for oy in 0..oh {
+                for ox in 0..ow {
+                    let row = (n * oh + oy) * ow + ox;
+                    for ky in 0..self.kernel.0 {
+                        for kx in 0..self.kernel.1 {
+                            if let Some(p) = self.tap((oy, ox), (ky, kx)) {
+                                let col = (ky * self.kernel.1 + kx) * channels;
+                                cols.slice_mut(s![row, col..col + channels])
+                                    .assign(&x.slice(s![n, p, ..]));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(cols)
+    }
+
+    // Sum unrolled rows back onto the positions they were read from.
+    fn col2im(&self, cols: &Array2<f64>, batch: usize) -> Array3<f64> {
+        let (oh, ow) = self.output;
+        let channels = self.channels;
+        let mut x = Array3::zeros((batch, self.positions(), channels));
+
+        for n in 0..batch {
+            for oy in 0..oh {
+                for ox in 0..ow {
+                    let row = (n * oh + oy) * ow + ox;
+                    for ky in 0..self.kernel.0 {
+                        for kx in 0..self.kernel.1 {
+                            if let Some(p) = self.tap((oy, ox), (ky, kx)) {
+                                let col = (ky * self.kernel.1 + kx) * channels;
+                                let mut dst = x.slice_mut(s![n, p, ..]);
+                                dst += &cols.slice(s![row, col..col + channels]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        x
+    }
+}
+
+fn one_dimensional(layer: Layers) -> bool {
+    matches!(
+        layer,
+        Layers::Conv1D { .. } | Layers::MaxPool1D { .. } | Layers::AvgPool1D { .. }
+    )
+}
+
+// Per sample shape of a grid, laid out the way the layer reads its input.
+pub(super) fn layout(layer: Layers, grid: (usize, usize), channels: usize) -> Vec<usize> {
+    match one_dimensional(layer) {
+        true => vec![grid.1, channels],
+        false => vec![grid.0, grid.1, channels],
+    }
+}
+
+/// Convolution over (length, channels) or (height, width, channels) inputs,
+/// carried as (batch, positions, channels) tensors. The kernel is one
+/// (window * channels, filters) matrix applied to every unrolled window.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(bound = "")]
+pub struct Conv<S: Storage = Linked> {
+    kind: Layers,
+    geometry: Geometry,
+    pub cols: Array2<f64>,
+    pub weights: Weights<S>,
+    pub d_z: Array3<f64>,
+    pub activation: Activations,
+}
+
+impl<S: Storage> Conv<S> {
+    pub fn new(
+        kind: Layers,
+        pool_size: usize,
+        input: &[usize],
+        filters: usize,
+        activation: Activations,
+    ) -> Result<Conv<S>, ManifoldError> {
+        let geometry = Geometry::new(kind, input)?;
+
+        Ok(Conv {
+            kind,
+            geometry,
+            cols: Array2::zeros((0, geometry.patch())),
+            weights: Weights::new(pool_size, (geometry.patch(), filters), filters),
+            d_z: Array3::zeros((0, 0, filters)),
+            activation,
+        })
+    }
+
+    /// Per sample output shape, laid out like the input.
+    pub fn output_shape(&self) -> Vec<usize> {
+        layout(self.kind, self.geometry.output, self.filters())
+    }
+
+    fn input_shape(&self) -> Vec<usize> {
+        layout(self.kind, self.geometry.input, self.geometry.channels)
+    }
+
+    fn filters(&self) -> usize {
+        self.weights.w.ncols()
+    }
+
+    fn positions_out(&self) -> usize {
+        self.geometry.output.0 * self.geometry.output.1
+    }
+}
+
+impl<S: Storage> Layer for Conv<S> {
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let batch_size = x.shape()[0];
+        let cols = self.geometry.im2col(&x)?;
+
+        let z_batch = cols.dot(&self.weights.w) + &self.weights.b;
+        let activ = self.activation.wake();
+
+        let a_z_batch = activ.a(z_batch.clone());
+        let d_z_batch = activ.d(z_batch);
+
+        let shape = (batch_size, self.positions_out(), self.filters());
+        let mut a_z = reshape(a_z_batch, shape)?;
+        let mut d_z = reshape(d_z_batch, shape)?;
+
+        if let Some(mask) = self.weights.dropout_mask(a_z.dim()) {
+            a_z *= &mask;
+            d_z *= &mask;
+        }
+
+        self.d_z = d_z;
+        self.cols = cols;
+        Ok(a_z)
+    }
+
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let batch_size = x.shape()[0];
+        let cols = self.geometry.im2col(&x)?;
+        let a_z_batch = self
+            .activation
+            .wake()
+            .a(cols.dot(&self.weights.w) + &self.weights.b);
+
+        reshape(
+            a_z_batch,
+            (batch_size, self.positions_out(), self.filters()),
+        )
+    }
+
+    fn backward(&mut self, grad_output: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        expect_shape(self.d_z.shape(), grad_output.shape())?;
+
+        let batch_size = self.d_z.shape()[0];
+        let rows = batch_size * self.positions_out();
+        let filters = self.filters();
+
+        let grad_z =
+            reshape(grad_output, (rows, filters))? * reshape(self.d_z.clone(), (rows, filters))?;
+
+        let grad_cols = grad_z.dot(&self.weights.w.t());
+        let grad_w = self.cols.t().dot(&grad_z);
+        let grad_b = grad_z.sum_axis(Axis(0));
+        self.weights.descend(&grad_w, &grad_b, batch_size);
+
+        Ok(self.geometry.col2im(&grad_cols, batch_size))
+    }
+
+    fn weights(&self) -> Option<&dyn Weighted> {
+        Some(&self.weights)
+    }
+
+    fn weights_mut(&mut self) -> Option<&mut dyn Weighted> {
+        Some(&mut self.weights)
+    }
+
+    fn kind(&self) -> Layers {
+        self.kind
+    }
+
+    fn describe(&self) -> LayerSummary {
+        let (links, distinct, bytes) = self.weights.footprint();
+        let cached = self.cols.len() + self.d_z.len();
+
+        LayerSummary {
+            kind: self.kind,
+            input: self.input_shape(),
+            output: self.output_shape(),
+            activation: self.activation,
+            links,
+            distinct,
+            bytes: bytes + cached * size_of::<f64>(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array3;
+    use ndarray_rand::rand_distr::StandardNormal;
+    use ndarray_rand::RandomExt;
+
+    use crate::activation::Activations;
+    use crate::layers::gradcheck::{wake, worst_error};
+    use crate::layers::types::Layers;
+
+    #[test]
+    fn conv1d_gradients_match_finite_differences() {
+        let kind = Layers::Conv1D {
+            kernel: 3,
+            stride: 2,
+            padding: 1,
+            dilation: 1,
+        };
+        let mut conv = wake(kind, &[7, 2], 3, Activations::Tanh);
+        let x = Array3::random((3, 7, 2), StandardNormal);
+
+        assert!(worst_error(conv.as_mut(), &x, true) < 1e-6);
+    }
+
+    #[test]
+    fn conv2d_gradients_match_finite_differences() {
+        let kind = Layers::Conv2D {
+            kernel: (2, 2),
+            stride: (1, 2),
+            padding: (1, 0),
+            dilation: (1, 1),
+        };
+        let mut conv = wake(kind, &[4, 5, 2], 3, Activations::Sigmoid);
+        let x = Array3::random((2, 20, 2), StandardNormal);
+
+        assert!(worst_error(conv.as_mut(), &x, true) < 1e-6);
+    }
+
+    #[test]
+    fn dilated_conv2d_gradients_match_finite_differences() {
+        let kind = Layers::Conv2D {
+            kernel: (2, 2),
+            stride: (1, 1),
+            padding: (0, 1),
+            dilation: (2, 1),
+        };
+        let mut conv = wake(kind, &[5, 4, 1], 2, Activations::Tanh);
+        let x = Array3::random((2, 20, 1), StandardNormal);
+
+        assert!(worst_error(conv.as_mut(), &x, true) < 1e-6);
+    }
+}
@@ -1,62 +1,53 @@
 use core::fmt::Debug;
+use std::mem::size_of;
 
-use ndarray::{Array, Array1, Array2, Array3, Axis};
-use ndarray_rand::rand_distr::Uniform;
-use ndarray_rand::RandomExt;
+use ndarray::{Array3, Axis};
 
 use serde::{self, Deserialize, Serialize};
 
 use crate::activation::Activations;
-use crate::substrate::Substrate;
+use crate::error::{expect_shape, reshape, ManifoldError};
 
-use super::types::Layer;
+use super::storage::{Linked, Storage};
+use super::types::{Layer, LayerSummary, Layers};
+use super::{Weighted, Weights};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Dense {
+#[serde(bound = "")]
+pub struct Dense<S: Storage = Linked> {
     pub x: Array3<f64>,
-    pub wi: Array2<usize>,
-    pub bi: Array1<usize>,
-    pub w: Array2<f64>,
-    pub b: Array1<f64>,
+    pub weights: Weights<S>,
     pub d_z: Array3<f64>,
-    pub grad_w: Array2<f64>,
-    pub grad_b: Array1<f64>,
     pub activation: Activations,
 }
 
-impl Dense {
+impl<S: Storage> Dense<S> {
     pub fn new(
         pool_size: usize,
         x_shape: (usize, usize, usize),
         w_shape: (usize, usize),
         b_shape: usize,
         activation: Activations,
-    ) -> Dense {
+    ) -> Dense<S> {
         Dense {
             x: Array3::zeros(x_shape),
-            wi: Array2::random(w_shape, Uniform::new(0, pool_size)),
-            bi: Array::random(b_shape, Uniform::new(0, pool_size)),
-            w: Array2::zeros(w_shape),
-            b: Array::zeros(b_shape),
+            weights: Weights::new(pool_size, w_shape, b_shape),
             d_z: Array3::zeros(x_shape),
-            grad_w: Array2::zeros(w_shape),
-            grad_b: Array::zeros(b_shape),
             activation,
         }
     }
 }
 
-impl Layer for Dense {
-    fn forward(&mut self, x: Array3<f64>) -> Array3<f64> {
+impl<S: Storage> Layer for Dense<S> {
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
         let batch_size = x.shape()[0];
         let sequence_length = x.shape()[1];
         let features = x.shape()[2];
+        let Weights { w, b, .. } = &self.weights;
+        expect_shape(&[w.nrows()], &[features])?;
 
-        let x_reshaped = x
-            .clone()
-            .into_shape((batch_size * sequence_length, features))
-            .unwrap();
-        let z_batch = x_reshaped.dot(&self.w) + &self.b;
+        let x_reshaped = reshape(x.clone(), (batch_size * sequence_length, features))?;
+        let z_batch = x_reshaped.dot(w) + b;
         let activ = self.activation.wake();
 
         let a_z_batch = activ.a(z_batch.clone());
@@ -65,92 +56,125 @@ impl Layer for Dense {
         let new_features = a_z_batch.shape()[1];
 
         // Reshape a_z and d_z back into 3d
-        let a_z = a_z_batch
-            .into_shape((batch_size, sequence_length, new_features))
-            .unwrap();
-        let d_z = d_z_batch
-            .into_shape((batch_size, sequence_length, new_features))
-            .unwrap();
+        let mut a_z = reshape(a_z_batch, (batch_size, sequence_length, new_features))?;
+        let mut d_z = reshape(d_z_batch, (batch_size, sequence_length, new_features))?;
+
+        // Dropped units pass no gradient back, so the mask goes on d_z as well.
+        if let Some(mask) = self.weights.dropout_mask(a_z.dim()) {
+            a_z *= &mask;
+            d_z *= &mask;
+        }
 
         self.d_z = d_z;
         self.x = x;
-        a_z
+        Ok(a_z)
+    }
+
+    // Forward pass without dropout that leaves the backprop caches alone.
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let (batch_size, sequence_length, features) = x.dim();
+        let Weights { w, b, .. } = &self.weights;
+        expect_shape(&[w.nrows()], &[features])?;
+
+        let x_reshaped = reshape(x, (batch_size * sequence_length, features))?;
+        let z_batch = x_reshaped.dot(w) + b;
+        let a_z_batch = self.activation.wake().a(z_batch);
+
+        let new_features = a_z_batch.shape()[1];
+        reshape(a_z_batch, (batch_size, sequence_length, new_features))
     }
 
-    fn backward(&mut self, grad_output: Array3<f64>) -> Array3<f64> {
+    fn backward(&mut self, grad_output: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        expect_shape(self.d_z.shape(), grad_output.shape())?;
+
         let dz_batch_size = self.d_z.shape()[0];
         let dz_sequence_length = self.d_z.shape()[1];
         let dz_features = self.d_z.shape()[2];
 
-        let d_z_batch = &self
-            .d_z
-            .clone()
-            .into_shape((dz_batch_size * dz_sequence_length, dz_features))
-            .unwrap();
+        let d_z_batch = &reshape(
+            self.d_z.clone(),
+            (dz_batch_size * dz_sequence_length, dz_features),
+        )?;
 
         let x_batch_size = self.x.shape()[0];
         let x_sequence_length = self.x.shape()[1];
         let x_features = self.x.shape()[2];
 
-        let x_batch = &self
-            .x
-            .clone()
-            .into_shape((x_batch_size * x_sequence_length, x_features))
-            .unwrap();
+        let x_batch = &reshape(
+            self.x.clone(),
+            (x_batch_size * x_sequence_length, x_features),
+        )?;
 
         let grad_batch_size = grad_output.shape()[0];
         let grad_features = grad_output.shape()[2];
         let grad_sequence_length = grad_output.shape()[1];
 
-        let grad_output_batch = grad_output
-            .into_shape((grad_batch_size * grad_sequence_length, grad_features))
-            .unwrap();
+        let grad_output_batch = reshape(
+            grad_output,
+            (grad_batch_size * grad_sequence_length, grad_features),
+        )?;
         let grad_z = grad_output_batch * d_z_batch;
 
-        let wt = self.w.t();
+        let wt = self.weights.w.t();
         let grad_input = grad_z.dot(&wt);
 
         let grad_w = x_batch.t().dot(&grad_z);
         let grad_b = grad_z.sum_axis(Axis(0));
 
-        // Mean gradients instead of accumulating
-        let avg_grad_w = grad_w.mapv(|x| x / x_batch_size as f64);
-        let avg_grad_b = grad_b.mapv(|x| x / x_batch_size as f64);
+        self.weights.descend(&grad_w, &grad_b, x_batch_size);
 
-        self.grad_w -= &(avg_grad_w);
-        self.grad_b -= &(avg_grad_b);
-
-        grad_input
-            .into_shape((x_batch_size, x_sequence_length, x_features))
-            .unwrap()
-    }
-
-    fn gradients(&self) -> (Array2<f64>, Array1<f64>) {
-        (self.grad_w.clone(), self.grad_b.clone())
-    }
-
-    fn gather(&mut self, substrate: &Substrate) {
-        self.w = self.wi.map(|ix| substrate.get(*ix));
-        self.b = self.bi.map(|ix| substrate.get(*ix));
+        reshape(grad_input, (x_batch_size, x_sequence_length, x_features))
     }
 
-    fn shift_weights(&mut self, shift: &Array2<usize>) {
-        self.wi += shift;
+    fn weights(&self) -> Option<&dyn Weighted> {
+        Some(&self.weights)
     }
 
-    fn assign_bi(&mut self, bi: &Array1<usize>) {
-        self.bi = bi.clone();
+    fn weights_mut(&mut self) -> Option<&mut dyn Weighted> {
+        Some(&mut self.weights)
     }
 
-    fn assign_grad_w(&mut self, grad: Array2<f64>) {
-        self.grad_w = grad;
+    fn kind(&self) -> Layers {
+        Layers::Dense
     }
 
-    fn assign_grad_b(&mut self, grad: Array1<f64>) {
-        self.grad_b = grad;
+    fn describe(&self) -> LayerSummary {
+        let (links, distinct, bytes) = self.weights.footprint();
+        let cached = self.x.len() + self.d_z.len();
+
+        LayerSummary {
+            kind: self.kind(),
+            input: vec![self.weights.w.nrows()],
+            output: vec![self.weights.w.ncols()],
+            activation: self.activation,
+            links,
+            distinct,
+            bytes: bytes + cached * size_of::<f64>(),
+        }
     }
+}
 
-    fn gradient_bindings(&self) -> (Array2<usize>, Array1<usize>) {
-        (self.wi.clone(), self.bi.clone())
+#[cfg(test)]
+mod tests {
+    use ndarray::Array3;
+    use ndarray_rand::rand_distr::StandardNormal;
+    use ndarray_rand::RandomExt;
+
+    use crate::activation::Activations;
+    use crate::layers::gradcheck::{wake, worst_error};
+    use crate::layers::types::Layers;
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        for activation in [
+            Activations::Tanh,
+            Activations::Sigmoid,
+            Activations::Identity,
+        ] {
+            let mut dense = wake(Layers::Dense, &[4], 3, activation);
+            let x = Array3::random((5, 1, 4), StandardNormal);
+
+            assert!(worst_error(dense.as_mut(), &x, true) < 1e-6);
+        }
     }
 }
@@ -0,0 +1,230 @@
+use std::mem::size_of;
+
+use ndarray::Array3;
+
+use serde::{self, Deserialize, Serialize};
+
+use crate::activation::Activations;
+use crate::error::{expect_shape, ManifoldError};
+
+use super::types::{Layer, LayerSummary, Layers};
+use super::Mode;
+
+// SELU's negative saturation, -scale * alpha, where AlphaDropout sends
+// dropped values.
+const SATURATION: f64 = -1.0507009873554805 * 1.6732632423543772;
+
+/// Drops activations while training and passes them through untouched
+/// otherwise.
+///
+/// Dropout zeroes single values and scales the kept ones up, SpatialDropout
+/// does the same to whole channels of a sample. AlphaDropout sends dropped
+/// values to SELU's saturation instead of zero and shifts the result back, so
+/// the zero mean and unit variance SELU keeps are kept too.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Dropout {
+    kind: Layers,
+    input: Vec<usize>,
+    mode: Mode,
+    // Gradient of the output with respect to the input.
+    #[serde(skip)]
+    mask: Option<Array3<f64>>,
+}
+
+impl Dropout {
+    pub fn new(kind: Layers, input: &[usize]) -> Result<Dropout, ManifoldError> {
+        let rate = match kind {
+            Layers::Dropout { rate }
+            | Layers::AlphaDropout { rate }
+            | Layers::SpatialDropout { rate } => rate,
+            _ => {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "{} does not drop activations",
+                    kind.name()
+                )))
+            }
+        };
+
+        if !(0. ..1.).contains(&rate) || input.is_empty() {
+            return Err(ManifoldError::InvalidLayer(format!(
+                "{} needs a rate in 0..1 and a channel axis, found {} over {:?}",
+                kind.name(),
+                rate,
+                input
+            )));
+        }
+
+        Ok(Dropout {
+            kind,
+            input: input.to_vec(),
+            mode: Mode::default(),
+            mask: None,
+        })
+    }
+
+    pub fn output_shape(&self) -> Vec<usize> {
+        self.input.clone()
+    }
+
+    fn rate(&self) -> f64 {
+        match self.kind {
+            Layers::Dropout { rate }
+            | Layers::AlphaDropout { rate }
+            | Layers::SpatialDropout { rate } => rate,
+            _ => 0.,
+        }
+    }
+
+    fn draw(
+        &mut self,
+        shape: (usize, usize, usize),
+        keep: f64,
+    ) -> Result<Array3<f64>, ManifoldError> {
+        self.mode.keep(shape, keep).ok_or_else(|| {
+            ManifoldError::InvalidLayer(format!("{} can not keep {}", self.kind.name(), keep))
+        })
+    }
+
+    fn check(&self, x: &Array3<f64>) -> Result<(), ManifoldError> {
+        let (_, positions, channels) = x.dim();
+        let (last, rest) = self.input.split_last().unwrap_or((&0, &[]));
+        expect_shape(&[rest.iter().product(), *last], &[positions, channels])
+    }
+}
+
+impl Layer for Dropout {
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        self.check(&x)?;
+
+        let keep = 1. - self.rate();
+        if keep >= 1. || !self.mode.training() {
+            self.mask = None;
+            return Ok(x);
+        }
+
+        let (batch, positions, channels) = x.dim();
+        let (mask, y) = match self.kind {
+            Layers::SpatialDropout { .. } => {
+                let kept = self.draw((batch, 1, channels), keep)? / keep;
+                let mask = kept
+                    .broadcast(x.dim())
+                    .ok_or(ManifoldError::ShapeMismatch {
+                        expected: vec![batch, positions, channels],
+                        found: vec![batch, 1, channels],
+                    })?
+                    .to_owned();
+                (mask.clone(), x * mask)
+            }
+            Layers::AlphaDropout { .. } => {
+                let kept = self.draw(x.dim(), keep)?;
+                let a = (keep + SATURATION * SATURATION * keep * (1. - keep)).powf(-0.5);
+                let b = -a * SATURATION * (1. - keep);
+                let y = (x * &kept + kept.mapv(|k| (1. - k) * SATURATION)) * a + b;
+                (kept * a, y)
+            }
+            _ => {
+                let mask = self.draw(x.dim(), keep)? / keep;
+                (mask.clone(), x * mask)
+            }
+        };
+
+        self.mask = Some(mask);
+        Ok(y)
+    }
+
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        self.check(&x)?;
+        Ok(x)
+    }
+
+    fn backward(&mut self, grad_output: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        self.check(&grad_output)?;
+
+        match &self.mask {
+            Some(mask) => {
+                expect_shape(mask.shape(), grad_output.shape())?;
+                Ok(grad_output * mask)
+            }
+            None => Ok(grad_output),
+        }
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.mode.set_training(training);
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.mode.seed(seed);
+    }
+
+    fn kind(&self) -> Layers {
+        self.kind
+    }
+
+    fn describe(&self) -> LayerSummary {
+        LayerSummary {
+            kind: self.kind,
+            input: self.input.clone(),
+            output: self.output_shape(),
+            activation: Activations::Identity,
+            links: 0,
+            distinct: 0,
+            bytes: self.mask.as_ref().map_or(0, |mask| mask.len()) * size_of::<f64>(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array3;
+    use ndarray_rand::rand_distr::StandardNormal;
+    use ndarray_rand::RandomExt;
+
+    use super::Dropout;
+    use crate::layers::types::{Layer, Layers};
+
+    fn seeded(kind: Layers, seed: u64) -> Dropout {
+        let mut dropout = Dropout::new(kind, &[5, 8]).unwrap();
+        dropout.seed(seed);
+        dropout
+    }
+
+    #[test]
+    fn seeded_layers_drop_the_same_values() {
+        for kind in [
+            Layers::Dropout { rate: 0.4 },
+            Layers::AlphaDropout { rate: 0.4 },
+            Layers::SpatialDropout { rate: 0.4 },
+        ] {
+            let x = Array3::random((4, 5, 8), StandardNormal);
+            let (mut a, mut b) = (seeded(kind, 9), seeded(kind, 9));
+
+            for _ in 0..3 {
+                let y = a.forward(x.clone()).unwrap();
+                assert_eq!(b.forward(x.clone()).unwrap(), y);
+                assert_ne!(y, x);
+            }
+        }
+    }
+
+    #[test]
+    fn inverted_dropout_keeps_the_mean_activation() {
+        let x = Array3::ones((200, 5, 8));
+        let y = seeded(Layers::Dropout { rate: 0.25 }, 2)
+            .forward(x.clone())
+            .unwrap();
+
+        assert!((y.mean().unwrap() - 1.).abs() < 0.02);
+    }
+
+    #[test]
+    fn nothing_is_dropped_out_of_training() {
+        let x = Array3::random((4, 5, 8), StandardNormal);
+        let mut dropout = seeded(Layers::Dropout { rate: 0.5 }, 1);
+        dropout.set_training(false);
+
+        assert_eq!(dropout.forward(x.clone()).unwrap(), x);
+        assert_eq!(dropout.backward(x.clone()).unwrap(), x);
+        assert_eq!(dropout.infer(x.clone()).unwrap(), x);
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::BTreeSet;
+use std::mem::size_of;
+
+use ndarray::{s, Array1, Array2, Array3, Axis};
+
+use serde::{self, Deserialize, Serialize};
+
+use crate::activation::Activations;
+use crate::error::{expect_shape, reshape, ManifoldError};
+use crate::substrate::Substrate;
+
+use super::storage::{Linked, Storage};
+use super::types::{Layer, LayerSummary, Layers};
+use super::{Weighted, Weights};
+
+/// Integer ids looked up in a (vocabulary, size) table, every input value
+/// becoming a vector of `size`. A per sample input of `[length]` comes out
+/// as `[length, size]`.
+///
+/// Only rows that were looked up carry gradient, and only those rows are
+/// shifted. Embedding has no activation of its own, the layer's is ignored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(bound = "")]
+pub struct Embedding<S: Storage = Linked> {
+    vocabulary: usize,
+    input: Vec<usize>,
+    pub weights: Weights<S>,
+    #[serde(skip)]
+    ids: Array2<usize>,
+    #[serde(skip)]
+    touched: BTreeSet<usize>,
+    #[serde(skip)]
+    mask: Option<Array3<f64>>,
+}
+
+impl<S: Storage> Embedding<S> {
+    pub fn new(
+        kind: Layers,
+        pool_size: usize,
+        input: &[usize],
+        size: usize,
+    ) -> Result<Embedding<S>, ManifoldError> {
+        let vocabulary = match kind {
+            Layers::Embedding { vocabulary } if vocabulary > 0 && !input.is_empty() => vocabulary,
+            _ => {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "{} can not embed {:?}",
+                    kind.name(),
+                    input
+                )))
+            }
+        };
+
+        Ok(Embedding {
+            vocabulary,
+            input: input.to_vec(),
+            weights: Weights::new(pool_size, (vocabulary, size), 0),
+            ids: Array2::zeros((0, 0)),
+            touched: BTreeSet::new(),
+            mask: None,
+        })
+    }
+
+    pub fn output_shape(&self) -> Vec<usize> {
+        let mut output = self.input.clone();
+        output.push(self.weights.w.ncols());
+        output
+    }
+
+    // One row of ids per sample, every value checked against the vocabulary.
+    fn ids(&self, x: Array3<f64>) -> Result<Array2<usize>, ManifoldError> {
+        let (batch, positions, channels) = x.dim();
+        expect_shape(&[self.input.iter().product()], &[positions * channels])?;
+
+        if let Some(id) = x
+            .iter()
+            .find(|id| id.fract() != 0. || **id < 0. || **id >= self.vocabulary as f64)
+        {
+            return Err(ManifoldError::InvalidLayer(format!(
+                "Embedding id {} outside of a vocabulary of {}",
+                id, self.vocabulary
+            )));
+        }
+
+        Ok(reshape(x, (batch, positions * channels))?.mapv(|id| id as usize))
+    }
+
+    fn lookup(&self, ids: &Array2<usize>) -> Result<Array3<f64>, ManifoldError> {
+        let (batch, positions) = ids.dim();
+        let rows = self
+            .weights
+            .w
+            .select(Axis(0), &ids.iter().copied().collect::<Vec<usize>>());
+
+        reshape(rows, (batch, positions, self.weights.w.ncols()))
+    }
+}
+
+impl<S: Storage> Layer for Embedding<S> {
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let ids = self.ids(x)?;
+        let mut y = self.lookup(&ids)?;
+
+        self.mask = self.weights.dropout_mask(y.dim());
+        if let Some(mask) = &self.mask {
+            y *= mask;
+        }
+
+        self.ids = ids;
+        Ok(y)
+    }
+
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        self.lookup(&self.ids(x)?)
+    }
+
+    fn backward(&mut self, mut grad_output: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let (batch, positions) = self.ids.dim();
+        expect_shape(
+            &[batch, positions, self.weights.w.ncols()],
+            grad_output.shape(),
+        )?;
+
+        if let Some(mask) = &self.mask {
+            grad_output *= mask;
+        }
+
+        let mut grad_w = Array2::zeros(self.weights.w.dim());
+        for ((n, p), id) in self.ids.indexed_iter() {
+            self.touched.insert(*id);
+            grad_w
+                .row_mut(*id)
+                .scaled_add(1., &grad_output.slice(s![n, p, ..]));
+        }
+        self.weights.descend(&grad_w, &Array1::zeros(0), batch);
+
+        // Ids are not differentiable, nothing flows back.
+        let channels = self.input.last().copied().unwrap_or(1);
+        Ok(Array3::zeros((batch, positions / channels, channels)))
+    }
+
+    fn weights(&self) -> Option<&dyn Weighted> {
+        Some(&self.weights)
+    }
+
+    fn weights_mut(&mut self) -> Option<&mut dyn Weighted> {
+        Some(&mut self.weights)
+    }
+
+    // Only rows looked up since the last shift move, whatever else the
+    // gradients hold after being rolled over, noised or clipped.
+    fn shift(&mut self, substrate: &Substrate, learning_rate: f64) -> Result<(), ManifoldError> {
+        let rows = std::mem::take(&mut self.touched)
+            .into_iter()
+            .collect::<Vec<usize>>();
+        let Weights {
+            links, w, grad_w, ..
+        } = &mut self.weights;
+        links.shift_rows(substrate, w, grad_w, &rows, learning_rate)
+    }
+
+    fn touched(&self) -> Option<BTreeSet<usize>> {
+        Some(self.touched.clone())
+    }
+
+    fn touch(&mut self, rows: &BTreeSet<usize>) {
+        self.touched.extend(rows);
+    }
+
+    // Only rows looked up since the last shift are pulled on, so rows that
+    // were not looked up stay put.
+    fn penalize(&mut self, substrate: &Substrate) {
+        let weights = &mut self.weights;
+        if let Some(penalty) = weights.regularization.penalty {
+            let mut pull = Array2::zeros(weights.grad_w.dim());
+            weights
+                .links
+                .penalize(penalty, &mut pull, &weights.w, substrate);
+
+            for id in self.touched.iter() {
+                weights.grad_w.row_mut(*id).scaled_add(1., &pull.row(*id));
+            }
+        }
+    }
+
+    fn kind(&self) -> Layers {
+        Layers::Embedding {
+            vocabulary: self.vocabulary,
+        }
+    }
+
+    fn describe(&self) -> LayerSummary {
+        let (links, distinct, bytes) = self.weights.footprint();
+
+        LayerSummary {
+            kind: self.kind(),
+            input: self.input.clone(),
+            output: self.output_shape(),
+            activation: Activations::Identity,
+            links,
+            distinct,
+            bytes: bytes + self.ids.len() * size_of::<usize>(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array2, Array3};
+    use ndarray_rand::rand_distr::{StandardNormal, Uniform};
+    use ndarray_rand::RandomExt;
+
+    use crate::activation::Activations;
+    use crate::layers::gradcheck::{wake, worst_error};
+    use crate::layers::storage::{Init, Linked};
+    use crate::layers::types::Layers;
+    use crate::substrate::Substrate;
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut embedding = wake(
+            Layers::Embedding { vocabulary: 6 },
+            &[3, 1],
+            4,
+            Activations::Identity,
+        );
+        let ids = Array3::random((4, 3, 1), Uniform::new(0, 6)).mapv(|id: usize| id as f64);
+
+        assert!(worst_error(embedding.as_mut(), &ids, false) < 1e-6);
+    }
+
+    // Rolled over and noised gradients reach every row, but only the rows
+    // looked up since the last shift may move.
+    #[test]
+    fn shifts_only_rows_looked_up() {
+        let substrate = Substrate::new(1000, -1.0..1.0);
+        let (mut embedding, _) = Layers::wake::<Linked>(
+            Layers::Embedding { vocabulary: 5 },
+            substrate.size,
+            &[2],
+            3,
+            Activations::Identity,
+        )
+        .unwrap();
+        embedding.initialize(&substrate, Init::Uniform).unwrap();
+        let (before, _) = embedding.gradient_bindings().unwrap();
+
+        let ids = array![[[1., 3.]], [[3., 1.]]];
+        let y = embedding.forward(ids).unwrap();
+        embedding.backward(Array3::ones(y.dim())).unwrap();
+        let (grad_w, _) = embedding.gradients_mut().unwrap();
+        *grad_w += &Array2::random((5, 3), StandardNormal);
+
+        embedding.shift(&substrate, 1.).unwrap();
+        let (after, _) = embedding.gradient_bindings().unwrap();
+        for row in [0, 2, 4] {
+            assert_eq!(before.row(row), after.row(row));
+        }
+        assert!(before.row(1) != after.row(1) || before.row(3) != after.row(3));
+
+        // Nothing was looked up since, whatever the gradients still hold.
+        embedding.shift(&substrate, 1.).unwrap();
+        assert_eq!(embedding.gradient_bindings().unwrap().0, after);
+    }
+}
@@ -0,0 +1,92 @@
+use ndarray::Array3;
+
+use serde::{self, Deserialize, Serialize};
+
+use crate::activation::Activations;
+use crate::error::{expect_shape, reshape, ManifoldError};
+
+use super::types::{Layer, LayerSummary, Layers};
+
+/// Every position and channel of a sample laid out as one vector, so
+/// spatial outputs can feed the head.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Flatten {
+    input: Vec<usize>,
+}
+
+impl Flatten {
+    pub fn new(input: &[usize]) -> Flatten {
+        Flatten {
+            input: input.to_vec(),
+        }
+    }
+
+    pub fn output_shape(&self) -> Vec<usize> {
+        vec![self.input.iter().product()]
+    }
+
+    fn positions(&self) -> usize {
+        self.input[..self.input.len().saturating_sub(1)]
+            .iter()
+            .product()
+    }
+
+    fn channels(&self) -> usize {
+        self.input.last().copied().unwrap_or(1)
+    }
+}
+
+impl Layer for Flatten {
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        self.infer(x)
+    }
+
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let (batch, positions, channels) = x.dim();
+        expect_shape(&[self.positions(), self.channels()], &[positions, channels])?;
+
+        reshape(x, (batch, 1, positions * channels))
+    }
+
+    fn backward(&mut self, grad_output: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let (batch, outputs, features) = grad_output.dim();
+        expect_shape(&[1, self.output_shape()[0]], &[outputs, features])?;
+
+        reshape(grad_output, (batch, self.positions(), self.channels()))
+    }
+
+    fn kind(&self) -> Layers {
+        Layers::Flatten
+    }
+
+    fn describe(&self) -> LayerSummary {
+        LayerSummary {
+            kind: self.kind(),
+            input: self.input.clone(),
+            output: self.output_shape(),
+            activation: Activations::Identity,
+            links: 0,
+            distinct: 0,
+            bytes: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array3;
+    use ndarray_rand::rand_distr::StandardNormal;
+    use ndarray_rand::RandomExt;
+
+    use crate::activation::Activations;
+    use crate::layers::gradcheck::{wake, worst_error};
+    use crate::layers::types::Layers;
+
+    #[test]
+    fn flatten_gradients_match_finite_differences() {
+        let mut flatten = wake(Layers::Flatten, &[2, 3, 2], 0, Activations::Identity);
+        let x = Array3::random((3, 6, 2), StandardNormal);
+
+        assert!(worst_error(flatten.as_mut(), &x, true) < 1e-6);
+    }
+}
@@ -0,0 +1,88 @@
+use ndarray::Array3;
+use ndarray_rand::rand_distr::StandardNormal;
+use ndarray_rand::RandomExt;
+
+use crate::activation::Activations;
+use crate::substrate::Substrate;
+
+use super::storage::{Init, Isolated};
+use super::types::{Layer, Layers};
+
+const EPSILON: f64 = 1e-6;
+
+/// An isolated layer with Xavier weights, so checks need no substrate.
+pub fn wake(
+    layer: Layers,
+    input: &[usize],
+    size: usize,
+    activation: Activations,
+) -> Box<dyn Layer> {
+    let (mut layer, _) = Layers::wake::<Isolated>(layer, 0, input, size, activation).unwrap();
+    layer.initialize(&Substrate::blank(), Init::Xavier).unwrap();
+    layer
+}
+
+// Objective the checks differentiate, the output weighed by fixed noise.
+fn objective(layer: &mut dyn Layer, x: &Array3<f64>, r: &Array3<f64>) -> f64 {
+    (layer.forward(x.clone()).unwrap() * r).sum()
+}
+
+fn relative(analytic: f64, numeric: f64) -> f64 {
+    (analytic - numeric).abs() / analytic.abs().max(numeric.abs()).max(1.)
+}
+
+/// Worst relative error between backward and central differences, over
+/// every weight and bias and, when `inputs`, every value of `x`. Gradients
+/// are kept as descent directions averaged over the batch, so they are
+/// turned back into plain sums first.
+pub fn worst_error(layer: &mut dyn Layer, x: &Array3<f64>, inputs: bool) -> f64 {
+    let y = layer.forward(x.clone()).unwrap();
+    let r = Array3::random(y.dim(), StandardNormal);
+    let dx = layer.backward(r.clone()).unwrap();
+    let batch = x.dim().0 as f64;
+    let mut worst: f64 = 0.;
+
+    if let Some((grad_w, grad_b)) = layer.gradients() {
+        let (w, b) = match layer.weights() {
+            Some(weights) => {
+                let (w, b) = weights.values();
+                (w.clone(), b.clone())
+            }
+            None => return worst,
+        };
+
+        for (ix, value) in w.indexed_iter() {
+            let mut at = |delta: f64| {
+                layer.weights_mut().unwrap().values_mut().0[ix] = value + delta;
+                objective(layer, x, &r)
+            };
+            let numeric = (at(EPSILON) - at(-EPSILON)) / (2. * EPSILON);
+            at(0.);
+            worst = worst.max(relative(-grad_w[ix] * batch, numeric));
+        }
+
+        for (ix, value) in b.indexed_iter() {
+            let mut at = |delta: f64| {
+                layer.weights_mut().unwrap().values_mut().1[ix] = value + delta;
+                objective(layer, x, &r)
+            };
+            let numeric = (at(EPSILON) - at(-EPSILON)) / (2. * EPSILON);
+            at(0.);
+            worst = worst.max(relative(-grad_b[ix] * batch, numeric));
+        }
+    }
+
+    if inputs {
+        for (ix, value) in x.indexed_iter() {
+            let mut at = |delta: f64| {
+                let mut shifted = x.clone();
+                shifted[ix] = value + delta;
+                objective(layer, &shifted, &r)
+            };
+            let numeric = (at(EPSILON) - at(-EPSILON)) / (2. * EPSILON);
+            worst = worst.max(relative(dx[ix], numeric));
+        }
+    }
+
+    worst
+}
@@ -1,6 +1,30 @@
+mod attention;
+mod conv;
 mod dense;
-mod dense_iso;
+mod dropout;
+mod embedding;
+mod flatten;
+#[cfg(test)]
+mod gradcheck;
+mod norm;
+mod pool;
+mod recurrent;
+pub mod regularization;
+pub mod storage;
 pub mod types;
+mod weights;
 
+pub use attention::{Attention, Encoder};
+pub use conv::Conv;
 pub use dense::Dense;
-pub use dense_iso::Layer as DenseIndependent;
+pub use dropout::Dropout;
+pub use embedding::Embedding;
+pub use flatten::Flatten;
+pub use norm::Norm;
+pub use pool::{GlobalAvgPool, Pool};
+pub use recurrent::Recurrent;
+pub use regularization::{Anchor, Mode, Penalty, Regularization};
+pub use storage::{Init, Isolated, Linked, Storage};
+pub use weights::{Weighted, Weights};
+
+pub type DenseIndependent = Dense<Isolated>;
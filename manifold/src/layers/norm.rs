@@ -0,0 +1,311 @@
+use std::mem::size_of;
+
+use ndarray::{Array, Array1, Array2, Array3, ArrayView1, Axis};
+
+use serde::{self, Deserialize, Serialize};
+
+use crate::activation::Activations;
+use crate::error::{expect_shape, reshape, ManifoldError};
+use crate::substrate::Substrate;
+
+use super::storage::{Init, Linked, Storage};
+use super::types::{Layer, LayerSummary, Layers};
+use super::{Weighted, Weights};
+
+const EPSILON: f64 = 1e-5;
+
+// Every row scaled to zero mean and unit variance, returned with the
+// inverse standard deviation of each row for the backward pass.
+pub(super) fn normalize_rows(x: &Array2<f64>) -> (Array2<f64>, Array1<f64>) {
+    let n = x.ncols() as f64;
+    let mean = x.sum_axis(Axis(1)) / n;
+    let centered = x - &mean.insert_axis(Axis(1));
+    let variance = centered.mapv(|v| v * v).sum_axis(Axis(1)) / n;
+    let inv_std = variance.mapv(|v| 1. / (v + EPSILON).sqrt());
+
+    (centered * inv_std.view().insert_axis(Axis(1)), inv_std)
+}
+
+// Gradient of normalize_rows' input from the gradient of its output.
+pub(super) fn unnormalize_rows(
+    d_xhat: &Array2<f64>,
+    xhat: &Array2<f64>,
+    inv_std: &Array1<f64>,
+) -> Array2<f64> {
+    let n = xhat.ncols() as f64;
+    let mean_d = d_xhat.sum_axis(Axis(1)) / n;
+    let mean_dx = (d_xhat * xhat).sum_axis(Axis(1)) / n;
+
+    (d_xhat - &mean_d.insert_axis(Axis(1)) - xhat * &mean_dx.insert_axis(Axis(1)))
+        * inv_std.view().insert_axis(Axis(1))
+}
+
+// Layer normalization with a gain and shift per feature. Returns the output
+// with the normalized rows and inverse deviations backward needs.
+pub(super) fn layer_norm(
+    x: &Array2<f64>,
+    gain: ArrayView1<f64>,
+    shift: ArrayView1<f64>,
+) -> (Array2<f64>, Array2<f64>, Array1<f64>) {
+    let (xhat, inv_std) = normalize_rows(x);
+    (&xhat * &gain + shift, xhat, inv_std)
+}
+
+// Gradients of layer_norm's input, gain and shift.
+pub(super) fn unlayer_norm(
+    dy: &Array2<f64>,
+    gain: ArrayView1<f64>,
+    xhat: &Array2<f64>,
+    inv_std: &Array1<f64>,
+) -> (Array2<f64>, Array1<f64>, Array1<f64>) {
+    let d_gain = (dy * xhat).sum_axis(Axis(0));
+    let d_shift = dy.sum_axis(Axis(0));
+
+    (
+        unnormalize_rows(&(dy * &gain), xhat, inv_std),
+        d_gain,
+        d_shift,
+    )
+}
+
+/// Batch or layer normalization over the last axis, scaled by gamma (the
+/// one row of `w`) and shifted by beta (`b`).
+///
+/// BatchNorm normalizes every feature over the batch's positions and keeps
+/// running statistics for inference, LayerNorm normalizes every position
+/// over its features. Gamma and beta are links when the kind asks for it,
+/// otherwise plain floats starting at one and zero.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(bound = "")]
+pub struct Norm<S: Storage = Linked> {
+    kind: Layers,
+    input: Vec<usize>,
+    pub weights: Weights<S>,
+    pub running_mean: Array1<f64>,
+    pub running_var: Array1<f64>,
+    #[serde(skip)]
+    xhat: Array2<f64>,
+    #[serde(skip)]
+    inv_std: Array1<f64>,
+    #[serde(skip)]
+    mask: Option<Array3<f64>>,
+}
+
+impl<S: Storage> Norm<S> {
+    pub fn new(kind: Layers, pool_size: usize, input: &[usize]) -> Result<Norm<S>, ManifoldError> {
+        let features = match (kind, input.last()) {
+            (Layers::BatchNorm { .. } | Layers::LayerNorm { .. }, Some(features)) => *features,
+            _ => {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "{} can not normalize {:?}",
+                    kind.name(),
+                    input
+                )))
+            }
+        };
+
+        let mut weights = Weights::new(pool_size, (1, features), features);
+        if !S::LINKED {
+            weights.w.fill(1.);
+            weights.b.fill(0.);
+        }
+
+        Ok(Norm {
+            kind,
+            input: input.to_vec(),
+            weights,
+            running_mean: Array::zeros(features),
+            running_var: Array::ones(features),
+            xhat: Array2::zeros((0, features)),
+            inv_std: Array::zeros(0),
+            mask: None,
+        })
+    }
+
+    pub fn output_shape(&self) -> Vec<usize> {
+        self.input.clone()
+    }
+
+    // Every position of the batch as one row of features.
+    fn rows(&self, x: Array3<f64>) -> Result<Array2<f64>, ManifoldError> {
+        let (batch, positions, features) = x.dim();
+        let expected = self.input[..self.input.len() - 1].iter().product::<usize>();
+        expect_shape(&[expected, self.weights.w.ncols()], &[positions, features])?;
+
+        reshape(x, (batch * positions, features))
+    }
+
+    // Rows normalized by the running statistics, with the inverse running
+    // deviation of every feature.
+    fn running(&self, rows: &Array2<f64>) -> (Array2<f64>, Array1<f64>) {
+        let inv_std = self.running_var.mapv(|v| 1. / (v + EPSILON).sqrt());
+        ((rows - &self.running_mean) * &inv_std, inv_std)
+    }
+
+    fn scale(&self, xhat: &Array2<f64>) -> Array2<f64> {
+        xhat * &self.weights.w.row(0) + &self.weights.b
+    }
+}
+
+impl<S: Storage> Layer for Norm<S> {
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let shape = x.dim();
+        let rows = self.rows(x)?;
+
+        // Batch statistics are features normalized over rows, the transpose
+        // of what LayerNorm does. Out of training the running ones are used
+        // and left alone.
+        let (xhat, inv_std) = match self.kind {
+            Layers::BatchNorm { .. } if !self.weights.mode.training() => self.running(&rows),
+            Layers::BatchNorm { momentum, .. } => {
+                let n = rows.nrows() as f64;
+                let mean = rows.sum_axis(Axis(0)) / n;
+                let (xhat, inv_std) = normalize_rows(&rows.t().to_owned());
+                let var = inv_std.mapv(|v| 1. / (v * v) - EPSILON);
+
+                self.running_mean = &self.running_mean * momentum + mean * (1. - momentum);
+                self.running_var = &self.running_var * momentum + var * (1. - momentum);
+                (xhat.reversed_axes(), inv_std)
+            }
+            _ => normalize_rows(&rows),
+        };
+
+        let mut y = reshape(self.scale(&xhat), shape)?;
+        self.mask = self.weights.dropout_mask(y.dim());
+        if let Some(mask) = &self.mask {
+            y *= mask;
+        }
+
+        self.xhat = xhat;
+        self.inv_std = inv_std;
+        Ok(y)
+    }
+
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let shape = x.dim();
+        let rows = self.rows(x)?;
+
+        let xhat = match self.kind {
+            Layers::BatchNorm { .. } => self.running(&rows).0,
+            _ => normalize_rows(&rows).0,
+        };
+
+        reshape(self.scale(&xhat), shape)
+    }
+
+    fn backward(&mut self, mut grad_output: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let (batch, positions, features) = grad_output.dim();
+        expect_shape(
+            &[self.xhat.nrows(), self.weights.w.ncols()],
+            &[batch * positions, features],
+        )?;
+
+        if let Some(mask) = &self.mask {
+            grad_output *= mask;
+        }
+
+        let dy = reshape(grad_output, (batch * positions, features))?;
+        let d_gain = (&dy * &self.xhat).sum_axis(Axis(0));
+        let d_shift = dy.sum_axis(Axis(0));
+        let d_xhat = dy * self.weights.w.row(0);
+
+        // Running statistics are constants, so only the scaling is left.
+        let dx = match self.kind {
+            Layers::BatchNorm { .. } if !self.weights.mode.training() => d_xhat * &self.inv_std,
+            Layers::BatchNorm { .. } => unnormalize_rows(
+                &d_xhat.reversed_axes().to_owned(),
+                &self.xhat.t().to_owned(),
+                &self.inv_std,
+            )
+            .reversed_axes(),
+            _ => unnormalize_rows(&d_xhat, &self.xhat, &self.inv_std),
+        };
+
+        self.weights
+            .descend(&d_gain.insert_axis(Axis(0)), &d_shift, batch);
+
+        reshape(
+            dx.as_standard_layout().to_owned(),
+            (batch, positions, features),
+        )
+    }
+
+    fn weights(&self) -> Option<&dyn Weighted> {
+        Some(&self.weights)
+    }
+
+    fn weights_mut(&mut self) -> Option<&mut dyn Weighted> {
+        Some(&mut self.weights)
+    }
+
+    fn statistics(&self) -> Option<(Array1<f64>, Array1<f64>)> {
+        match self.kind {
+            Layers::BatchNorm { .. } => Some((self.running_mean.clone(), self.running_var.clone())),
+            _ => None,
+        }
+    }
+
+    fn set_statistics(
+        &mut self,
+        (mean, var): &(Array1<f64>, Array1<f64>),
+    ) -> Result<(), ManifoldError> {
+        expect_shape(self.running_mean.shape(), mean.shape())?;
+        expect_shape(self.running_var.shape(), var.shape())?;
+
+        self.running_mean.assign(mean);
+        self.running_var.assign(var);
+        Ok(())
+    }
+
+    // There is nothing to draw, gains start at one and shifts at zero.
+    fn initialize(&mut self, substrate: &Substrate, init: Init) -> Result<(), ManifoldError> {
+        self.weights.initialize_blocks(substrate, init, &[])
+    }
+
+    fn kind(&self) -> Layers {
+        self.kind
+    }
+
+    fn describe(&self) -> LayerSummary {
+        let (links, distinct, bytes) = self.weights.footprint();
+        let cached =
+            self.running_mean.len() + self.running_var.len() + self.xhat.len() + self.inv_std.len();
+
+        LayerSummary {
+            kind: self.kind,
+            input: self.input.clone(),
+            output: self.output_shape(),
+            activation: Activations::Identity,
+            links,
+            distinct,
+            bytes: bytes + cached * size_of::<f64>(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array3;
+    use ndarray_rand::rand_distr::StandardNormal;
+    use ndarray_rand::RandomExt;
+
+    use crate::activation::Activations;
+    use crate::layers::gradcheck::{wake, worst_error};
+    use crate::layers::types::Layers;
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        for kind in [
+            Layers::BatchNorm {
+                momentum: 0.9,
+                linked: false,
+            },
+            Layers::LayerNorm { linked: false },
+        ] {
+            let mut norm = wake(kind, &[3, 4], 0, Activations::Identity);
+            let x = Array3::random((5, 3, 4), StandardNormal);
+
+            assert!(worst_error(norm.as_mut(), &x, true) < 1e-6, "{:?}", kind);
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::mem::size_of;
+
+use ndarray::{Array3, Axis};
+
+use serde::{self, Deserialize, Serialize};
+
+use crate::activation::Activations;
+use crate::error::{expect_shape, ManifoldError};
+
+use super::conv::{layout, Geometry};
+use super::types::{Layer, LayerSummary, Layers};
+
+/// Max or average pooling over (length, channels) or (height, width,
+/// channels) inputs, each channel on its own. Windows never pad.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Pool {
+    kind: Layers,
+    geometry: Geometry,
+    // Input position every output was taken from, max pooling only.
+    pub routes: Array3<usize>,
+}
+
+impl Pool {
+    pub fn new(kind: Layers, input: &[usize]) -> Result<Pool, ManifoldError> {
+        let geometry = Geometry::new(kind, input)?;
+
+        Ok(Pool {
+            kind,
+            geometry,
+            routes: Array3::zeros((0, 0, geometry.channels)),
+        })
+    }
+
+    /// Per sample output shape, laid out like the input.
+    pub fn output_shape(&self) -> Vec<usize> {
+        layout(self.kind, self.geometry.output, self.geometry.channels)
+    }
+
+    fn is_max(&self) -> bool {
+        matches!(
+            self.kind,
+            Layers::MaxPool1D { .. } | Layers::MaxPool2D { .. }
+        )
+    }
+
+    // Input positions covered by the window of every output position.
+    fn windows(&self) -> Vec<Vec<usize>> {
+        let geometry = &self.geometry;
+        let (oh, ow) = geometry.output;
+        let (kh, kw) = geometry.kernel;
+
+        (0..oh * ow)
+            .map(|o| {
+                (0..kh * kw)
+                    .filter_map(|k| geometry.tap((o / ow, o % ow), (k / kw, k % kw)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn pool(&self, x: &Array3<f64>) -> Result<(Array3<f64>, Array3<usize>), ManifoldError> {
+        let (batch, positions, channels) = x.dim();
+        expect_shape(
+            &[self.geometry.positions(), self.geometry.channels],
+            &[positions, channels],
+        )?;
+
+        let windows = self.windows();
+        let max = self.is_max();
+        let mut pooled = Array3::zeros((batch, windows.len(), channels));
+        let mut routes = Array3::zeros(pooled.dim());
+
+        for ((n, o, c), value) in pooled.indexed_iter_mut() {
+            let window = &windows[o];
+
+            if max {
+                let best = window.iter().copied().fold(window[0], |best, p| {
+                    if x[[n, p, c]] > x[[n, best, c]] {
+                        p
+                    } else {
+                        best
+                    }
+                });
+                routes[[n, o, c]] = best;
+                *value = x[[n, best, c]];
+            } else {
+                *value = window.iter().map(|p| x[[n, *p, c]]).sum::<f64>() / window.len() as f64;
+            }
+        }
+
+        Ok((pooled, routes))
+    }
+}
+
+impl Layer for Pool {
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let (pooled, routes) = self.pool(&x)?;
+        self.routes = routes;
+        Ok(pooled)
+    }
+
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        Ok(self.pool(&x)?.0)
+    }
+
+    fn backward(&mut self, grad_output: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let windows = self.windows();
+        let (batch, outputs, channels) = grad_output.dim();
+        expect_shape(
+            &[windows.len(), self.geometry.channels],
+            &[outputs, channels],
+        )?;
+
+        let max = self.is_max();
+        if max {
+            expect_shape(self.routes.shape(), grad_output.shape())?;
+        }
+
+        // Max sends each gradient back to the winner, average spreads it
+        // evenly over the window.
+        let mut grad_input = Array3::zeros((batch, self.geometry.positions(), channels));
+        for ((n, o, c), grad) in grad_output.indexed_iter() {
+            if max {
+                grad_input[[n, self.routes[[n, o, c]], c]] += grad;
+            } else {
+                for p in windows[o].iter() {
+                    grad_input[[n, *p, c]] += grad / windows[o].len() as f64;
+                }
+            }
+        }
+
+        Ok(grad_input)
+    }
+
+    fn kind(&self) -> Layers {
+        self.kind
+    }
+
+    fn describe(&self) -> LayerSummary {
+        LayerSummary {
+            kind: self.kind,
+            input: layout(self.kind, self.geometry.input, self.geometry.channels),
+            output: self.output_shape(),
+            activation: Activations::Identity,
+            links: 0,
+            distinct: 0,
+            bytes: self.routes.len() * size_of::<usize>(),
+        }
+    }
+}
+
+/// Mean of every channel over all positions of a sample, whatever the
+/// input's spatial shape.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GlobalAvgPool {
+    input: Vec<usize>,
+}
+
+impl GlobalAvgPool {
+    pub fn new(input: &[usize]) -> Result<GlobalAvgPool, ManifoldError> {
+        if input.is_empty() {
+            return Err(ManifoldError::InvalidLayer(
+                "GlobalAvgPool needs a channel axis".to_string(),
+            ));
+        }
+
+        Ok(GlobalAvgPool {
+            input: input.to_vec(),
+        })
+    }
+
+    pub fn output_shape(&self) -> Vec<usize> {
+        self.input[self.input.len() - 1..].to_vec()
+    }
+
+    fn positions(&self) -> usize {
+        self.input[..self.input.len() - 1].iter().product()
+    }
+}
+
+impl Layer for GlobalAvgPool {
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        self.infer(x)
+    }
+
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let channels = self.output_shape();
+        expect_shape(&[self.positions(), channels[0]], &x.shape()[1..])?;
+
+        Ok((x.sum_axis(Axis(1)) / self.positions() as f64).insert_axis(Axis(1)))
+    }
+
+    fn backward(&mut self, grad_output: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let (batch, outputs, channels) = grad_output.dim();
+        expect_shape(&[1, self.output_shape()[0]], &[outputs, channels])?;
+
+        let spread = grad_output / self.positions() as f64;
+        Ok(spread
+            .broadcast((batch, self.positions(), channels))
+            .ok_or(ManifoldError::ShapeMismatch {
+                expected: vec![batch, self.positions(), channels],
+                found: vec![batch, outputs, channels],
+            })?
+            .to_owned())
+    }
+
+    fn kind(&self) -> Layers {
+        Layers::GlobalAvgPool
+    }
+
+    fn describe(&self) -> LayerSummary {
+        LayerSummary {
+            kind: self.kind(),
+            input: self.input.clone(),
+            output: self.output_shape(),
+            activation: Activations::Identity,
+            links: 0,
+            distinct: 0,
+            bytes: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array3;
+    use ndarray_rand::rand_distr::StandardNormal;
+    use ndarray_rand::RandomExt;
+    use rand::seq::SliceRandom;
+    use rand::thread_rng;
+
+    use crate::activation::Activations;
+    use crate::layers::gradcheck::{wake, worst_error};
+    use crate::layers::types::Layers;
+
+    // Distinct values far enough apart that nudging one never changes which
+    // value a window picks.
+    fn untied(shape: (usize, usize, usize)) -> Array3<f64> {
+        let mut values = (0..shape.0 * shape.1 * shape.2)
+            .map(|i| i as f64 * 0.01)
+            .collect::<Vec<f64>>();
+        values.shuffle(&mut thread_rng());
+        Array3::from_shape_vec(shape, values).unwrap()
+    }
+
+    #[test]
+    fn max_pool1d_gradients_match_finite_differences() {
+        let kind = Layers::MaxPool1D { pool: 3, stride: 2 };
+        let mut pool = wake(kind, &[7, 2], 0, Activations::Identity);
+
+        assert!(worst_error(pool.as_mut(), &untied((3, 7, 2)), true) < 1e-6);
+    }
+
+    #[test]
+    fn max_pool2d_gradients_match_finite_differences() {
+        let mut pool = wake(
+            Layers::max_pool2d((2, 2)),
+            &[4, 4, 3],
+            0,
+            Activations::Identity,
+        );
+
+        assert!(worst_error(pool.as_mut(), &untied((2, 16, 3)), true) < 1e-6);
+    }
+
+    #[test]
+    fn avg_pool1d_gradients_match_finite_differences() {
+        let kind = Layers::AvgPool1D { pool: 3, stride: 2 };
+        let mut pool = wake(kind, &[7, 2], 0, Activations::Identity);
+        let x = Array3::random((3, 7, 2), StandardNormal);
+
+        assert!(worst_error(pool.as_mut(), &x, true) < 1e-6);
+    }
+
+    #[test]
+    fn avg_pool2d_gradients_match_finite_differences() {
+        let kind = Layers::AvgPool2D {
+            pool: (2, 3),
+            stride: (1, 2),
+        };
+        let mut pool = wake(kind, &[3, 5, 2], 0, Activations::Identity);
+        let x = Array3::random((2, 15, 2), StandardNormal);
+
+        assert!(worst_error(pool.as_mut(), &x, true) < 1e-6);
+    }
+
+    #[test]
+    fn global_avg_pool_gradients_match_finite_differences() {
+        let mut pool = wake(Layers::GlobalAvgPool, &[4, 3], 0, Activations::Identity);
+        let x = Array3::random((3, 4, 3), StandardNormal);
+
+        assert!(worst_error(pool.as_mut(), &x, true) < 1e-6);
+    }
+}
@@ -0,0 +1,445 @@
+use std::mem::size_of;
+
+use ndarray::{concatenate, s, Array1, Array2, Array3, ArrayView2, Axis};
+
+use serde::{self, Deserialize, Serialize};
+
+use crate::activation::{Activation, Activations};
+use crate::error::{expect_shape, ManifoldError};
+
+use super::storage::{Linked, Storage};
+use super::types::{Layer, LayerSummary, Layers};
+use super::{Weighted, Weights};
+
+// What one time step of the last forward pass left behind for backward.
+#[derive(Debug, Clone)]
+struct Step {
+    // [x_t, h_t-1], the rows every gate reads.
+    v: Array2<f64>,
+    // [x_t, r * h_t-1], the rows a GRU candidate reads.
+    reset: Array2<f64>,
+    // Gate pre-activations, one block of hidden columns per gate.
+    z: Array2<f64>,
+    // Cell state after the step, carried by LSTM only.
+    c: Array2<f64>,
+}
+
+/// Elman RNN, GRU or LSTM over (length, features) inputs, unrolled along the
+/// sequence axis and trained with backprop through time.
+///
+/// Input and recurrent weights of every gate share one (features + hidden,
+/// gates * hidden) matrix, so gates are linked into the substrate like any
+/// Dense weight. Gate blocks are [i, f, g, o] for LSTM and [z, r, n] for GRU.
+/// `activation` is the candidate nonlinearity, gates always use sigmoid.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(bound = "")]
+pub struct Recurrent<S: Storage = Linked> {
+    kind: Layers,
+    length: usize,
+    features: usize,
+    hidden: usize,
+    pub weights: Weights<S>,
+    pub activation: Activations,
+    #[serde(skip)]
+    steps: Vec<Step>,
+    #[serde(skip)]
+    mask: Option<Array3<f64>>,
+}
+
+impl<S: Storage> Recurrent<S> {
+    pub fn new(
+        kind: Layers,
+        pool_size: usize,
+        input: &[usize],
+        hidden: usize,
+        activation: Activations,
+    ) -> Result<Recurrent<S>, ManifoldError> {
+        // A flat input is a sequence of one step.
+        let (length, features) = match input {
+            [features] => (1, *features),
+            [length, features] => (*length, *features),
+            _ => {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "{} expects (length, features) input, found {:?}",
+                    kind.name(),
+                    input
+                )))
+            }
+        };
+
+        let gates = match kind {
+            Layers::RNN { .. } => 1,
+            Layers::GRU { .. } => 3,
+            Layers::LSTM { .. } => 4,
+            _ => {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "{} is not recurrent",
+                    kind.name()
+                )))
+            }
+        };
+
+        let w_shape = (features + hidden, gates * hidden);
+
+        Ok(Recurrent {
+            kind,
+            length,
+            features,
+            hidden,
+            weights: Weights::new(pool_size, w_shape, gates * hidden),
+            activation,
+            steps: vec![],
+            mask: None,
+        })
+    }
+
+    fn sequences(&self) -> bool {
+        match self.kind {
+            Layers::RNN { return_sequences }
+            | Layers::GRU { return_sequences }
+            | Layers::LSTM { return_sequences } => return_sequences,
+            _ => false,
+        }
+    }
+
+    /// Per sample output shape, every hidden state or only the last one.
+    pub fn output_shape(&self) -> Vec<usize> {
+        match self.sequences() {
+            true => vec![self.length, self.hidden],
+            false => vec![self.hidden],
+        }
+    }
+
+    // Hidden columns of gate `k`.
+    fn gate(&self, z: &Array2<f64>, k: usize) -> Array2<f64> {
+        z.slice(s![.., k * self.hidden..(k + 1) * self.hidden])
+            .to_owned()
+    }
+
+    fn step(
+        &self,
+        x: ArrayView2<f64>,
+        h: &Array2<f64>,
+        c: &Array2<f64>,
+        sigmoid: &dyn Activation,
+        activ: &dyn Activation,
+    ) -> (Array2<f64>, Array2<f64>, Step) {
+        let n = self.hidden;
+        let v = concatenate![Axis(1), x, h.view()];
+        let empty = Array2::zeros((0, 0));
+
+        match self.kind {
+            Layers::LSTM { .. } => {
+                let z = v.dot(&self.weights.w) + &self.weights.b;
+                let (i, f) = (sigmoid.a(self.gate(&z, 0)), sigmoid.a(self.gate(&z, 1)));
+                let (g, o) = (activ.a(self.gate(&z, 2)), sigmoid.a(self.gate(&z, 3)));
+
+                let c = f * c + i * g;
+                let h = o * activ.a(c.clone());
+                let step = Step {
+                    v,
+                    reset: empty,
+                    z,
+                    c: c.clone(),
+                };
+                (h, c, step)
+            }
+            Layers::GRU { .. } => {
+                let zr = v.dot(&self.weights.w.slice(s![.., ..2 * n]))
+                    + self.weights.b.slice(s![..2 * n]);
+                let (u, r) = (sigmoid.a(self.gate(&zr, 0)), sigmoid.a(self.gate(&zr, 1)));
+
+                let reset = concatenate![Axis(1), x, (&r * h).view()];
+                let a_n = reset.dot(&self.weights.w.slice(s![.., 2 * n..]))
+                    + self.weights.b.slice(s![2 * n..]);
+                let candidate = activ.a(a_n.clone());
+
+                let h = (1. - &u) * candidate + &u * h;
+                let step = Step {
+                    v,
+                    reset,
+                    z: concatenate![Axis(1), zr, a_n],
+                    c: empty,
+                };
+                (h, c.clone(), step)
+            }
+            _ => {
+                let z = v.dot(&self.weights.w) + &self.weights.b;
+                let h = activ.a(z.clone());
+                let step = Step {
+                    v,
+                    reset: empty.clone(),
+                    z,
+                    c: empty,
+                };
+                (h, c.clone(), step)
+            }
+        }
+    }
+
+    // One step of backprop through time. Takes the gradients reaching h_t
+    // and c_t, adds this step's weight gradients and returns the gradients
+    // of x_t, h_t-1 and c_t-1.
+    #[allow(clippy::too_many_arguments)]
+    fn unstep(
+        &self,
+        step: &Step,
+        c_prev: &Array2<f64>,
+        dh: Array2<f64>,
+        dc: Array2<f64>,
+        grad_w: &mut Array2<f64>,
+        grad_b: &mut Array1<f64>,
+        sigmoid: &dyn Activation,
+        activ: &dyn Activation,
+    ) -> (Array2<f64>, Array2<f64>, Array2<f64>) {
+        let (n, features) = (self.hidden, self.features);
+        let z = &step.z;
+
+        let (dz, mut dx, mut dh_prev, dc_prev) = match self.kind {
+            Layers::LSTM { .. } => {
+                let (i, f) = (sigmoid.a(self.gate(z, 0)), sigmoid.a(self.gate(z, 1)));
+                let (g, o) = (activ.a(self.gate(z, 2)), sigmoid.a(self.gate(z, 3)));
+
+                let dc = dc + &dh * &o * activ.d(step.c.clone());
+                let d_o = dh * activ.a(step.c.clone());
+                let dz = concatenate![
+                    Axis(1),
+                    (&dc * &g * sigmoid.d(self.gate(z, 0))).view(),
+                    (&dc * c_prev * sigmoid.d(self.gate(z, 1))).view(),
+                    (&dc * &i * activ.d(self.gate(z, 2))).view(),
+                    (d_o * sigmoid.d(self.gate(z, 3))).view()
+                ];
+                let dc_prev = dc * f;
+                let empty = Array2::zeros((dz.nrows(), 0));
+                (dz, empty.clone(), empty, dc_prev)
+            }
+            Layers::GRU { .. } => {
+                let (u, r) = (sigmoid.a(self.gate(z, 0)), sigmoid.a(self.gate(z, 1)));
+                let a_n = self.gate(z, 2);
+                let candidate = activ.a(a_n.clone());
+                let h_prev = step.v.slice(s![.., features..]);
+
+                let da_n = &dh * (1. - &u) * activ.d(a_n);
+                let du = &dh * (&h_prev - &candidate);
+                let dh_prev = dh * &u;
+
+                grad_w
+                    .slice_mut(s![.., 2 * n..])
+                    .scaled_add(1., &step.reset.t().dot(&da_n));
+                grad_b
+                    .slice_mut(s![2 * n..])
+                    .scaled_add(1., &da_n.sum_axis(Axis(0)));
+
+                let d_reset = da_n.dot(&self.weights.w.slice(s![.., 2 * n..]).t());
+                let d_rh = d_reset.slice(s![.., features..]);
+                let dr = &d_rh * &h_prev;
+                let dh_prev = dh_prev + &d_rh * &r;
+
+                let dz = concatenate![
+                    Axis(1),
+                    (du * sigmoid.d(self.gate(z, 0))).view(),
+                    (dr * sigmoid.d(self.gate(z, 1))).view()
+                ];
+                let dx = d_reset.slice(s![.., ..features]).to_owned();
+                (dz, dx, dh_prev, dc)
+            }
+            _ => {
+                let dz = dh * activ.d(z.clone());
+                let empty = Array2::zeros((dz.nrows(), 0));
+                (dz, empty.clone(), empty, dc)
+            }
+        };
+
+        // Every gate reading [x_t, h_t-1], the GRU candidate was done above.
+        let columns = dz.ncols();
+        grad_w
+            .slice_mut(s![.., ..columns])
+            .scaled_add(1., &step.v.t().dot(&dz));
+        grad_b
+            .slice_mut(s![..columns])
+            .scaled_add(1., &dz.sum_axis(Axis(0)));
+
+        let dv = dz.dot(&self.weights.w.slice(s![.., ..columns]).t());
+        let (dv_x, dv_h) = dv.view().split_at(Axis(1), features);
+
+        dx = match dx.is_empty() {
+            true => dv_x.to_owned(),
+            false => dx + dv_x,
+        };
+        dh_prev = match dh_prev.is_empty() {
+            true => dv_h.to_owned(),
+            false => dh_prev + dv_h,
+        };
+
+        (dx, dh_prev, dc_prev)
+    }
+
+    fn run(&self, x: &Array3<f64>) -> Result<(Array3<f64>, Vec<Step>), ManifoldError> {
+        let (batch, length, features) = x.dim();
+        expect_shape(&[self.length, self.features], &[length, features])?;
+
+        let sigmoid = Activations::Sigmoid.wake();
+        let activ = self.activation.wake();
+        let sequences = self.sequences();
+
+        let mut h = Array2::zeros((batch, self.hidden));
+        let mut c = Array2::zeros((batch, self.hidden));
+        let mut steps = Vec::with_capacity(length);
+        let mut output = Array3::zeros((batch, if sequences { length } else { 1 }, self.hidden));
+
+        for t in 0..length {
+            let (h_t, c_t, step) = self.step(
+                x.slice(s![.., t, ..]),
+                &h,
+                &c,
+                sigmoid.as_ref(),
+                activ.as_ref(),
+            );
+            if sequences {
+                output.slice_mut(s![.., t, ..]).assign(&h_t);
+            }
+            (h, c) = (h_t, c_t);
+            steps.push(step);
+        }
+
+        if !sequences {
+            output.slice_mut(s![.., 0, ..]).assign(&h);
+        }
+        Ok((output, steps))
+    }
+}
+
+impl<S: Storage> Layer for Recurrent<S> {
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let (mut output, steps) = self.run(&x)?;
+
+        self.mask = self.weights.dropout_mask(output.dim());
+        if let Some(mask) = &self.mask {
+            output *= mask;
+        }
+
+        self.steps = steps;
+        Ok(output)
+    }
+
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        Ok(self.run(&x)?.0)
+    }
+
+    fn backward(&mut self, mut grad_output: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let length = self.steps.len();
+        let batch = self.steps.first().map_or(0, |step| step.v.nrows());
+        let sequences = self.sequences();
+
+        expect_shape(
+            &[batch, if sequences { length } else { 1 }, self.hidden],
+            grad_output.shape(),
+        )?;
+        if let Some(mask) = &self.mask {
+            grad_output *= mask;
+        }
+
+        let sigmoid = Activations::Sigmoid.wake();
+        let activ = self.activation.wake();
+
+        let mut grad_w = Array2::zeros(self.weights.w.dim());
+        let mut grad_b = Array1::zeros(self.weights.b.dim());
+        let mut grad_input = Array3::zeros((batch, length, self.features));
+        let mut dh = Array2::zeros((batch, self.hidden));
+        let mut dc = Array2::zeros((batch, self.hidden));
+        let zeros = Array2::zeros((batch, self.hidden));
+
+        for t in (0..length).rev() {
+            if sequences {
+                dh += &grad_output.slice(s![.., t, ..]);
+            } else if t == length - 1 {
+                dh += &grad_output.slice(s![.., 0, ..]);
+            }
+
+            let c_prev = match (t, &self.kind) {
+                (1.., Layers::LSTM { .. }) => &self.steps[t - 1].c,
+                _ => &zeros,
+            };
+
+            let (dx, dh_prev, dc_prev) = self.unstep(
+                &self.steps[t],
+                c_prev,
+                dh,
+                dc,
+                &mut grad_w,
+                &mut grad_b,
+                sigmoid.as_ref(),
+                activ.as_ref(),
+            );
+
+            grad_input.slice_mut(s![.., t, ..]).assign(&dx);
+            (dh, dc) = (dh_prev, dc_prev);
+        }
+
+        self.weights.descend(&grad_w, &grad_b, batch);
+
+        Ok(grad_input)
+    }
+
+    fn weights(&self) -> Option<&dyn Weighted> {
+        Some(&self.weights)
+    }
+
+    fn weights_mut(&mut self) -> Option<&mut dyn Weighted> {
+        Some(&mut self.weights)
+    }
+
+    fn kind(&self) -> Layers {
+        self.kind
+    }
+
+    fn describe(&self) -> LayerSummary {
+        let (links, distinct, bytes) = self.weights.footprint();
+        let cached = self
+            .steps
+            .iter()
+            .map(|step| step.v.len() + step.reset.len() + step.z.len() + step.c.len())
+            .sum::<usize>();
+
+        LayerSummary {
+            kind: self.kind,
+            input: vec![self.length, self.features],
+            output: self.output_shape(),
+            activation: self.activation,
+            links,
+            distinct,
+            bytes: bytes + cached * size_of::<f64>(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array3;
+    use ndarray_rand::rand_distr::StandardNormal;
+    use ndarray_rand::RandomExt;
+
+    use crate::activation::Activations;
+    use crate::layers::gradcheck::{wake, worst_error};
+    use crate::layers::types::Layers;
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        for return_sequences in [false, true] {
+            for kind in [
+                Layers::RNN { return_sequences },
+                Layers::GRU { return_sequences },
+                Layers::LSTM { return_sequences },
+            ] {
+                let mut recurrent = wake(kind, &[4, 3], 5, Activations::Tanh);
+                let x = Array3::random((3, 4, 3), StandardNormal);
+
+                assert!(
+                    worst_error(recurrent.as_mut(), &x, true) < 1e-6,
+                    "{:?}",
+                    kind
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use ndarray::{Array2, Array3, Zip};
+use ndarray_rand::rand_distr::Bernoulli;
+use ndarray_rand::RandomExt;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::substrate::Substrate;
+
+/// Where a penalty pulls links. `Index` and `Center` pull on the link itself,
+/// measured in substrate steps. `Magnitude` pulls on the pooled value, toward
+/// whichever indices hold values near zero.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum Anchor {
+    Index(usize),
+    Center,
+    Magnitude,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum Penalty {
+    L1 { lambda: f64, anchor: Anchor },
+    L2 { lambda: f64, anchor: Anchor },
+}
+
+impl Penalty {
+    // grad holds descent directions (negated gradients) as Dense accumulates
+    // them, so the penalty is subtracted.
+    pub fn apply(
+        &self,
+        grad: &mut Array2<f64>,
+        links: &Array2<usize>,
+        values: &Array2<f64>,
+        substrate: &Substrate,
+    ) {
+        let (lambda, anchor) = self.parts();
+
+        let zero = match anchor {
+            Anchor::Index(ix) => ix,
+            Anchor::Center => substrate.size / 2,
+            Anchor::Magnitude => return self.apply_values(grad, values),
+        };
+
+        let step = substrate.step().max(f64::EPSILON);
+
+        Zip::from(grad).and(links).for_each(|g, l| {
+            let d = (*l as f64 - zero as f64) / step;
+            *g -= lambda * self.shape(d);
+        });
+    }
+
+    /// Pull on the values alone, whatever the anchor.
+    pub fn apply_values(&self, grad: &mut Array2<f64>, values: &Array2<f64>) {
+        let (lambda, _) = self.parts();
+
+        Zip::from(grad)
+            .and(values)
+            .for_each(|g, v| *g -= lambda * self.shape(*v));
+    }
+
+    fn parts(&self) -> (f64, Anchor) {
+        match *self {
+            Penalty::L1 { lambda, anchor } | Penalty::L2 { lambda, anchor } => (lambda, anchor),
+        }
+    }
+
+    // Derivative of the penalty at distance d from its anchor.
+    fn shape(&self, d: f64) -> f64 {
+        match self {
+            Penalty::L2 { .. } => d,
+            Penalty::L1 { .. } if d == 0. => 0.,
+            Penalty::L1 { .. } => d.signum(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Regularization {
+    pub penalty: Option<Penalty>,
+    pub dropout: f64,
+}
+
+impl Regularization {
+    // Inverted dropout, so kept activations are scaled up during training and
+    // nothing needs rescaling at inference.
+    pub fn dropout_mask(
+        &self,
+        shape: (usize, usize, usize),
+        mode: &mut Mode,
+    ) -> Option<Array3<f64>> {
+        if self.dropout <= 0. || !mode.training() {
+            return None;
+        }
+
+        let keep = (1. - self.dropout).clamp(0., 1.);
+        let scale = if keep > 0. { 1. / keep } else { 0. };
+
+        Some(mode.keep(shape, keep)?.mapv(|k| k * scale))
+    }
+}
+
+/// Whether a layer is training, and where its dropout masks come from.
+/// Layers start out training, drawing masks from the thread rng until
+/// seeded. A seeded stream is dumped with the layer and picks up where it
+/// left off once loaded.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Mode {
+    pub inference: bool,
+    #[serde(with = "stream")]
+    rng: Option<ChaCha8Rng>,
+}
+
+mod stream {
+    use rand_chacha::ChaCha8Rng;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::optimizers::RngState;
+
+    pub fn serialize<S: Serializer>(
+        rng: &Option<ChaCha8Rng>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        rng.as_ref().map(RngState::capture).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<ChaCha8Rng>, D::Error> {
+        Ok(Option::<RngState>::deserialize(deserializer)?.map(|state| state.restore()))
+    }
+}
+
+impl Mode {
+    pub fn training(&self) -> bool {
+        !self.inference
+    }
+
+    pub fn set_training(&mut self, training: bool) {
+        self.inference = !training;
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Some(ChaCha8Rng::seed_from_u64(seed));
+    }
+
+    /// Ones where a value is kept, with probability `keep`, zeros elsewhere.
+    pub fn keep(&mut self, shape: (usize, usize, usize), keep: f64) -> Option<Array3<f64>> {
+        let bernoulli = Bernoulli::new(keep).ok()?;
+        let kept = match &mut self.rng {
+            Some(rng) => Array3::random_using(shape, bernoulli, rng),
+            None => Array3::random(shape, bernoulli),
+        };
+
+        Some(kept.mapv(|k| if k { 1. } else { 0. }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mode, Regularization};
+
+    fn dropout(rate: f64) -> Regularization {
+        Regularization {
+            penalty: None,
+            dropout: rate,
+        }
+    }
+
+    fn seeded(seed: u64) -> Mode {
+        let mut mode = Mode::default();
+        mode.seed(seed);
+        mode
+    }
+
+    #[test]
+    fn seeded_masks_are_reproducible() {
+        let (mut a, mut b, mut c) = (seeded(3), seeded(3), seeded(4));
+
+        for _ in 0..3 {
+            let mask = dropout(0.5).dropout_mask((4, 2, 8), &mut a).unwrap();
+            assert_eq!(dropout(0.5).dropout_mask((4, 2, 8), &mut b).unwrap(), mask);
+            assert_ne!(dropout(0.5).dropout_mask((4, 2, 8), &mut c).unwrap(), mask);
+        }
+    }
+
+    // Kept values are scaled by 1 / keep, so the mask averages out to one
+    // and the expected activation is left as it was.
+    #[test]
+    fn inverted_scaling_keeps_the_expected_activation() {
+        let mask = dropout(0.3)
+            .dropout_mask((100, 10, 100), &mut seeded(5))
+            .unwrap();
+
+        assert!(mask
+            .iter()
+            .all(|m| *m == 0. || (*m - 1. / 0.7).abs() < 1e-12));
+        assert!((mask.mean().unwrap() - 1.).abs() < 0.01);
+    }
+
+    #[test]
+    fn nothing_is_dropped_out_of_training() {
+        let mut mode = seeded(6);
+        mode.set_training(false);
+
+        assert!(dropout(0.5).dropout_mask((4, 2, 8), &mut mode).is_none());
+        assert!(dropout(0.)
+            .dropout_mask((4, 2, 8), &mut seeded(6))
+            .is_none());
+    }
+}
@@ -0,0 +1,405 @@
+use core::fmt::Debug;
+
+use ndarray::{Array, Array1, Array2, Axis};
+use ndarray_rand::rand_distr::{Normal, Uniform};
+use ndarray_rand::RandomExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::Penalty;
+use crate::error::ManifoldError;
+use crate::substrate::Substrate;
+
+/// How a layer's weights start out. `Uniform` is whatever the storage
+/// starts with, links spread evenly over the pool or floats in 0..1.
+/// `Xavier` and `He` draw normal weights with deviations of
+/// sqrt(2 / (fan_in + fan_out)) and sqrt(2 / fan_in), biases starting at
+/// zero. Linked weights point at the pooled value nearest each draw, so
+/// draws beyond the pool's range land on its edges.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum Init {
+    #[default]
+    Uniform,
+    Xavier,
+    He,
+}
+
+impl Init {
+    /// Deviation of weights mapping fan_in values to fan_out, none for
+    /// `Uniform`.
+    pub fn deviation(&self, fan_in: usize, fan_out: usize) -> Option<f64> {
+        let (fan_in, fan_out) = (fan_in.max(1) as f64, fan_out.max(1) as f64);
+        match self {
+            Init::Uniform => None,
+            Init::Xavier => Some((2. / (fan_in + fan_out)).sqrt()),
+            Init::He => Some((2. / fan_in).sqrt()),
+        }
+    }
+
+    /// Weights of a (fan_in, fan_out) matrix, none for `Uniform`.
+    pub fn sample(&self, shape: (usize, usize)) -> Option<Array2<f64>> {
+        self.deviation(shape.0, shape.1)
+            .and_then(|deviation| Normal::new(0., deviation).ok())
+            .map(|normal| Array2::random(shape, normal))
+    }
+}
+
+/// Where a layer's weights and bias live. Layers hold gathered float values
+/// either way and leave moving them to their storage.
+///
+/// Gradients arrive as descent directions (negated gradients).
+pub trait Storage: Debug + Clone + Send + Sync + Serialize + DeserializeOwned + 'static {
+    /// Whether weights are links into a shared substrate.
+    const LINKED: bool;
+
+    /// Storage for a (w_shape, b_shape) pair along with starting values.
+    fn new(
+        pool_size: usize,
+        w_shape: (usize, usize),
+        b_shape: usize,
+    ) -> (Self, Array2<f64>, Array1<f64>);
+
+    fn gather(
+        &self,
+        substrate: &Substrate,
+        w: &mut Array2<f64>,
+        b: &mut Array1<f64>,
+    ) -> Result<(), ManifoldError>;
+
+    fn shift(
+        &mut self,
+        substrate: &Substrate,
+        w: &mut Array2<f64>,
+        b: &mut Array1<f64>,
+        grad_w: &mut Array2<f64>,
+        grad_b: &mut Array1<f64>,
+        learning_rate: f64,
+    ) -> Result<(), ManifoldError>;
+
+    /// Restart `w` and `b` as `init` has them.
+    fn initialize(
+        &mut self,
+        substrate: &Substrate,
+        init: Init,
+        w: &mut Array2<f64>,
+        b: &mut Array1<f64>,
+    ) -> Result<(), ManifoldError>;
+
+    /// Point `w` and `b` at the given values, or as near to them as the
+    /// storage gets.
+    fn assign(
+        &mut self,
+        substrate: &Substrate,
+        values: (Array2<f64>, Array1<f64>),
+        w: &mut Array2<f64>,
+        b: &mut Array1<f64>,
+    ) -> Result<(), ManifoldError>;
+
+    /// Shift only the given rows of `w`, leaving the other rows and the bias
+    /// where they are.
+    fn shift_rows(
+        &mut self,
+        substrate: &Substrate,
+        w: &mut Array2<f64>,
+        grad_w: &mut Array2<f64>,
+        rows: &[usize],
+        learning_rate: f64,
+    ) -> Result<(), ManifoldError>;
+
+    fn penalize(
+        &self,
+        penalty: Penalty,
+        grad_w: &mut Array2<f64>,
+        w: &Array2<f64>,
+        substrate: &Substrate,
+    );
+
+    fn links(&self) -> Option<(&Array2<usize>, &Array1<usize>)>;
+    fn links_mut(&mut self) -> Option<(&mut Array2<usize>, &mut Array1<usize>)>;
+}
+
+/// Weights are indices into a substrate, training moves the indices.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Linked {
+    pub wi: Array2<usize>,
+    pub bi: Array1<usize>,
+}
+
+impl Storage for Linked {
+    const LINKED: bool = true;
+
+    fn new(
+        pool_size: usize,
+        w_shape: (usize, usize),
+        b_shape: usize,
+    ) -> (Linked, Array2<f64>, Array1<f64>) {
+        let linked = Linked {
+            wi: Array2::random(w_shape, Uniform::new(0, pool_size)),
+            bi: Array::random(b_shape, Uniform::new(0, pool_size)),
+        };
+        (linked, Array2::zeros(w_shape), Array::zeros(b_shape))
+    }
+
+    fn gather(
+        &self,
+        substrate: &Substrate,
+        w: &mut Array2<f64>,
+        b: &mut Array1<f64>,
+    ) -> Result<(), ManifoldError> {
+        *w = substrate.gather(&self.wi)?;
+        *b = substrate.gather(&self.bi)?;
+        Ok(())
+    }
+
+    fn shift(
+        &mut self,
+        substrate: &Substrate,
+        w: &mut Array2<f64>,
+        b: &mut Array1<f64>,
+        grad_w: &mut Array2<f64>,
+        grad_b: &mut Array1<f64>,
+        learning_rate: f64,
+    ) -> Result<(), ManifoldError> {
+        let mut b_grad_reshaped = grad_b.to_owned().insert_axis(Axis(1));
+        let mut b_link_reshaped = self.bi.to_owned().insert_axis(Axis(1));
+
+        substrate.highspeed(grad_w, &mut self.wi, learning_rate);
+        substrate.highspeed(&mut b_grad_reshaped, &mut b_link_reshaped, learning_rate);
+
+        self.bi = b_link_reshaped.remove_axis(Axis(1));
+        *grad_b = b_grad_reshaped.remove_axis(Axis(1));
+        self.gather(substrate, w, b)
+    }
+
+    fn initialize(
+        &mut self,
+        substrate: &Substrate,
+        init: Init,
+        w: &mut Array2<f64>,
+        b: &mut Array1<f64>,
+    ) -> Result<(), ManifoldError> {
+        match init.sample(self.wi.dim()) {
+            Some(values) => self.assign(substrate, (values, Array::zeros(self.bi.len())), w, b),
+            None => self.gather(substrate, w, b),
+        }
+    }
+
+    fn assign(
+        &mut self,
+        substrate: &Substrate,
+        (w_values, b_values): (Array2<f64>, Array1<f64>),
+        w: &mut Array2<f64>,
+        b: &mut Array1<f64>,
+    ) -> Result<(), ManifoldError> {
+        self.wi = w_values.mapv(|v| substrate.nearest(v));
+        self.bi = b_values.mapv(|v| substrate.nearest(v));
+        self.gather(substrate, w, b)
+    }
+
+    fn shift_rows(
+        &mut self,
+        substrate: &Substrate,
+        w: &mut Array2<f64>,
+        grad_w: &mut Array2<f64>,
+        rows: &[usize],
+        learning_rate: f64,
+    ) -> Result<(), ManifoldError> {
+        for row in rows.iter() {
+            let mut grad = grad_w.row(*row).to_owned().insert_axis(Axis(0));
+            let mut links = self.wi.row(*row).to_owned().insert_axis(Axis(0));
+
+            substrate.highspeed(&mut grad, &mut links, learning_rate);
+
+            w.row_mut(*row)
+                .assign(&substrate.gather(&links.row(0).to_owned())?);
+            grad_w.row_mut(*row).assign(&grad.row(0));
+            self.wi.row_mut(*row).assign(&links.row(0));
+        }
+        Ok(())
+    }
+
+    fn penalize(
+        &self,
+        penalty: Penalty,
+        grad_w: &mut Array2<f64>,
+        w: &Array2<f64>,
+        substrate: &Substrate,
+    ) {
+        penalty.apply(grad_w, &self.wi, w, substrate);
+    }
+
+    fn links(&self) -> Option<(&Array2<usize>, &Array1<usize>)> {
+        Some((&self.wi, &self.bi))
+    }
+
+    fn links_mut(&mut self) -> Option<(&mut Array2<usize>, &mut Array1<usize>)> {
+        Some((&mut self.wi, &mut self.bi))
+    }
+}
+
+/// Plain float weights owned by the layer, no substrate involved.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Isolated;
+
+impl Storage for Isolated {
+    const LINKED: bool = false;
+
+    fn new(
+        _pool_size: usize,
+        w_shape: (usize, usize),
+        b_shape: usize,
+    ) -> (Isolated, Array2<f64>, Array1<f64>) {
+        (
+            Isolated,
+            Array2::random(w_shape, Uniform::new(0., 1.)),
+            Array::random(b_shape, Uniform::new(0., 1.)),
+        )
+    }
+
+    fn gather(
+        &self,
+        _substrate: &Substrate,
+        _w: &mut Array2<f64>,
+        _b: &mut Array1<f64>,
+    ) -> Result<(), ManifoldError> {
+        Ok(())
+    }
+
+    fn shift(
+        &mut self,
+        _substrate: &Substrate,
+        w: &mut Array2<f64>,
+        b: &mut Array1<f64>,
+        grad_w: &mut Array2<f64>,
+        grad_b: &mut Array1<f64>,
+        learning_rate: f64,
+    ) -> Result<(), ManifoldError> {
+        w.scaled_add(learning_rate, grad_w);
+        b.scaled_add(learning_rate, grad_b);
+        Ok(())
+    }
+
+    fn initialize(
+        &mut self,
+        _substrate: &Substrate,
+        init: Init,
+        w: &mut Array2<f64>,
+        b: &mut Array1<f64>,
+    ) -> Result<(), ManifoldError> {
+        if let Some(values) = init.sample(w.dim()) {
+            *w = values;
+            b.fill(0.);
+        }
+        Ok(())
+    }
+
+    fn assign(
+        &mut self,
+        _substrate: &Substrate,
+        (w_values, b_values): (Array2<f64>, Array1<f64>),
+        w: &mut Array2<f64>,
+        b: &mut Array1<f64>,
+    ) -> Result<(), ManifoldError> {
+        *w = w_values;
+        *b = b_values;
+        Ok(())
+    }
+
+    fn shift_rows(
+        &mut self,
+        _substrate: &Substrate,
+        w: &mut Array2<f64>,
+        grad_w: &mut Array2<f64>,
+        rows: &[usize],
+        learning_rate: f64,
+    ) -> Result<(), ManifoldError> {
+        for row in rows.iter() {
+            w.row_mut(*row).scaled_add(learning_rate, &grad_w.row(*row));
+        }
+        Ok(())
+    }
+
+    // There are no links to anchor, every penalty falls back to pulling the
+    // values themselves toward zero.
+    fn penalize(
+        &self,
+        penalty: Penalty,
+        grad_w: &mut Array2<f64>,
+        w: &Array2<f64>,
+        _substrate: &Substrate,
+    ) {
+        penalty.apply_values(grad_w, w);
+    }
+
+    fn links(&self) -> Option<(&Array2<usize>, &Array1<usize>)> {
+        None
+    }
+
+    fn links_mut(&mut self) -> Option<(&mut Array2<usize>, &mut Array1<usize>)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array1, Array2};
+
+    use super::{Init, Isolated, Linked, Storage};
+    use crate::substrate::Substrate;
+
+    const SHAPE: (usize, usize) = (512, 256);
+
+    fn initialized<S: Storage>(substrate: &Substrate, init: Init) -> (Array2<f64>, Array1<f64>) {
+        let (mut storage, mut w, mut b) = S::new(substrate.size, SHAPE, SHAPE.1);
+        storage.initialize(substrate, init, &mut w, &mut b).unwrap();
+        (w, b)
+    }
+
+    // Mean and deviation of the weights.
+    fn moments(w: &Array2<f64>) -> (f64, f64) {
+        let mean = w.mean().unwrap();
+        (mean, w.mapv(|x| (x - mean).powi(2)).mean().unwrap().sqrt())
+    }
+
+    #[test]
+    fn uniform_weights_spread_over_their_range() {
+        let substrate = Substrate::new(10000, -1.0..1.0);
+
+        let (w, _) = initialized::<Linked>(&substrate, Init::Uniform);
+        let (mean, deviation) = moments(&w);
+        assert!(w.iter().all(|x| (-1.0..1.0).contains(x)));
+        assert!(mean.abs() < 0.02);
+        assert!((deviation - (1f64 / 3.).sqrt()).abs() < 0.02);
+
+        let (w, _) = initialized::<Isolated>(&substrate, Init::Uniform);
+        let (mean, deviation) = moments(&w);
+        assert!(w.iter().all(|x| (0.0..1.0).contains(x)));
+        assert!((mean - 0.5).abs() < 0.01);
+        assert!((deviation - (1f64 / 12.).sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn xavier_and_he_weights_have_the_requested_deviation() {
+        let substrate = Substrate::new(10000, -1.0..1.0);
+
+        for init in [Init::Xavier, Init::He] {
+            let expected = init.deviation(SHAPE.0, SHAPE.1).unwrap();
+
+            for (w, b) in [
+                initialized::<Linked>(&substrate, init),
+                initialized::<Isolated>(&substrate, init),
+            ] {
+                let (mean, deviation) = moments(&w);
+                assert!(mean.abs() < expected * 0.05, "{:?} mean {}", init, mean);
+                assert!(
+                    (deviation / expected - 1.).abs() < 0.1,
+                    "{:?} deviation {} for {}",
+                    init,
+                    deviation,
+                    expected
+                );
+                assert!(b.iter().all(|x| x.abs() < 1e-3));
+            }
+        }
+    }
+}
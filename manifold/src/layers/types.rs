@@ -1,42 +1,462 @@
-use super::Dense;
-use crate::{Activations, Substrate};
+use std::collections::BTreeSet;
+use std::error::Error;
+
+use super::storage::{Init, Isolated, Storage};
+use super::weights::Weighted;
+use super::{
+    Attention, Conv, Dense, Dropout, Embedding, Encoder, Flatten, GlobalAvgPool, Norm, Pool,
+    Recurrent, Regularization,
+};
+use crate::{Activations, ManifoldError, Substrate};
 use ndarray::{Array1, Array2, Array3};
 use serde::{Deserialize, Serialize};
 
-pub trait IsolatedLayer {
-    fn forward(&mut self, x: Array3<f64>) -> Array3<f64>;
-    fn backward(&mut self, grad_output: Array3<f64>) -> Array3<f64>;
-    fn gradients(&self) -> (Array2<f64>, Array1<f64>);
+pub trait Layer: LayerBox + Send + Sync {
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError>;
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError>;
+    fn backward(&mut self, grad_output: Array3<f64>) -> Result<Array3<f64>, ManifoldError>;
+    // Everything from here to kind() concerns weights, reached through
+    // weights(). Layers without any, like pooling or Flatten, leave these at
+    // their defaults.
+    fn weights(&self) -> Option<&dyn Weighted> {
+        None
+    }
+    fn weights_mut(&mut self) -> Option<&mut dyn Weighted> {
+        None
+    }
+    fn gradients(&self) -> Option<(Array2<f64>, Array1<f64>)> {
+        self.weights().map(|weights| weights.gradients())
+    }
+    fn gradients_mut(&mut self) -> Option<(&mut Array2<f64>, &mut Array1<f64>)> {
+        self.weights_mut().map(|weights| weights.gradients_mut())
+    }
+    fn gather(&mut self, substrate: &Substrate) -> Result<(), ManifoldError> {
+        self.weights_mut()
+            .map_or(Ok(()), |weights| weights.gather(substrate))
+    }
+    /// Step weights along the accumulated gradients, however they are stored.
+    fn shift(&mut self, substrate: &Substrate, learning_rate: f64) -> Result<(), ManifoldError> {
+        self.weights_mut()
+            .map_or(Ok(()), |weights| weights.shift(substrate, learning_rate))
+    }
+    fn shift_weights(&mut self, shift: &Array2<usize>) {
+        if let Some((wi, _)) = self.weights_mut().and_then(|weights| weights.links_mut()) {
+            *wi += shift;
+        }
+    }
+    fn assign_wi(&mut self, wi: &Array2<usize>) {
+        if let Some((links, _)) = self.weights_mut().and_then(|weights| weights.links_mut()) {
+            *links = wi.clone();
+        }
+    }
+    fn assign_bi(&mut self, bi: &Array1<usize>) {
+        if let Some((_, links)) = self.weights_mut().and_then(|weights| weights.links_mut()) {
+            *links = bi.clone();
+        }
+    }
+    fn assign_grad_w(&mut self, grad: Array2<f64>) {
+        if let Some((grad_w, _)) = self.gradients_mut() {
+            *grad_w = grad;
+        }
+    }
+    fn assign_grad_b(&mut self, grad: Array1<f64>) {
+        if let Some((_, grad_b)) = self.gradients_mut() {
+            *grad_b = grad;
+        }
+    }
+    // Rows of w gradients were accumulated for since the last shift, for
+    // layers that only shift those. Trainers reducing replica gradients into
+    // a network hand it the replicas' rows through touch().
+    fn touched(&self) -> Option<BTreeSet<usize>> {
+        None
+    }
+    fn touch(&mut self, _rows: &BTreeSet<usize>) {}
+    // Values learned in training without gradients, like BatchNorm's running
+    // mean and variance. Trainers training replicas merge them back.
+    fn statistics(&self) -> Option<(Array1<f64>, Array1<f64>)> {
+        None
+    }
+    fn set_statistics(
+        &mut self,
+        _statistics: &(Array1<f64>, Array1<f64>),
+    ) -> Result<(), ManifoldError> {
+        Ok(())
+    }
+    fn gradient_bindings(&self) -> Option<(Array2<usize>, Array1<usize>)> {
+        self.weights()
+            .and_then(|weights| weights.links())
+            .map(|(wi, bi)| (wi.clone(), bi.clone()))
+    }
+    fn regularize(&mut self, regularization: Regularization) {
+        if let Some(weights) = self.weights_mut() {
+            weights.regularize(regularization);
+        }
+    }
+    fn penalize(&mut self, substrate: &Substrate) {
+        if let Some(weights) = self.weights_mut() {
+            weights.penalize(substrate);
+        }
+    }
+    fn initialize(&mut self, substrate: &Substrate, init: Init) -> Result<(), ManifoldError> {
+        self.weights_mut()
+            .map_or(Ok(()), |weights| weights.initialize(substrate, init))
+    }
+    fn set_training(&mut self, training: bool) {
+        if let Some(weights) = self.weights_mut() {
+            weights.mode_mut().set_training(training);
+        }
+    }
+    fn seed(&mut self, seed: u64) {
+        if let Some(weights) = self.weights_mut() {
+            weights.mode_mut().seed(seed);
+        }
+    }
+    fn kind(&self) -> Layers;
+    fn describe(&self) -> LayerSummary;
+}
+
+/// Boxed copies and serialized bytes of any layer that is Clone and
+/// Serialize, which every layer is.
+pub trait LayerBox {
+    fn clone_box(&self) -> Box<dyn Layer>;
+    fn dump(&self) -> Result<Vec<u8>, Box<dyn Error>>;
+}
+
+impl<T: Layer + Clone + Serialize + 'static> LayerBox for T {
+    fn clone_box(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+
+    fn dump(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+}
+
+impl Clone for Box<dyn Layer> {
+    fn clone(&self) -> Box<dyn Layer> {
+        self.clone_box()
+    }
 }
 
-pub trait Layer {
-    fn forward(&mut self, x: Array3<f64>) -> Array3<f64>;
-    fn backward(&mut self, grad_output: Array3<f64>) -> Array3<f64>;
-    fn gradients(&self) -> (Array2<f64>, Array1<f64>);
-    fn gather(&mut self, substrate: &Substrate);
-    fn shift_weights(&mut self, shift: &Array2<usize>);
-    fn assign_bi(&mut self, shift: &Array1<usize>);
-    fn assign_grad_w(&mut self, grad: Array2<f64>);
-    fn assign_grad_b(&mut self, grad: Array1<f64>);
-    fn gradient_bindings(&self) -> (Array2<usize>, Array1<usize>);
+/// Shape and footprint of one woven layer. Shapes are per sample.
+#[derive(Debug, Clone)]
+pub struct LayerSummary {
+    pub kind: Layers,
+    pub input: Vec<usize>,
+    pub output: Vec<usize>,
+    pub activation: Activations,
+    pub links: usize,
+    pub distinct: usize,
+    pub bytes: usize,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum Layers {
     Dense,
+    /// Over (length, channels) inputs.
+    Conv1D {
+        kernel: usize,
+        stride: usize,
+        padding: usize,
+        dilation: usize,
+    },
+    /// Over (height, width, channels) inputs, (rows, columns) per setting.
+    Conv2D {
+        kernel: (usize, usize),
+        stride: (usize, usize),
+        padding: (usize, usize),
+        dilation: (usize, usize),
+    },
+    /// Largest value per channel in each window of a (length, channels) input.
+    MaxPool1D {
+        pool: usize,
+        stride: usize,
+    },
+    MaxPool2D {
+        pool: (usize, usize),
+        stride: (usize, usize),
+    },
+    /// Mean per channel in each window of a (length, channels) input.
+    AvgPool1D {
+        pool: usize,
+        stride: usize,
+    },
+    AvgPool2D {
+        pool: (usize, usize),
+        stride: (usize, usize),
+    },
+    /// Mean per channel over every position.
+    GlobalAvgPool,
+    /// Recurrent layers over (length, features) inputs, returning every
+    /// hidden state or only the last one.
+    RNN {
+        return_sequences: bool,
+    },
+    GRU {
+        return_sequences: bool,
+    },
+    LSTM {
+        return_sequences: bool,
+    },
+    /// Multi-head self-attention over (length, features) inputs, `size`
+    /// being the width the heads split between them.
+    Attention {
+        heads: usize,
+    },
+    /// Transformer encoder block, `size` being its feed forward width.
+    Encoder {
+        heads: usize,
+    },
+    /// All positions and channels of a sample as one vector.
+    Flatten,
+    /// Normalize every feature over the batch, keeping running statistics
+    /// for inference, `momentum` being how much of them each batch keeps.
+    /// Gamma and beta are links when `linked`, plain floats otherwise.
+    BatchNorm {
+        momentum: f64,
+        linked: bool,
+    },
+    /// Normalize every position over its features.
+    LayerNorm {
+        linked: bool,
+    },
+    /// Integer ids below `vocabulary` looked up as vectors of `size`.
+    Embedding {
+        vocabulary: usize,
+    },
+    /// Zero a `rate` of activations while training, scaling the rest up.
+    Dropout {
+        rate: f64,
+    },
+    /// Dropout for SELU networks, keeping their mean and variance.
+    AlphaDropout {
+        rate: f64,
+    },
+    /// Zero a `rate` of channels of every sample while training.
+    SpatialDropout {
+        rate: f64,
+    },
 }
 
 impl Layers {
-    pub fn wake(
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layers::Dense => "Dense",
+            Layers::Conv1D { .. } => "Conv1D",
+            Layers::Conv2D { .. } => "Conv2D",
+            Layers::MaxPool1D { .. } => "MaxPool1D",
+            Layers::MaxPool2D { .. } => "MaxPool2D",
+            Layers::AvgPool1D { .. } => "AvgPool1D",
+            Layers::AvgPool2D { .. } => "AvgPool2D",
+            Layers::GlobalAvgPool => "GlobalAvgPool",
+            Layers::RNN { .. } => "RNN",
+            Layers::GRU { .. } => "GRU",
+            Layers::LSTM { .. } => "LSTM",
+            Layers::Attention { .. } => "Attention",
+            Layers::Encoder { .. } => "Encoder",
+            Layers::Flatten => "Flatten",
+            Layers::BatchNorm { .. } => "BatchNorm",
+            Layers::LayerNorm { .. } => "LayerNorm",
+            Layers::Embedding { .. } => "Embedding",
+            Layers::Dropout { .. } => "Dropout",
+            Layers::AlphaDropout { .. } => "AlphaDropout",
+            Layers::SpatialDropout { .. } => "SpatialDropout",
+        }
+    }
+
+    /// Whether `size` means anything for the layer. Pooling, Flatten,
+    /// normalization and dropout ignore it, along with the activation.
+    pub fn sized(&self) -> bool {
+        matches!(
+            self,
+            Layers::Dense
+                | Layers::Conv1D { .. }
+                | Layers::Conv2D { .. }
+                | Layers::RNN { .. }
+                | Layers::GRU { .. }
+                | Layers::LSTM { .. }
+                | Layers::Attention { .. }
+                | Layers::Encoder { .. }
+                | Layers::Embedding { .. }
+        )
+    }
+
+    /// Conv1D with unit stride and dilation and no padding.
+    pub fn conv1d(kernel: usize) -> Layers {
+        Layers::Conv1D {
+            kernel,
+            stride: 1,
+            padding: 0,
+            dilation: 1,
+        }
+    }
+
+    /// Conv2D with unit stride and dilation and no padding.
+    pub fn conv2d(kernel: (usize, usize)) -> Layers {
+        Layers::Conv2D {
+            kernel,
+            stride: (1, 1),
+            padding: (0, 0),
+            dilation: (1, 1),
+        }
+    }
+
+    /// Non overlapping pooling windows of `pool` positions.
+    pub fn max_pool1d(pool: usize) -> Layers {
+        Layers::MaxPool1D { pool, stride: pool }
+    }
+
+    pub fn max_pool2d(pool: (usize, usize)) -> Layers {
+        Layers::MaxPool2D { pool, stride: pool }
+    }
+
+    pub fn avg_pool1d(pool: usize) -> Layers {
+        Layers::AvgPool1D { pool, stride: pool }
+    }
+
+    pub fn avg_pool2d(pool: (usize, usize)) -> Layers {
+        Layers::AvgPool2D { pool, stride: pool }
+    }
+
+    /// BatchNorm with linked gamma and beta, keeping 0.9 of its running
+    /// statistics per batch.
+    pub fn batch_norm() -> Layers {
+        Layers::BatchNorm {
+            momentum: 0.9,
+            linked: true,
+        }
+    }
+
+    pub fn layer_norm() -> Layers {
+        Layers::LayerNorm { linked: true }
+    }
+
+    /// Build a layer of `size` units (filters for convolutions) over a per
+    /// sample input shape, returning it with its per sample output shape.
+    /// Dense applies to the last axis and keeps the rest.
+    pub fn wake<S: Storage>(
         layer: Layers,
         pool_size: usize,
-        x_shape: (usize, usize, usize),
-        w_shape: (usize, usize),
-        b_shape: usize,
+        input: &[usize],
+        size: usize,
         activation: Activations,
-    ) -> Box<dyn Layer> {
+    ) -> Result<(Box<dyn Layer>, Vec<usize>), ManifoldError> {
+        match layer {
+            Layers::Dense => {
+                let (features, rest) = input
+                    .split_last()
+                    .ok_or_else(|| ManifoldError::EmptyLayer("Input".to_string()))?;
+                let positions = rest.iter().product();
+                let dense = Dense::<S>::new(
+                    pool_size,
+                    (1, positions, *features),
+                    (*features, size),
+                    size,
+                    activation,
+                );
+
+                let mut output = rest.to_vec();
+                output.push(size);
+                Ok((Box::new(dense), output))
+            }
+            Layers::Conv1D { .. } | Layers::Conv2D { .. } => {
+                let conv = Conv::<S>::new(layer, pool_size, input, size, activation)?;
+                let output = conv.output_shape();
+                Ok((Box::new(conv), output))
+            }
+            Layers::MaxPool1D { .. }
+            | Layers::MaxPool2D { .. }
+            | Layers::AvgPool1D { .. }
+            | Layers::AvgPool2D { .. } => {
+                let pool = Pool::new(layer, input)?;
+                let output = pool.output_shape();
+                Ok((Box::new(pool), output))
+            }
+            Layers::RNN { .. } | Layers::GRU { .. } | Layers::LSTM { .. } => {
+                let recurrent = Recurrent::<S>::new(layer, pool_size, input, size, activation)?;
+                let output = recurrent.output_shape();
+                Ok((Box::new(recurrent), output))
+            }
+            Layers::Attention { .. } => {
+                let attention = Attention::<S>::new(layer, pool_size, input, size)?;
+                let output = attention.output_shape();
+                Ok((Box::new(attention), output))
+            }
+            Layers::Encoder { .. } => {
+                let encoder = Encoder::<S>::new(layer, pool_size, input, size, activation)?;
+                let output = encoder.output_shape();
+                Ok((Box::new(encoder), output))
+            }
+            Layers::Embedding { .. } => {
+                let embedding = Embedding::<S>::new(layer, pool_size, input, size)?;
+                let output = embedding.output_shape();
+                Ok((Box::new(embedding), output))
+            }
+            Layers::GlobalAvgPool => {
+                let pool = GlobalAvgPool::new(input)?;
+                let output = pool.output_shape();
+                Ok((Box::new(pool), output))
+            }
+            Layers::BatchNorm { linked, .. } | Layers::LayerNorm { linked } => {
+                let norm: Box<dyn Layer> = match linked {
+                    true => Box::new(Norm::<S>::new(layer, pool_size, input)?),
+                    false => Box::new(Norm::<Isolated>::new(layer, pool_size, input)?),
+                };
+                Ok((norm, input.to_vec()))
+            }
+            Layers::Dropout { .. }
+            | Layers::AlphaDropout { .. }
+            | Layers::SpatialDropout { .. } => {
+                let dropout = Dropout::new(layer, input)?;
+                let output = dropout.output_shape();
+                Ok((Box::new(dropout), output))
+            }
+            Layers::Flatten => {
+                let flatten = Flatten::new(input);
+                let output = flatten.output_shape();
+                Ok((Box::new(flatten), output))
+            }
+        }
+    }
+
+    pub fn load<S: Storage>(
+        layer: Layers,
+        serialized: &[u8],
+    ) -> Result<Box<dyn Layer>, ManifoldError> {
         match layer {
-            Layers::Dense => Box::new(Dense::new(pool_size, x_shape, w_shape, b_shape, activation)),
+            Layers::Dense => Ok(Box::new(bincode::deserialize::<Dense<S>>(serialized)?)),
+            Layers::Conv1D { .. } | Layers::Conv2D { .. } => {
+                Ok(Box::new(bincode::deserialize::<Conv<S>>(serialized)?))
+            }
+            Layers::MaxPool1D { .. }
+            | Layers::MaxPool2D { .. }
+            | Layers::AvgPool1D { .. }
+            | Layers::AvgPool2D { .. } => Ok(Box::new(bincode::deserialize::<Pool>(serialized)?)),
+            Layers::RNN { .. } | Layers::GRU { .. } | Layers::LSTM { .. } => {
+                Ok(Box::new(bincode::deserialize::<Recurrent<S>>(serialized)?))
+            }
+            Layers::Attention { .. } => {
+                Ok(Box::new(bincode::deserialize::<Attention<S>>(serialized)?))
+            }
+            Layers::Encoder { .. } => Ok(Box::new(bincode::deserialize::<Encoder<S>>(serialized)?)),
+            Layers::Embedding { .. } => {
+                Ok(Box::new(bincode::deserialize::<Embedding<S>>(serialized)?))
+            }
+            Layers::GlobalAvgPool => {
+                Ok(Box::new(bincode::deserialize::<GlobalAvgPool>(serialized)?))
+            }
+            Layers::Flatten => Ok(Box::new(bincode::deserialize::<Flatten>(serialized)?)),
+            Layers::BatchNorm { linked, .. } | Layers::LayerNorm { linked } => match linked {
+                true => Ok(Box::new(bincode::deserialize::<Norm<S>>(serialized)?)),
+                false => Ok(Box::new(bincode::deserialize::<Norm<Isolated>>(
+                    serialized,
+                )?)),
+            },
+            Layers::Dropout { .. }
+            | Layers::AlphaDropout { .. }
+            | Layers::SpatialDropout { .. } => {
+                Ok(Box::new(bincode::deserialize::<Dropout>(serialized)?))
+            }
         }
     }
 }
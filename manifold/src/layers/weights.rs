@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::ops::Range;
+
+use ndarray::{s, Array1, Array2, Array3};
+use ndarray_rand::rand_distr::Normal;
+use ndarray_rand::RandomExt;
+
+use serde::{self, Deserialize, Serialize};
+
+use crate::error::ManifoldError;
+use crate::substrate::Substrate;
+
+use super::storage::{Init, Linked, Storage};
+use super::{Mode, Regularization};
+
+/// One (w, b) pair of a layer with its gradients, kept by `S`, along with
+/// the regularization and mode every layer with weights carries. Layers
+/// hand it out through `Layer::weights`, which gives them every weight
+/// method of `Layer` for free.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(bound = "")]
+pub struct Weights<S: Storage = Linked> {
+    pub links: S,
+    pub w: Array2<f64>,
+    pub b: Array1<f64>,
+    pub grad_w: Array2<f64>,
+    pub grad_b: Array1<f64>,
+    pub regularization: Regularization,
+    pub mode: Mode,
+}
+
+impl<S: Storage> Weights<S> {
+    pub fn new(pool_size: usize, w_shape: (usize, usize), b_shape: usize) -> Weights<S> {
+        let (links, w, b) = S::new(pool_size, w_shape, b_shape);
+
+        Weights {
+            links,
+            w,
+            b,
+            grad_w: Array2::zeros(w_shape),
+            grad_b: Array1::zeros(b_shape),
+            regularization: Regularization::default(),
+            mode: Mode::default(),
+        }
+    }
+
+    /// Accumulate gradients summed over `batch` samples as their mean,
+    /// stored as descent directions.
+    pub fn descend(&mut self, grad_w: &Array2<f64>, grad_b: &Array1<f64>, batch: usize) {
+        let batch = batch.max(1) as f64;
+        self.grad_w.scaled_add(-1. / batch, grad_w);
+        self.grad_b.scaled_add(-1. / batch, grad_b);
+    }
+
+    /// Restart `w` one block of columns at a time, each drawn by `init` with
+    /// its own (fan_in, fan_out). Columns outside every block are gains set
+    /// to one, biases start at zero. `Uniform` leaves everything as it is.
+    pub fn initialize_blocks(
+        &mut self,
+        substrate: &Substrate,
+        init: Init,
+        blocks: &[(Range<usize>, usize, usize)],
+    ) -> Result<(), ManifoldError> {
+        if init == Init::Uniform {
+            return Ok(());
+        }
+
+        let mut w = Array2::ones(self.w.dim());
+        for (columns, fan_in, fan_out) in blocks.iter() {
+            if let Some(normal) = init
+                .deviation(*fan_in, *fan_out)
+                .and_then(|deviation| Normal::new(0., deviation).ok())
+            {
+                let mut block = w.slice_mut(s![.., columns.clone()]);
+                block.assign(&Array2::random(block.dim(), normal));
+            }
+        }
+
+        self.links.assign(
+            substrate,
+            (w, Array1::zeros(self.b.len())),
+            &mut self.w,
+            &mut self.b,
+        )
+    }
+
+    pub fn dropout_mask(&mut self, shape: (usize, usize, usize)) -> Option<Array3<f64>> {
+        self.regularization.dropout_mask(shape, &mut self.mode)
+    }
+}
+
+/// The weight methods of `Layer`, implemented once for `Weights`.
+pub trait Weighted: Send + Sync {
+    fn values(&self) -> (&Array2<f64>, &Array1<f64>);
+    fn values_mut(&mut self) -> (&mut Array2<f64>, &mut Array1<f64>);
+    fn gradients(&self) -> (Array2<f64>, Array1<f64>);
+    fn gradients_mut(&mut self) -> (&mut Array2<f64>, &mut Array1<f64>);
+    fn gather(&mut self, substrate: &Substrate) -> Result<(), ManifoldError>;
+    fn shift(&mut self, substrate: &Substrate, learning_rate: f64) -> Result<(), ManifoldError>;
+    fn initialize(&mut self, substrate: &Substrate, init: Init) -> Result<(), ManifoldError>;
+    fn links_mut(&mut self) -> Option<(&mut Array2<usize>, &mut Array1<usize>)>;
+    fn links(&self) -> Option<(&Array2<usize>, &Array1<usize>)>;
+    fn regularize(&mut self, regularization: Regularization);
+    fn penalize(&mut self, substrate: &Substrate);
+    fn mode_mut(&mut self) -> &mut Mode;
+    /// Links with how many of them are distinct, and bytes held by the
+    /// weights, gradients and links.
+    fn footprint(&self) -> (usize, usize, usize);
+}
+
+impl<S: Storage> Weighted for Weights<S> {
+    fn values(&self) -> (&Array2<f64>, &Array1<f64>) {
+        (&self.w, &self.b)
+    }
+
+    fn values_mut(&mut self) -> (&mut Array2<f64>, &mut Array1<f64>) {
+        (&mut self.w, &mut self.b)
+    }
+
+    fn gradients(&self) -> (Array2<f64>, Array1<f64>) {
+        (self.grad_w.clone(), self.grad_b.clone())
+    }
+
+    fn gradients_mut(&mut self) -> (&mut Array2<f64>, &mut Array1<f64>) {
+        (&mut self.grad_w, &mut self.grad_b)
+    }
+
+    fn gather(&mut self, substrate: &Substrate) -> Result<(), ManifoldError> {
+        self.links.gather(substrate, &mut self.w, &mut self.b)
+    }
+
+    fn shift(&mut self, substrate: &Substrate, learning_rate: f64) -> Result<(), ManifoldError> {
+        self.links.shift(
+            substrate,
+            &mut self.w,
+            &mut self.b,
+            &mut self.grad_w,
+            &mut self.grad_b,
+            learning_rate,
+        )
+    }
+
+    fn initialize(&mut self, substrate: &Substrate, init: Init) -> Result<(), ManifoldError> {
+        self.links
+            .initialize(substrate, init, &mut self.w, &mut self.b)
+    }
+
+    fn links_mut(&mut self) -> Option<(&mut Array2<usize>, &mut Array1<usize>)> {
+        self.links.links_mut()
+    }
+
+    fn links(&self) -> Option<(&Array2<usize>, &Array1<usize>)> {
+        self.links.links()
+    }
+
+    fn regularize(&mut self, regularization: Regularization) {
+        self.regularization = regularization;
+    }
+
+    fn penalize(&mut self, substrate: &Substrate) {
+        if let Some(penalty) = self.regularization.penalty {
+            self.links
+                .penalize(penalty, &mut self.grad_w, &self.w, substrate);
+        }
+    }
+
+    fn mode_mut(&mut self) -> &mut Mode {
+        &mut self.mode
+    }
+
+    fn footprint(&self) -> (usize, usize, usize) {
+        let (links, distinct) = match self.links.links() {
+            Some((wi, bi)) => (
+                wi.len() + bi.len(),
+                wi.iter().chain(bi.iter()).collect::<HashSet<_>>().len(),
+            ),
+            None => (0, 0),
+        };
+        let values = self.w.len() + self.b.len() + self.grad_w.len() + self.grad_b.len();
+
+        (
+            links,
+            distinct,
+            links * size_of::<usize>() + values * size_of::<f64>(),
+        )
+    }
+}
@@ -1,16 +1,20 @@
 mod activation;
+mod error;
 pub mod f;
 pub mod layers;
 mod loss;
 pub mod manifold;
+mod metric;
 pub mod neat;
 pub mod optimizers;
 pub mod substrate;
 pub mod util;
 
 pub use activation::Activations;
-pub use loss::Losses;
+pub use error::ManifoldError;
+pub use loss::{Head, Losses, MultiTask};
 pub use manifold as nn;
+pub use metric::{ConfusionMatrix, Metric, Metrics};
 pub use neat::Neat;
 pub use substrate::Substrate;
 
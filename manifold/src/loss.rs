@@ -1,13 +1,15 @@
 use std::fmt::Debug;
-use std::rc::Rc;
+use std::sync::Arc;
 
-use ndarray::{Array1, Array2, Axis};
+use ndarray::{concatenate, s, Array1, Array2, Axis};
 use ndarray_stats::QuantileExt;
 use serde::{Deserialize, Serialize};
 
-pub trait Loss {
-    fn a(&self, pred: Array2<f64>, target: Array2<f64>) -> Array1<f64>;
-    fn d(&self, pred: Array2<f64>, target: Array2<f64>) -> Array2<f64>;
+use crate::error::{expect_shape, ManifoldError};
+
+pub trait Loss: Send + Sync {
+    fn a(&self, pred: Array2<f64>, target: Array2<f64>) -> Result<Array1<f64>, ManifoldError>;
+    fn d(&self, pred: Array2<f64>, target: Array2<f64>) -> Result<Array2<f64>, ManifoldError>;
 }
 
 impl Debug for dyn Loss {
@@ -19,18 +21,14 @@ impl Debug for dyn Loss {
 pub struct MSE;
 
 impl MSE {
-    pub fn new() -> Rc<MSE> {
-        Rc::new(MSE)
+    pub fn new() -> Arc<MSE> {
+        Arc::new(MSE)
     }
 }
 
 impl Loss for MSE {
-    fn a(&self, pred: Array2<f64>, target: Array2<f64>) -> Array1<f64> {
-        assert_eq!(
-            pred.shape(),
-            target.shape(),
-            "Predictions and targets must have the same shape."
-        );
+    fn a(&self, pred: Array2<f64>, target: Array2<f64>) -> Result<Array1<f64>, ManifoldError> {
+        expect_shape(target.shape(), pred.shape())?;
 
         let features = pred.shape()[1];
 
@@ -38,23 +36,24 @@ impl Loss for MSE {
         let exp = diff.mapv_into(|x| x.powi(2));
         let sum = exp.sum_axis(Axis(1));
         let mse = sum.mapv_into(|x| x / features as f64);
-        mse
+        Ok(mse)
     }
 
-    fn d(&self, pred: Array2<f64>, target: Array2<f64>) -> Array2<f64> {
+    fn d(&self, pred: Array2<f64>, target: Array2<f64>) -> Result<Array2<f64>, ManifoldError> {
+        expect_shape(target.shape(), pred.shape())?;
         let features = pred.shape()[1];
 
         let diff = pred - target;
         let dx = diff.mapv_into(|x| (x * 2.) / features as f64);
-        dx
+        Ok(dx)
     }
 }
 
 pub struct SoftmaxCrossEntropy;
 
 impl SoftmaxCrossEntropy {
-    pub fn new() -> Rc<SoftmaxCrossEntropy> {
-        Rc::new(SoftmaxCrossEntropy)
+    pub fn new() -> Arc<SoftmaxCrossEntropy> {
+        Arc::new(SoftmaxCrossEntropy)
     }
 }
 
@@ -79,17 +78,19 @@ impl SoftmaxCrossEntropy {
 }
 
 impl Loss for SoftmaxCrossEntropy {
-    fn a(&self, pred: Array2<f64>, target: Array2<f64>) -> Array1<f64> {
+    fn a(&self, pred: Array2<f64>, target: Array2<f64>) -> Result<Array1<f64>, ManifoldError> {
+        expect_shape(target.shape(), pred.shape())?;
         let softmax_pred = self.softmax(pred);
 
         let log_pred = softmax_pred.map(|x| x.ln());
         let prod = log_pred * target;
-        prod.map_axis(Axis(1), |axis| -axis.sum())
+        Ok(prod.map_axis(Axis(1), |axis| -axis.sum()))
     }
 
-    fn d(&self, pred: Array2<f64>, target: Array2<f64>) -> Array2<f64> {
+    fn d(&self, pred: Array2<f64>, target: Array2<f64>) -> Result<Array2<f64>, ManifoldError> {
+        expect_shape(target.shape(), pred.shape())?;
         let softmax_pred = self.softmax(pred);
-        softmax_pred - target
+        Ok(softmax_pred - target)
     }
 }
 
@@ -100,10 +101,205 @@ pub enum Losses {
 }
 
 impl Losses {
-    pub fn wake(&self) -> Rc<dyn Loss> {
+    pub fn wake(&self) -> Arc<dyn Loss> {
         match self {
             Losses::MeanSquaredError => MSE::new(),
             Losses::SoftmaxCrossEntropy => SoftmaxCrossEntropy::new(),
         }
     }
 }
+
+/// One output of a multi-task model: how many columns of the prediction it
+/// owns, the loss scoring them and that loss's weight in the total.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Head {
+    pub size: usize,
+    pub loss: Losses,
+    pub weight: f64,
+}
+
+impl Head {
+    pub fn new(size: usize, loss: Losses, weight: f64) -> Head {
+        Head { size, loss, weight }
+    }
+}
+
+// A head's loss and weight with its columns of a prediction and target.
+type Task<'a> = (&'a Arc<dyn Loss>, f64, Array2<f64>, Array2<f64>);
+
+/// Heads laid side by side in one prediction, each scored by its own loss.
+/// The loss of a sample is the weighted sum over heads, and every head's
+/// columns get their own loss's gradient scaled by its weight.
+pub struct MultiTask {
+    heads: Vec<(usize, Arc<dyn Loss>, f64)>,
+}
+
+impl MultiTask {
+    pub fn new(heads: &[Head]) -> MultiTask {
+        MultiTask {
+            heads: heads
+                .iter()
+                .map(|head| (head.size, head.loss.wake(), head.weight))
+                .collect(),
+        }
+    }
+
+    /// Columns of every head, for predictions or targets alike.
+    pub fn split(&self, y: &Array2<f64>) -> Result<Vec<Array2<f64>>, ManifoldError> {
+        let width = self.heads.iter().map(|(size, _, _)| size).sum::<usize>();
+        expect_shape(&[y.nrows(), width], y.shape())?;
+
+        let mut start = 0;
+        Ok(self
+            .heads
+            .iter()
+            .map(|(size, _, _)| {
+                start += size;
+                y.slice(s![.., start - size..start]).to_owned()
+            })
+            .collect())
+    }
+
+    // Every head's loss with its weight and its columns of pred and target.
+    fn pair(
+        &self,
+        pred: &Array2<f64>,
+        target: &Array2<f64>,
+    ) -> Result<Vec<Task<'_>>, ManifoldError> {
+        expect_shape(target.shape(), pred.shape())?;
+
+        Ok(self
+            .heads
+            .iter()
+            .zip(self.split(pred)?.into_iter().zip(self.split(target)?))
+            .map(|((_, loss, weight), (pred, target))| (loss, *weight, pred, target))
+            .collect())
+    }
+
+    /// Unweighted mean loss of every head.
+    pub fn per_head(
+        &self,
+        pred: &Array2<f64>,
+        target: &Array2<f64>,
+    ) -> Result<Vec<f64>, ManifoldError> {
+        self.pair(pred, target)?
+            .into_iter()
+            .map(|(loss, _, pred, target)| Ok(loss.a(pred, target)?.mean().unwrap_or(0.)))
+            .collect()
+    }
+}
+
+impl Loss for MultiTask {
+    fn a(&self, pred: Array2<f64>, target: Array2<f64>) -> Result<Array1<f64>, ManifoldError> {
+        let mut total = Array1::zeros(pred.nrows());
+        for (loss, weight, pred, target) in self.pair(&pred, &target)? {
+            total.scaled_add(weight, &loss.a(pred, target)?);
+        }
+        Ok(total)
+    }
+
+    fn d(&self, pred: Array2<f64>, target: Array2<f64>) -> Result<Array2<f64>, ManifoldError> {
+        let grads = self
+            .pair(&pred, &target)?
+            .into_iter()
+            .map(|(loss, weight, pred, target)| Ok(loss.d(pred, target)? * weight))
+            .collect::<Result<Vec<_>, ManifoldError>>()?;
+        let views = grads.iter().map(|grad| grad.view()).collect::<Vec<_>>();
+
+        concatenate(Axis(1), &views).map_err(|_| ManifoldError::ShapeMismatch {
+            expected: pred.shape().to_vec(),
+            found: grads
+                .iter()
+                .flat_map(|grad| grad.shape().to_vec())
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array2};
+
+    use super::{Head, Loss, Losses, MultiTask};
+
+    fn task() -> MultiTask {
+        MultiTask::new(&[
+            Head::new(2, Losses::MeanSquaredError, 0.5),
+            Head::new(3, Losses::SoftmaxCrossEntropy, 2.),
+        ])
+    }
+
+    fn sample() -> (Array2<f64>, Array2<f64>) {
+        let pred = array![[0.5, -1., 1., 2., 0.], [2., 0., -1., 0., 3.]];
+        let target = array![[1., 0., 0., 1., 0.], [1., 1., 0., 0., 1.]];
+        (pred, target)
+    }
+
+    // Squared error over the first two columns and cross entropy over the
+    // softmax of the last three, row by row.
+    fn by_hand(pred: &Array2<f64>, target: &Array2<f64>) -> Vec<(f64, f64)> {
+        pred.rows()
+            .into_iter()
+            .zip(target.rows())
+            .map(|(p, t)| {
+                let mse = ((p[0] - t[0]).powi(2) + (p[1] - t[1]).powi(2)) / 2.;
+                let sum = (2..5).map(|c| p[c].exp()).sum::<f64>();
+                let ce = -(2..5).map(|c| t[c] * (p[c].exp() / sum).ln()).sum::<f64>();
+                (mse, ce)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn per_head_losses_match_each_loss_alone() {
+        let (pred, target) = sample();
+        let rows = by_hand(&pred, &target);
+
+        let per_head = task().per_head(&pred, &target).unwrap();
+        let mse = rows.iter().map(|r| r.0).sum::<f64>() / 2.;
+        let ce = rows.iter().map(|r| r.1).sum::<f64>() / 2.;
+
+        assert!((per_head[0] - mse).abs() < 1e-12);
+        assert!((per_head[1] - ce).abs() < 1e-12);
+    }
+
+    #[test]
+    fn total_loss_weighs_every_head() {
+        let (pred, target) = sample();
+        let rows = by_hand(&pred, &target);
+
+        let total = task().a(pred, target).unwrap();
+        for (total, (mse, ce)) in total.iter().zip(rows) {
+            assert!((total - (0.5 * mse + 2. * ce)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn gradient_weighs_every_head_in_its_own_columns() {
+        let (pred, target) = sample();
+        let d = task().d(pred.clone(), target.clone()).unwrap();
+
+        for r in 0..2 {
+            for c in 0..2 {
+                let mse = (pred[[r, c]] - target[[r, c]]) * 2. / 2.;
+                assert!((d[[r, c]] - 0.5 * mse).abs() < 1e-12);
+            }
+            let sum = (2..5).map(|c| pred[[r, c]].exp()).sum::<f64>();
+            for c in 2..5 {
+                let ce = pred[[r, c]].exp() / sum - target[[r, c]];
+                assert!((d[[r, c]] - 2. * ce).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn widths_other_than_the_heads_are_errors() {
+        let pred = Array2::zeros((2, 4));
+        let target = Array2::zeros((2, 4));
+
+        assert!(task().split(&pred).is_err());
+        assert!(task().per_head(&pred, &target).is_err());
+        assert!(task().a(pred.clone(), target.clone()).is_err());
+        assert!(task().d(pred, target).is_err());
+    }
+}
@@ -1,66 +1,95 @@
 use std::error::Error;
 use std::ops::Range;
-use std::rc::Rc;
 use std::sync::Arc;
 
-use ndarray::{Array1, Array2, Array3, Axis};
+use ndarray::{Array2, Array3};
 use rand::{thread_rng, Rng};
 
 use serde::{self, Deserialize, Serialize};
 
 use crate::activation::Activations;
-use crate::layers::types::Layer;
-use crate::layers::Dense;
-use crate::loss::{Loss, Losses};
+use crate::error::ManifoldError;
+use crate::layers::types::Layers;
+use crate::layers::{Init, Isolated, Linked, Penalty, Regularization, Storage};
+use crate::loss::{Head, Loss, Losses};
 use crate::substrate::Substrate;
 
-use super::types::{GradientRetention, Manifold};
+use super::sequential::Sequential;
+use super::summary::Summary;
+use super::types::{
+    GradientRetention, LayerBindings, LayerGradients, LayerRows, LayerStatistics, Manifold,
+};
 
 pub type LayerSchema = Vec<usize>;
-pub type Web = Vec<Dense>;
 
+pub type DNN = Feedforward<Linked>;
+pub type DNNIsolated = Feedforward<Isolated>;
+
+/// Dense layers of the sizes in `layers` sharing one hidden activation,
+/// woven into a Sequential.
 #[derive(Serialize, Deserialize, Clone)]
-pub struct DNN {
-    #[serde(skip)]
-    substrate: Arc<Substrate>,
-    d_in: usize,
-    d_out: usize,
-    web: Web,
+#[serde(bound = "")]
+pub struct Feedforward<S: Storage> {
+    net: Sequential<S>,
     hidden_activation: Activations,
-    verbose: bool,
-    gradient_retention: GradientRetention,
+    regularization: Regularization,
+    normalization: Option<Layers>,
+    init: Init,
     pub layers: LayerSchema,
     pub loss: Losses,
 }
 
 impl DNN {
     pub fn new(substrate: Arc<Substrate>, d_in: usize, d_out: usize, layers: Vec<usize>) -> DNN {
-        DNN {
-            substrate,
-            d_in,
-            d_out,
-            layers,
-            web: Web::new(),
+        Feedforward::build(substrate, d_in, d_out, layers)
+    }
+}
+
+impl DNNIsolated {
+    pub fn new(d_in: usize, d_out: usize, layers: Vec<usize>) -> DNNIsolated {
+        Feedforward::build(Arc::new(Substrate::blank()), d_in, d_out, layers)
+    }
+}
+
+impl<S: Storage> Feedforward<S> {
+    fn build(
+        substrate: Arc<Substrate>,
+        d_in: usize,
+        d_out: usize,
+        layers: Vec<usize>,
+    ) -> Feedforward<S> {
+        Feedforward {
+            net: Sequential::new(substrate, d_in, d_out),
             hidden_activation: Activations::Relu,
-            verbose: false,
+            regularization: Regularization::default(),
+            normalization: None,
+            init: Init::default(),
+            layers,
             loss: Losses::MeanSquaredError,
-            gradient_retention: GradientRetention::Zero,
         }
     }
 
-    pub fn dynamic(d_in: usize, d_out: usize, breadth: Range<usize>, depth: Range<usize>) -> DNN {
+    pub fn dynamic(
+        d_in: usize,
+        d_out: usize,
+        breadth: Range<usize>,
+        depth: Range<usize>,
+    ) -> Feedforward<S> {
         let mut rng = thread_rng();
         let depth = rng.gen_range(depth);
         let layers = (0..depth)
             .map(|_| rng.gen_range(breadth.clone()))
             .collect::<Vec<usize>>();
 
-        DNN::new(Arc::new(Substrate::blank()), d_in, d_out, layers)
+        Feedforward::build(Arc::new(Substrate::blank()), d_in, d_out, layers)
     }
 
-    pub fn set_substrate(&mut self, substrate: Arc<Substrate>) -> &mut Self {
-        self.substrate = substrate;
-        self
+    pub fn d_in(&self) -> usize {
+        self.net.d_in()
+    }
+
+    pub fn d_out(&self) -> usize {
+        self.net.d_out()
     }
 
     pub fn set_hidden_activation(&mut self, activation: Activations) -> &mut Self {
@@ -68,114 +97,221 @@ impl DNN {
         self
     }
 
+    /// Same as `Manifold::set_substrate`, for callers without the trait in
+    /// scope.
+    pub fn set_substrate(&mut self, substrate: Arc<Substrate>) -> &mut Self {
+        Manifold::set_substrate(self, substrate)
+    }
+
     pub fn set_loss(&mut self, loss: Losses) -> &mut Self {
         self.loss = loss;
         self
     }
 
+    /// Split the output into heads with losses and weights of their own,
+    /// their sizes adding up to `d_out`.
+    pub fn set_heads(&mut self, heads: Vec<Head>) -> &mut Self {
+        self.net.set_heads(heads);
+        self
+    }
+
     pub fn set_gradient_retention(&mut self, method: GradientRetention) -> &mut Self {
-        self.gradient_retention = method;
+        self.net.set_gradient_retention(method);
         self
     }
 
-    pub fn gather(&mut self) -> &mut Self {
-        for layer in self.web.iter_mut() {
-            layer.gather(&self.substrate);
-        }
+    pub fn set_penalty(&mut self, penalty: Penalty) -> &mut Self {
+        self.regularization.penalty = Some(penalty);
+        self
+    }
+
+    /// Dropout rate applied to every hidden layer's activations.
+    pub fn set_dropout(&mut self, rate: f64) -> &mut Self {
+        self.regularization.dropout = rate;
+        self
+    }
+
+    /// Normalize the output of every hidden layer, with `Layers::BatchNorm`
+    /// or `Layers::LayerNorm`.
+    pub fn set_normalization(&mut self, norm: Layers) -> &mut Self {
+        self.normalization = Some(norm);
+        self
+    }
+
+    /// Initialization of every dense layer, the output layer included.
+    pub fn set_initialization(&mut self, init: Init) -> &mut Self {
+        self.init = init;
         self
     }
 
+    /// Activation of the output layer, an Identity by default. With none
+    /// the last hidden layer, which must have `d_out` units, is the output.
+    pub fn set_output(&mut self, activation: Option<Activations>) -> &mut Self {
+        self.net.set_output(activation);
+        self
+    }
+
+    pub fn gather(&mut self) -> Result<&mut Self, ManifoldError> {
+        self.net.gather()?;
+        Ok(self)
+    }
+
+    pub fn summary(&self) -> Result<Summary, ManifoldError> {
+        self.net.summary()
+    }
+
     pub fn dump(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
         Ok(bincode::serialize(self)?)
     }
 
-    pub fn load(serialized: &Vec<u8>) -> Result<DNN, Box<dyn Error>> {
-        Ok(bincode::deserialize(serialized)?)
+    /// DNNs dumped before they were built on Sequential have another layout
+    /// and fail to load.
+    pub fn load(serialized: &[u8]) -> Result<Feedforward<S>, ManifoldError> {
+        let mut nn: Feedforward<S> = bincode::deserialize(serialized).map_err(|e| {
+            ManifoldError::Deserialization(format!(
+                "{}, DNNs dumped before they were built on Sequential cannot be loaded",
+                e
+            ))
+        })?;
+        nn.net.attune();
+        Ok(nn)
     }
 }
 
-impl Manifold for DNN {
-    fn weave(&mut self) -> &mut Self {
-        let mut x_shape = (1, 1, self.d_in);
-        let mut w_shape: (usize, usize);
-        let mut b_shape: usize;
-        let mut p_dim = self.d_in;
-
-        for layer_size in self.layers.iter() {
-            w_shape = (p_dim, *layer_size);
-            b_shape = w_shape.1;
-
-            self.web.push(Dense::new(
-                self.substrate.size,
-                x_shape,
-                w_shape,
-                b_shape,
-                self.hidden_activation,
-            ));
-            p_dim = *layer_size;
-            x_shape = (1, 1, w_shape.1);
-        }
+impl<S: Storage> Manifold for Feedforward<S> {
+    fn weave(&mut self) -> Result<&mut Self, ManifoldError> {
+        self.net.layers = self
+            .layers
+            .iter()
+            .flat_map(|size| {
+                let dense = (
+                    *size,
+                    self.hidden_activation,
+                    Layers::Dense,
+                    self.regularization,
+                    self.init,
+                );
+                let norm = self.normalization.map(|norm| {
+                    (
+                        0,
+                        Activations::Identity,
+                        norm,
+                        Regularization::default(),
+                        Init::default(),
+                    )
+                });
+                std::iter::once(dense).chain(norm)
+            })
+            .collect();
+        self.net.regularize_head(Regularization {
+            dropout: 0.,
+            ..self.regularization
+        });
+        self.net.initialize_head(self.init);
+        self.net.set_loss(self.loss);
 
-        let w_shape = (p_dim, self.d_out);
-        let b_shape = w_shape.1;
+        self.net.weave()?;
+        Ok(self)
+    }
 
-        self.web.push(Dense::new(
-            self.substrate.size,
-            x_shape,
-            w_shape,
-            b_shape,
-            Activations::Identity,
-        ));
-        self
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        self.net.forward(x)
     }
 
-    fn forward(&mut self, mut x: Array3<f64>) -> Array3<f64> {
-        for layer in self.web.iter_mut() {
-            x = layer.forward(x);
-        }
-        x
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        self.net.infer(x)
     }
 
-    fn backwards(
+    fn accumulate(
         &mut self,
         y_pred: Array2<f64>,
         y: Array2<f64>,
-        loss: Rc<dyn Loss>,
-        learning_rate: f64,
-    ) {
-        let grad_output_i = loss.d(y_pred, y);
-
-        let mut grad_output = grad_output_i.insert_axis(Axis(1));
-
-        for layer in self.web.iter_mut().rev() {
-            grad_output = layer.backward(grad_output);
-
-            let grad_b_dim = layer.grad_b.raw_dim();
-            let grad_w_dim = layer.grad_w.raw_dim();
-
-            let mut b_grad_reshaped = layer.grad_b.to_owned().insert_axis(Axis(1));
-            let mut b_link_reshaped = layer.bi.to_owned().insert_axis(Axis(1));
-
-            self.substrate
-                .highspeed(&mut layer.grad_w, &mut layer.wi, learning_rate);
-            self.substrate
-                .highspeed(&mut b_grad_reshaped, &mut b_link_reshaped, learning_rate);
-
-            layer.assign_bi(&b_link_reshaped.remove_axis(Axis(1)));
-            layer.assign_grad_b(b_grad_reshaped.remove_axis(Axis(1)));
-            layer.gather(&self.substrate);
-
-            match self.gradient_retention {
-                GradientRetention::Zero => {
-                    layer.assign_grad_b(Array1::zeros(grad_b_dim));
-                    layer.assign_grad_w(Array2::zeros(grad_w_dim));
-                }
-                GradientRetention::Roll => (),
-            }
-        }
+        loss: Arc<dyn Loss>,
+    ) -> Result<(), ManifoldError> {
+        self.net.accumulate(y_pred, y, loss)
+    }
+
+    fn gradients_mut(&mut self) -> Vec<LayerGradients<'_>> {
+        self.net.gradients_mut()
+    }
+
+    fn shift(&mut self, learning_rate: f64) -> Result<(), ManifoldError> {
+        self.net.shift(learning_rate)
+    }
+
+    fn bindings(&self) -> Vec<LayerBindings> {
+        self.net.bindings()
+    }
+
+    fn bind(&mut self, bindings: Vec<LayerBindings>) -> Result<(), ManifoldError> {
+        self.net.bind(bindings)
+    }
+
+    fn touched(&self) -> Vec<LayerRows> {
+        self.net.touched()
+    }
+
+    fn touch(&mut self, rows: Vec<LayerRows>) {
+        self.net.touch(rows)
+    }
+
+    fn statistics(&self) -> Vec<LayerStatistics> {
+        self.net.statistics()
+    }
+
+    fn set_statistics(&mut self, statistics: Vec<LayerStatistics>) -> Result<(), ManifoldError> {
+        self.net.set_statistics(statistics)
     }
 
-    fn get_loss_fn(&mut self) -> Rc<dyn Loss> {
-        self.loss.wake()
+    // The public loss field may have changed since the network was woven.
+    fn get_loss_fn(&mut self) -> Arc<dyn Loss> {
+        self.net.set_loss(self.loss);
+        self.net.get_loss_fn()
+    }
+
+    fn get_substrate(&self) -> Option<Arc<Substrate>> {
+        self.net.get_substrate()
+    }
+
+    fn set_substrate(&mut self, substrate: Arc<Substrate>) -> &mut Self {
+        self.net.set_substrate(substrate);
+        self
+    }
+
+    fn set_training(&mut self, training: bool) -> &mut Self {
+        self.net.set_training(training);
+        self
+    }
+
+    fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.net.set_seed(seed);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::DNN;
+    use crate::loss::Losses;
+    use crate::manifold::types::Manifold;
+    use crate::substrate::Substrate;
+
+    // Setting the public field after weaving still picks the loss trained
+    // with, and it survives a dump and load.
+    #[test]
+    fn loss_field_picks_the_loss() {
+        let mut nn = DNN::new(Substrate::new(1000, -1.0..1.0).share(), 3, 2, vec![4]);
+        nn.weave().unwrap().gather().unwrap();
+        nn.loss = Losses::SoftmaxCrossEntropy;
+
+        let (pred, target) = (array![[2., 0.]], array![[1., 0.]]);
+        let a = nn.get_loss_fn().a(pred.clone(), target.clone()).unwrap();
+        let expected = (1. + (-2f64).exp()).ln();
+        assert!((a[0] - expected).abs() < 1e-12);
+
+        let loaded = DNN::load(&nn.dump().unwrap()).unwrap();
+        assert!(matches!(loaded.loss, Losses::SoftmaxCrossEntropy));
     }
 }
@@ -0,0 +1,549 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use ndarray::{concatenate, Array2, Array3, ArrayView2, ArrayView3, Axis};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{expect_shape, ManifoldError};
+use crate::loss::Loss;
+use crate::optimizers::{Hyper, MiniBatchGradientDescent};
+use crate::substrate::Substrate;
+
+use super::types::{LayerBindings, LayerGradients, LayerRows, LayerStatistics, Manifold};
+use super::{Composable, DNN};
+
+/// Any network that can sit in an ensemble.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum Member {
+    Dense(DNN),
+    Composable(Composable),
+}
+
+impl Member {
+    pub fn gather(&mut self) -> Result<&mut Self, ManifoldError> {
+        match self {
+            Member::Dense(nn) => {
+                nn.gather()?;
+            }
+            Member::Composable(nn) => {
+                nn.gather()?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Input and output channels.
+    pub fn dims(&self) -> (usize, usize) {
+        match self {
+            Member::Dense(nn) => (nn.d_in(), nn.d_out()),
+            Member::Composable(nn) => (nn.d_in(), nn.d_out()),
+        }
+    }
+}
+
+impl From<DNN> for Member {
+    fn from(nn: DNN) -> Member {
+        Member::Dense(nn)
+    }
+}
+
+impl From<Composable> for Member {
+    fn from(nn: Composable) -> Member {
+        Member::Composable(nn)
+    }
+}
+
+impl Manifold for Member {
+    fn weave(&mut self) -> Result<&mut Self, ManifoldError> {
+        match self {
+            Member::Dense(nn) => {
+                nn.weave()?;
+            }
+            Member::Composable(nn) => {
+                nn.weave()?;
+            }
+        }
+        Ok(self)
+    }
+
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        match self {
+            Member::Dense(nn) => nn.forward(x),
+            Member::Composable(nn) => nn.forward(x),
+        }
+    }
+
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        match self {
+            Member::Dense(nn) => nn.infer(x),
+            Member::Composable(nn) => nn.infer(x),
+        }
+    }
+
+    fn accumulate(
+        &mut self,
+        pred: Array2<f64>,
+        target: Array2<f64>,
+        loss: Arc<dyn Loss>,
+    ) -> Result<(), ManifoldError> {
+        match self {
+            Member::Dense(nn) => nn.accumulate(pred, target, loss),
+            Member::Composable(nn) => nn.accumulate(pred, target, loss),
+        }
+    }
+
+    fn gradients_mut(&mut self) -> Vec<LayerGradients<'_>> {
+        match self {
+            Member::Dense(nn) => nn.gradients_mut(),
+            Member::Composable(nn) => nn.gradients_mut(),
+        }
+    }
+
+    fn shift(&mut self, learning_rate: f64) -> Result<(), ManifoldError> {
+        match self {
+            Member::Dense(nn) => nn.shift(learning_rate),
+            Member::Composable(nn) => nn.shift(learning_rate),
+        }
+    }
+
+    fn bindings(&self) -> Vec<LayerBindings> {
+        match self {
+            Member::Dense(nn) => nn.bindings(),
+            Member::Composable(nn) => nn.bindings(),
+        }
+    }
+
+    fn bind(&mut self, bindings: Vec<LayerBindings>) -> Result<(), ManifoldError> {
+        match self {
+            Member::Dense(nn) => nn.bind(bindings),
+            Member::Composable(nn) => nn.bind(bindings),
+        }
+    }
+
+    fn touched(&self) -> Vec<LayerRows> {
+        match self {
+            Member::Dense(nn) => nn.touched(),
+            Member::Composable(nn) => nn.touched(),
+        }
+    }
+
+    fn touch(&mut self, rows: Vec<LayerRows>) {
+        match self {
+            Member::Dense(nn) => nn.touch(rows),
+            Member::Composable(nn) => nn.touch(rows),
+        }
+    }
+
+    fn statistics(&self) -> Vec<LayerStatistics> {
+        match self {
+            Member::Dense(nn) => nn.statistics(),
+            Member::Composable(nn) => nn.statistics(),
+        }
+    }
+
+    fn set_statistics(&mut self, statistics: Vec<LayerStatistics>) -> Result<(), ManifoldError> {
+        match self {
+            Member::Dense(nn) => nn.set_statistics(statistics),
+            Member::Composable(nn) => nn.set_statistics(statistics),
+        }
+    }
+
+    fn get_loss_fn(&mut self) -> Arc<dyn Loss> {
+        match self {
+            Member::Dense(nn) => nn.get_loss_fn(),
+            Member::Composable(nn) => nn.get_loss_fn(),
+        }
+    }
+
+    fn get_substrate(&self) -> Option<Arc<Substrate>> {
+        match self {
+            Member::Dense(nn) => nn.get_substrate(),
+            Member::Composable(nn) => nn.get_substrate(),
+        }
+    }
+
+    fn set_substrate(&mut self, substrate: Arc<Substrate>) -> &mut Self {
+        match self {
+            Member::Dense(nn) => {
+                nn.set_substrate(substrate);
+            }
+            Member::Composable(nn) => {
+                nn.set_substrate(substrate);
+            }
+        }
+        self
+    }
+
+    fn set_training(&mut self, training: bool) -> &mut Self {
+        match self {
+            Member::Dense(nn) => {
+                nn.set_training(training);
+            }
+            Member::Composable(nn) => {
+                nn.set_training(training);
+            }
+        }
+        self
+    }
+
+    fn set_seed(&mut self, seed: u64) -> &mut Self {
+        match self {
+            Member::Dense(nn) => {
+                nn.set_seed(seed);
+            }
+            Member::Composable(nn) => {
+                nn.set_seed(seed);
+            }
+        }
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum Combine {
+    /// Mean of member outputs.
+    Average,
+    /// Majority class over member outputs, returned one-hot. A single output
+    /// column votes 0 or 1 around 0.5.
+    Vote,
+    /// Member outputs concatenated and fed through a linear DNN trained after
+    /// the members.
+    Stack,
+}
+
+/// Many networks as index maps over one pool. Serializing an ensemble writes
+/// the pool once alongside every member.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Ensemble {
+    substrate: Arc<Substrate>,
+    d_in: usize,
+    d_out: usize,
+    combine: Combine,
+    members: Vec<Member>,
+    stacker: Option<DNN>,
+    #[serde(skip)]
+    rng: Option<ChaCha8Rng>,
+}
+
+impl Ensemble {
+    pub fn new(substrate: Arc<Substrate>, d_in: usize, d_out: usize) -> Ensemble {
+        Ensemble {
+            substrate,
+            d_in,
+            d_out,
+            combine: Combine::Average,
+            members: vec![],
+            stacker: None,
+            rng: None,
+        }
+    }
+
+    pub fn set_combine(&mut self, combine: Combine) -> &mut Self {
+        self.combine = combine;
+        self
+    }
+
+    /// Bootstrap samples are drawn from this seed.
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.rng = Some(ChaCha8Rng::seed_from_u64(seed));
+        self
+    }
+
+    /// Add an unwoven member, it is moved onto the ensemble's substrate.
+    pub fn member(&mut self, member: impl Into<Member>) -> &mut Self {
+        let mut member = member.into();
+        member.set_substrate(self.substrate.clone());
+        self.members.push(member);
+        self
+    }
+
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    pub fn members_mut(&mut self) -> &mut Vec<Member> {
+        &mut self.members
+    }
+
+    /// Members must all read d_in channels and write d_out.
+    pub fn weave(&mut self) -> Result<&mut Self, ManifoldError> {
+        for member in self.members.iter() {
+            let (d_in, d_out) = member.dims();
+            expect_shape(&[self.d_in, self.d_out], &[d_in, d_out])?;
+        }
+
+        for member in self.members.iter_mut() {
+            member.weave()?.gather()?;
+        }
+
+        if let Combine::Stack = self.combine {
+            let mut stacker = DNN::new(
+                self.substrate.clone(),
+                self.members.len() * self.d_out,
+                self.d_out,
+                vec![],
+            );
+            stacker.weave()?.gather()?;
+            self.stacker = Some(stacker);
+        }
+
+        Ok(self)
+    }
+
+    // Member outputs side by side, (samples, 1, members * d_out).
+    fn features(&self, x: &Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        let outputs = self
+            .members
+            .iter()
+            .map(|member| member.infer(x.to_owned()))
+            .collect::<Result<Vec<Array3<f64>>, ManifoldError>>()?;
+        let views = outputs.iter().map(|o| o.view()).collect::<Vec<_>>();
+
+        concatenate(Axis(2), &views).map_err(|_| ManifoldError::ShapeMismatch {
+            expected: vec![x.shape()[0], 1, self.d_out],
+            found: outputs.iter().flat_map(|o| o.shape().to_vec()).collect(),
+        })
+    }
+
+    pub fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        if self.members.is_empty() {
+            return Err(ManifoldError::Unwoven);
+        }
+
+        Ok(match self.combine {
+            Combine::Average => {
+                let mut sum = Array3::zeros((x.shape()[0], 1, self.d_out));
+                for member in self.members.iter() {
+                    let pred = member.infer(x.to_owned())?;
+                    expect_shape(sum.shape(), pred.shape())?;
+                    sum += &pred;
+                }
+                sum / self.members.len() as f64
+            }
+            Combine::Vote => {
+                let classes = self.d_out.max(2);
+                let mut votes = Array2::<f64>::zeros((x.shape()[0], classes));
+
+                for member in self.members.iter() {
+                    let pred = member.infer(x.to_owned())?.remove_axis(Axis(1));
+                    expect_shape(&[x.shape()[0], self.d_out], pred.shape())?;
+                    for (i, row) in pred.rows().into_iter().enumerate() {
+                        let class = match self.d_out {
+                            1 => (row[0] >= 0.5) as usize,
+                            _ => crate::f::argmax(&row.to_vec()),
+                        };
+                        votes[[i, class]] += 1.;
+                    }
+                }
+
+                let mut out = Array2::zeros((x.shape()[0], self.d_out));
+                for (i, row) in votes.rows().into_iter().enumerate() {
+                    let class = crate::f::argmax(&row.to_vec());
+                    match self.d_out {
+                        1 => out[[i, 0]] = class as f64,
+                        _ => out[[i, class]] = 1.,
+                    }
+                }
+                out.insert_axis(Axis(1))
+            }
+            Combine::Stack => {
+                let features = self.features(&x)?;
+                self.stacker
+                    .as_ref()
+                    .ok_or(ManifoldError::Unwoven)?
+                    .infer(features)?
+            }
+        })
+    }
+
+    /// Rows of x are samples of a single position.
+    pub fn predict(&self, x: ArrayView2<f64>) -> Result<Array2<f64>, ManifoldError> {
+        self.predict_tensor(x.insert_axis(Axis(1)))
+    }
+
+    /// Samples of (positions, channels), each brought down to one output
+    /// position by the members.
+    pub fn predict_tensor(&self, x: ArrayView3<f64>) -> Result<Array2<f64>, ManifoldError> {
+        let y = self.infer(x.to_owned())?;
+        let (batch, _, channels) = y.dim();
+        expect_shape(&[batch, 1, channels], y.shape())?;
+
+        Ok(y.remove_axis(Axis(1)))
+    }
+
+    /// Bagging, every member trains on its own bootstrap sample of x and y.
+    /// A stacked ensemble then fits its combiner on the members' outputs.
+    pub fn train(
+        &mut self,
+        x: &Array3<f64>,
+        y: &Array3<f64>,
+        hyper: Hyper,
+    ) -> Result<&mut Self, ManifoldError> {
+        let mut rng = self.rng.take().unwrap_or_else(ChaCha8Rng::from_entropy);
+        let n = x.shape()[0];
+
+        for member in self.members.iter_mut() {
+            let indices = (0..n).map(|_| rng.gen_range(0..n)).collect::<Vec<usize>>();
+            let bag_x = x.select(Axis(0), &indices);
+            let bag_y = y.select(Axis(0), &indices);
+
+            MiniBatchGradientDescent::new(member)
+                .override_hyper(hyper.clone())
+                .set_seed(rng.gen())
+                .train(&bag_x, &bag_y)?;
+        }
+
+        if let Combine::Stack = self.combine {
+            let features = self.features(x)?;
+            let stacker = self.stacker.as_mut().ok_or(ManifoldError::Unwoven)?;
+
+            MiniBatchGradientDescent::new(stacker)
+                .override_hyper(hyper)
+                .set_seed(rng.gen())
+                .train(&features, y)?;
+        }
+
+        self.rng = Some(rng);
+        Ok(self)
+    }
+
+    pub fn get_substrate(&self) -> Arc<Substrate> {
+        self.substrate.clone()
+    }
+
+    pub fn dump(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    /// Members come back linked to the single deserialized pool.
+    pub fn load(serialized: &[u8]) -> Result<Ensemble, ManifoldError> {
+        let mut ensemble: Ensemble = bincode::deserialize(serialized)?;
+        let substrate = ensemble.substrate.clone();
+
+        for member in ensemble.members.iter_mut() {
+            member.set_substrate(substrate.clone()).gather()?;
+        }
+
+        if let Some(stacker) = ensemble.stacker.as_mut() {
+            stacker.set_substrate(substrate).gather()?;
+        }
+
+        Ok(ensemble)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array2, Array3, Axis};
+    use ndarray_rand::rand_distr::StandardNormal;
+    use ndarray_rand::RandomExt;
+
+    use super::{Combine, Ensemble};
+    use crate::error::ManifoldError;
+    use crate::manifold::types::Manifold;
+    use crate::manifold::DNN;
+    use crate::substrate::Substrate;
+
+    fn ensemble(combine: Combine, d_out: usize) -> Ensemble {
+        let substrate = Substrate::new(10000, -1.0..1.0).share();
+        let mut ensemble = Ensemble::new(substrate.clone(), 4, d_out);
+        ensemble.set_combine(combine);
+        for _ in 0..3 {
+            ensemble.member(DNN::new(substrate.clone(), 4, d_out, vec![6]));
+        }
+        ensemble.weave().unwrap();
+        ensemble
+    }
+
+    fn outputs(ensemble: &Ensemble, x: &Array3<f64>) -> Vec<Array2<f64>> {
+        ensemble
+            .members()
+            .iter()
+            .map(|m| m.infer(x.clone()).unwrap().remove_axis(Axis(1)))
+            .collect()
+    }
+
+    #[test]
+    fn average_is_the_mean_of_member_outputs() {
+        let ensemble = ensemble(Combine::Average, 2);
+        let x = Array3::random((5, 1, 4), StandardNormal);
+
+        let outputs = outputs(&ensemble, &x);
+        let mean = (&outputs[0] + &outputs[1] + &outputs[2]) / 3.;
+        let pred = ensemble.infer(x).unwrap().remove_axis(Axis(1));
+
+        assert!((pred - mean).iter().all(|d| d.abs() < 1e-12));
+    }
+
+    #[test]
+    fn vote_returns_the_majority_class() {
+        let ensemble = ensemble(Combine::Vote, 3);
+        let x = Array3::random((20, 1, 4), StandardNormal);
+
+        let outputs = outputs(&ensemble, &x);
+        let pred = ensemble.infer(x).unwrap().remove_axis(Axis(1));
+
+        for (i, row) in pred.rows().into_iter().enumerate() {
+            assert_eq!(row.sum(), 1.);
+            let class = crate::f::argmax(&row.to_vec());
+            let votes = |c: usize| {
+                outputs
+                    .iter()
+                    .filter(|o| crate::f::argmax(&o.row(i).to_vec()) == c)
+                    .count()
+            };
+            assert!((0..3).all(|c| votes(class) >= votes(c)));
+        }
+    }
+
+    #[test]
+    fn vote_thresholds_a_single_column() {
+        let ensemble = ensemble(Combine::Vote, 1);
+        let x = Array3::random((20, 1, 4), StandardNormal);
+
+        let outputs = outputs(&ensemble, &x);
+        let pred = ensemble.infer(x).unwrap().remove_axis(Axis(1));
+
+        for (i, p) in pred.column(0).iter().enumerate() {
+            let positive = outputs.iter().filter(|o| o[[i, 0]] >= 0.5).count();
+            assert_eq!(*p, (positive >= 2) as usize as f64);
+        }
+    }
+
+    #[test]
+    fn stack_feeds_member_outputs_to_the_combiner() {
+        let ensemble = ensemble(Combine::Stack, 2);
+        let x = Array3::random((5, 1, 4), StandardNormal);
+
+        let outputs = outputs(&ensemble, &x);
+        let features = ensemble.features(&x).unwrap().remove_axis(Axis(1));
+        assert_eq!(features.dim(), (5, 6));
+        for (m, output) in outputs.iter().enumerate() {
+            assert_eq!(features.slice(ndarray::s![.., 2 * m..2 * m + 2]), output);
+        }
+
+        let stacked = ensemble
+            .stacker
+            .as_ref()
+            .unwrap()
+            .infer(features.insert_axis(Axis(1)))
+            .unwrap();
+        assert_eq!(ensemble.infer(x).unwrap(), stacked);
+    }
+
+    #[test]
+    fn members_of_another_shape_are_rejected() {
+        let substrate = Substrate::new(10000, -1.0..1.0).share();
+        let mut ensemble = Ensemble::new(substrate.clone(), 4, 2);
+        ensemble
+            .member(DNN::new(substrate.clone(), 4, 2, vec![6]))
+            .member(DNN::new(substrate, 4, 3, vec![6]));
+
+        assert!(matches!(
+            ensemble.weave(),
+            Err(ManifoldError::ShapeMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,799 @@
+use std::error::Error;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use ndarray::{concatenate, s, Array2, Array3, Axis};
+
+use serde::{self, Deserialize, Serialize};
+
+use crate::activation::Activations;
+use crate::error::{expect_definition, expect_shape, reshape, ManifoldError};
+use crate::layers::types::Layers;
+use crate::layers::{Init, Isolated, Linked, Penalty, Regularization, Storage};
+use crate::loss::{Head, Loss, Losses, MultiTask};
+use crate::substrate::Substrate;
+
+use super::sequential::{frozen_web, LayerDefinition, Web};
+use super::summary::Summary;
+use super::types::{
+    GradientRetention, LayerBindings, LayerGradients, LayerRows, LayerStatistics, Manifold,
+};
+
+/// Layers wired as a directed acyclic graph, their weights kept by `S`.
+pub type ComposableGraph = Graph<Linked>;
+pub type ComposableGraphIsolated = Graph<Isolated>;
+
+/// Index of a node, as returned by the builder methods of `Graph`.
+pub type NodeId = usize;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Node {
+    /// Per sample input shape, channels last.
+    Input(Vec<usize>),
+    Layer {
+        from: NodeId,
+        definition: LayerDefinition,
+    },
+    /// Elementwise sum of nodes of the same shape, for residual connections.
+    Add(Vec<NodeId>),
+    /// Nodes joined along their last axis, the leading axes being equal.
+    Concat(Vec<NodeId>),
+}
+
+impl Node {
+    fn sources(&self) -> Vec<NodeId> {
+        match self {
+            Node::Input(_) => vec![],
+            Node::Layer { from, .. } => vec![*from],
+            Node::Add(from) | Node::Concat(from) => from.clone(),
+        }
+    }
+}
+
+/// Nodes can only read nodes added before them, so the order they are added
+/// in is an order they can be run in, and its reverse one for backprop.
+///
+/// Several inputs arrive packed into one tensor, in the order they were
+/// added (see `Graph::pack`). Heads are Dense layers, with an identity
+/// activation unless set otherwise, whose outputs are joined along the last axis in the order
+/// they were added, so predictions and targets hold every head's columns
+/// side by side (see `Graph::unpack`). Each head is scored by its own loss
+/// and weight, see `Graph::set_head_loss`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(bound = "")]
+pub struct Graph<S: Storage> {
+    #[serde(skip)]
+    substrate: Arc<Substrate>,
+    nodes: Vec<Node>,
+    heads: Vec<NodeId>,
+    // Loss and weight of every head, the graph's loss when left unset.
+    tasks: Vec<(Option<Losses>, f64)>,
+    #[serde(
+        serialize_with = "frozen_web::serialize",
+        deserialize_with = "frozen_web::deserialize::<_, S>"
+    )]
+    web: Web,
+    // Per woven node, its per sample shape and the web index of its layer.
+    shapes: Vec<Vec<usize>>,
+    slots: Vec<Option<usize>>,
+    gradient_retention: GradientRetention,
+    pub loss: Losses,
+    seed: Option<u64>,
+    inference: bool,
+    #[serde(skip)]
+    storage: PhantomData<S>,
+}
+
+fn reach(grads: &mut [Option<Array3<f64>>], ix: NodeId, grad: Array3<f64>) {
+    match &mut grads[ix] {
+        Some(sum) => *sum += &grad,
+        None => grads[ix] = Some(grad),
+    }
+}
+
+impl<S: Storage> Graph<S> {
+    pub fn new(substrate: Arc<Substrate>) -> Graph<S> {
+        Graph {
+            substrate,
+            nodes: Vec::new(),
+            heads: Vec::new(),
+            tasks: Vec::new(),
+            web: Web::new(),
+            shapes: Vec::new(),
+            slots: Vec::new(),
+            gradient_retention: GradientRetention::Zero,
+            loss: Losses::MeanSquaredError,
+            seed: None,
+            inference: false,
+            storage: PhantomData,
+        }
+    }
+
+    fn push(&mut self, node: Node) -> NodeId {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    pub fn input(&mut self, shape: Vec<usize>) -> NodeId {
+        self.push(Node::Input(shape))
+    }
+
+    pub fn layer(
+        &mut self,
+        from: NodeId,
+        size: usize,
+        activation: Activations,
+        layer: Layers,
+    ) -> NodeId {
+        let definition = (
+            size,
+            activation,
+            layer,
+            Regularization::default(),
+            Init::default(),
+        );
+        self.push(Node::Layer { from, definition })
+    }
+
+    /// A layer without weights, pooling, Flatten or dropout, or a
+    /// normalization.
+    pub fn weightless(&mut self, from: NodeId, layer: Layers) -> NodeId {
+        self.layer(from, 0, Activations::Identity, layer)
+    }
+
+    pub fn add(&mut self, from: &[NodeId]) -> NodeId {
+        self.push(Node::Add(from.to_vec()))
+    }
+
+    pub fn concat(&mut self, from: &[NodeId]) -> NodeId {
+        self.push(Node::Concat(from.to_vec()))
+    }
+
+    /// An output of `size` read from a node with one position per sample.
+    pub fn head(&mut self, from: NodeId, size: usize) -> NodeId {
+        let head = self.layer(from, size, Activations::Identity, Layers::Dense);
+        self.heads.push(head);
+        self.tasks.push((None, 1.));
+        head
+    }
+
+    /// Score a head with a loss of its own, weighted in the total. Heads
+    /// left alone use the graph's loss with a weight of one.
+    pub fn set_head_loss(&mut self, head: NodeId, loss: Losses, weight: f64) -> &mut Self {
+        if let Some(ix) = self.heads.iter().position(|h| *h == head) {
+            self.tasks[ix] = (Some(loss), weight);
+        }
+        self
+    }
+
+    /// Activation of a head, an Identity by default.
+    pub fn set_head_activation(&mut self, head: NodeId, activation: Activations) -> &mut Self {
+        if self.heads.contains(&head) {
+            if let Some(Node::Layer { definition, .. }) = self.nodes.get_mut(head) {
+                definition.1 = activation;
+            }
+        }
+        self
+    }
+
+    /// Every head with the loss and weight it is trained with.
+    pub fn tasks(&self) -> Vec<Head> {
+        self.head_sizes()
+            .into_iter()
+            .zip(self.tasks.iter())
+            .map(|(size, (loss, weight))| Head::new(size, loss.unwrap_or(self.loss), *weight))
+            .collect()
+    }
+
+    /// Initialize the weights of a layer node with `init`.
+    pub fn initialize(&mut self, node: NodeId, init: Init) -> &mut Self {
+        if let Some(Node::Layer { definition, .. }) = self.nodes.get_mut(node) {
+            definition.4 = init;
+        }
+        self
+    }
+
+    /// Penalize the links of a layer node.
+    pub fn penalize(&mut self, node: NodeId, penalty: Penalty) -> &mut Self {
+        if let Some(Node::Layer { definition, .. }) = self.nodes.get_mut(node) {
+            definition.3.penalty = Some(penalty);
+        }
+        self
+    }
+
+    /// Dropout on the activations of a layer node.
+    pub fn dropout(&mut self, node: NodeId, rate: f64) -> &mut Self {
+        if let Some(Node::Layer { definition, .. }) = self.nodes.get_mut(node) {
+            definition.3.dropout = rate;
+        }
+        self
+    }
+
+    /// Same as `Manifold::set_substrate`, for callers without the trait in
+    /// scope.
+    pub fn set_substrate(&mut self, substrate: Arc<Substrate>) -> &mut Self {
+        Manifold::set_substrate(self, substrate)
+    }
+
+    pub fn set_loss(&mut self, loss: Losses) -> &mut Self {
+        self.loss = loss;
+        self
+    }
+
+    pub fn set_gradient_retention(&mut self, method: GradientRetention) -> &mut Self {
+        self.gradient_retention = method;
+        self
+    }
+
+    pub fn gather(&mut self) -> Result<&mut Self, ManifoldError> {
+        for layer in self.web.iter_mut() {
+            layer.gather(&self.substrate)?;
+        }
+        Ok(self)
+    }
+
+    pub fn summary(&self) -> Result<Summary, ManifoldError> {
+        if self.web.is_empty() {
+            return Err(ManifoldError::Unwoven);
+        }
+        Ok(Summary::new(
+            self.web.iter().map(|layer| layer.as_ref()),
+            S::LINKED.then_some(self.substrate.as_ref()),
+        ))
+    }
+
+    pub fn dump(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    /// Layers come back in the network's mode, dropout streams picking up
+    /// where they were dumped.
+    pub fn load(serialized: &[u8]) -> Result<Graph<S>, ManifoldError> {
+        let mut nn = bincode::deserialize::<Graph<S>>(serialized)?;
+        nn.attune();
+        Ok(nn)
+    }
+
+    /// Pack one tensor per input, in the order the inputs were added, into
+    /// the single tensor forward and the trainers take.
+    pub fn pack(inputs: &[Array3<f64>]) -> Result<Array3<f64>, ManifoldError> {
+        let flat = inputs
+            .iter()
+            .map(|x| {
+                let (batch, positions, channels) = x.dim();
+                reshape(x.to_owned(), (batch, 1, positions * channels))
+            })
+            .collect::<Result<Vec<_>, ManifoldError>>()?;
+        let views = flat.iter().map(|x| x.view()).collect::<Vec<_>>();
+
+        concatenate(Axis(2), &views).map_err(|_| ManifoldError::ShapeMismatch {
+            expected: inputs.first().map_or(vec![], |x| x.shape()[..1].to_vec()),
+            found: inputs.iter().map(|x| x.shape()[0]).collect(),
+        })
+    }
+
+    /// Split predictions or targets into the columns of every head.
+    pub fn unpack(&self, y: &Array2<f64>) -> Result<Vec<Array2<f64>>, ManifoldError> {
+        let sizes = self.head_sizes();
+        expect_shape(&[sizes.iter().sum()], &y.shape()[1..])?;
+
+        let mut start = 0;
+        Ok(sizes
+            .iter()
+            .map(|size| {
+                start += size;
+                y.slice(s![.., start - size..start]).to_owned()
+            })
+            .collect())
+    }
+
+    fn head_sizes(&self) -> Vec<usize> {
+        self.heads
+            .iter()
+            .map(|head| match &self.nodes[*head] {
+                Node::Layer { definition, .. } => definition.0,
+                _ => 0,
+            })
+            .collect()
+    }
+
+    fn input_shapes(&self) -> Vec<&Vec<usize>> {
+        self.nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Input(shape) => Some(shape),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The packed tensor as one tensor per input. A lone input is taken as
+    // it comes, like Sequential does.
+    fn split(&self, x: Array3<f64>) -> Result<Vec<Array3<f64>>, ManifoldError> {
+        let shapes = self.input_shapes();
+        if shapes.len() == 1 {
+            return Ok(vec![x]);
+        }
+
+        let (batch, positions, channels) = x.dim();
+        let sizes = shapes
+            .iter()
+            .map(|shape| shape.iter().product::<usize>())
+            .collect::<Vec<usize>>();
+        expect_shape(&[sizes.iter().sum()], &[positions * channels])?;
+
+        let flat = reshape(x, (batch, positions * channels))?;
+        let mut start = 0;
+        shapes
+            .iter()
+            .zip(sizes.iter())
+            .map(|(shape, size)| {
+                start += size;
+                let channels = shape[shape.len() - 1];
+                reshape(
+                    flat.slice(s![.., start - size..start]).to_owned(),
+                    (batch, size / channels, channels),
+                )
+            })
+            .collect()
+    }
+
+    // Output of an Add or Concat node from the values of its sources.
+    fn merge(
+        &self,
+        node: &Node,
+        values: &[Option<Array3<f64>>],
+    ) -> Result<Array3<f64>, ManifoldError> {
+        let sources = node
+            .sources()
+            .iter()
+            .map(|from| values[*from].as_ref().ok_or(ManifoldError::Unwoven))
+            .collect::<Result<Vec<_>, ManifoldError>>()?;
+
+        match node {
+            Node::Add(_) => {
+                let mut sum = sources[0].to_owned();
+                for value in sources[1..].iter() {
+                    expect_shape(sum.shape(), value.shape())?;
+                    sum += *value;
+                }
+                Ok(sum)
+            }
+            _ => {
+                let views = sources.iter().map(|value| value.view()).collect::<Vec<_>>();
+                concatenate(Axis(2), &views).map_err(|_| ManifoldError::ShapeMismatch {
+                    expected: sources[0].shape().to_vec(),
+                    found: sources.iter().flat_map(|v| v.shape().to_vec()).collect(),
+                })
+            }
+        }
+    }
+
+    fn outputs(&self, values: &[Option<Array3<f64>>]) -> Result<Array3<f64>, ManifoldError> {
+        self.merge(&Node::Concat(self.heads.clone()), values)
+    }
+
+    // Bring every woven layer to the network's mode.
+    fn attune(&mut self) {
+        for layer in self.web.iter_mut() {
+            layer.set_training(!self.inference);
+        }
+    }
+
+    // Restart every layer's dropout stream from the network's seed.
+    fn reseed(&mut self) {
+        if let Some(seed) = self.seed {
+            for (ix, layer) in self.web.iter_mut().enumerate() {
+                layer.seed(seed.wrapping_add(ix as u64));
+            }
+        }
+    }
+
+    // Per sample shape of an Add or Concat node.
+    fn merged_shape(&self, ix: NodeId, shapes: &[Vec<usize>]) -> Result<Vec<usize>, ManifoldError> {
+        let node = &self.nodes[ix];
+        let sources = node.sources();
+        let first = shapes[sources[0]].clone();
+
+        match node {
+            Node::Add(_) => {
+                for from in sources[1..].iter() {
+                    expect_shape(&first, &shapes[*from])?;
+                }
+                Ok(first)
+            }
+            _ => {
+                let leading = &first[..first.len() - 1];
+                let mut channels = 0;
+                for from in sources.iter() {
+                    let (last, rest) = shapes[*from]
+                        .split_last()
+                        .ok_or_else(|| ManifoldError::EmptyLayer(format!("Node {}", from)))?;
+                    expect_shape(leading, rest)?;
+                    channels += last;
+                }
+
+                let mut shape = leading.to_vec();
+                shape.push(channels);
+                Ok(shape)
+            }
+        }
+    }
+}
+
+impl Graph<Isolated> {
+    pub fn isolated() -> Graph<Isolated> {
+        Graph::new(Arc::new(Substrate::blank()))
+    }
+}
+
+impl<S: Storage> Manifold for Graph<S> {
+    fn weave(&mut self) -> Result<&mut Self, ManifoldError> {
+        let inputs = self.input_shapes();
+        let sizes = self
+            .nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Layer { definition, .. } if definition.2.sized() => Some(definition.0),
+                _ => None,
+            })
+            .collect::<Vec<usize>>();
+        expect_definition(
+            S::LINKED.then_some(self.substrate.as_ref()),
+            inputs
+                .iter()
+                .map(|shape| shape.iter().product::<usize>())
+                .min()
+                .unwrap_or(0),
+            &sizes,
+            self.head_sizes().iter().sum(),
+        )?;
+
+        // Built aside so a failed weave leaves the network as it was.
+        let mut web = Web::new();
+        let mut shapes: Vec<Vec<usize>> = Vec::with_capacity(self.nodes.len());
+        let mut slots = Vec::with_capacity(self.nodes.len());
+
+        for (ix, node) in self.nodes.iter().enumerate() {
+            let sources = node.sources();
+            if let Some(from) = sources.iter().find(|from| **from >= ix) {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "Node {} reads node {}, which is not added before it",
+                    ix, from
+                )));
+            }
+            if sources.is_empty() && !matches!(node, Node::Input(_)) {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "Node {} joins no nodes",
+                    ix
+                )));
+            }
+
+            let (shape, slot) = match node {
+                Node::Input(shape) if shape.is_empty() || shape.contains(&0) => {
+                    return Err(ManifoldError::EmptyLayer(format!("Input {}", ix)))
+                }
+                Node::Input(shape) => (shape.clone(), None),
+                Node::Layer { from, definition } => {
+                    let (size, activation, layer, regularization, init) = definition;
+                    let (mut woken, output) = Layers::wake::<S>(
+                        *layer,
+                        self.substrate.size,
+                        &shapes[*from],
+                        *size,
+                        *activation,
+                    )?;
+                    woken.regularize(*regularization);
+                    woken.initialize(&self.substrate, *init)?;
+
+                    web.push(woken);
+                    (output, Some(web.len() - 1))
+                }
+                _ => (self.merged_shape(ix, &shapes)?, None),
+            };
+
+            shapes.push(shape);
+            slots.push(slot);
+        }
+
+        // Heads read one vector per sample.
+        for head in self.heads.iter() {
+            let shape = &shapes[*head];
+            if shape[..shape.len() - 1].iter().product::<usize>() != 1 {
+                return Err(ManifoldError::InvalidLayer(format!(
+                    "Head {} needs one position per sample, found {:?}",
+                    head, shape
+                )));
+            }
+        }
+
+        self.web = web;
+        self.shapes = shapes;
+        self.slots = slots;
+        self.attune();
+        self.reseed();
+
+        Ok(self)
+    }
+
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        if self.web.is_empty() {
+            return Err(ManifoldError::Unwoven);
+        }
+
+        let mut inputs = self.split(x)?.into_iter();
+        let mut values: Vec<Option<Array3<f64>>> = vec![None; self.nodes.len()];
+
+        for ix in 0..self.nodes.len() {
+            let value = match (&self.nodes[ix], self.slots[ix]) {
+                (Node::Input(_), _) => inputs.next().ok_or(ManifoldError::Unwoven)?,
+                (Node::Layer { from, .. }, Some(slot)) => {
+                    let x = values[*from].clone().ok_or(ManifoldError::Unwoven)?;
+                    self.web[slot].forward(x)?
+                }
+                (node, _) => self.merge(node, &values)?,
+            };
+            values[ix] = Some(value);
+        }
+
+        self.outputs(&values)
+    }
+
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        if self.web.is_empty() {
+            return Err(ManifoldError::Unwoven);
+        }
+
+        let mut inputs = self.split(x)?.into_iter();
+        let mut values: Vec<Option<Array3<f64>>> = vec![None; self.nodes.len()];
+
+        for ix in 0..self.nodes.len() {
+            let value = match (&self.nodes[ix], self.slots[ix]) {
+                (Node::Input(_), _) => inputs.next().ok_or(ManifoldError::Unwoven)?,
+                (Node::Layer { from, .. }, Some(slot)) => {
+                    let x = values[*from].clone().ok_or(ManifoldError::Unwoven)?;
+                    self.web[slot].infer(x)?
+                }
+                (node, _) => self.merge(node, &values)?,
+            };
+            values[ix] = Some(value);
+        }
+
+        self.outputs(&values)
+    }
+
+    fn accumulate(
+        &mut self,
+        y_pred: Array2<f64>,
+        y: Array2<f64>,
+        loss: Arc<dyn Loss>,
+    ) -> Result<(), ManifoldError> {
+        if self.web.is_empty() {
+            return Err(ManifoldError::Unwoven);
+        }
+        expect_shape(y.shape(), y_pred.shape())?;
+
+        // Gradients reaching every node, summed over the nodes reading it.
+        let mut grads: Vec<Option<Array3<f64>>> = vec![None; self.nodes.len()];
+
+        let grad = self.unpack(&loss.d(y_pred, y)?)?;
+        for (head, grad) in self.heads.iter().zip(grad) {
+            reach(&mut grads, *head, grad.insert_axis(Axis(1)));
+        }
+
+        for ix in (0..self.nodes.len()).rev() {
+            let Some(grad) = grads[ix].take() else {
+                continue;
+            };
+
+            match (&self.nodes[ix], self.slots[ix]) {
+                (Node::Input(_), _) => (),
+                (Node::Layer { from, .. }, Some(slot)) => {
+                    let layer = &mut self.web[slot];
+                    let grad_input = layer.backward(grad)?;
+                    layer.penalize(&self.substrate);
+                    reach(&mut grads, *from, grad_input);
+                }
+                (Node::Add(from), _) => {
+                    for from in from.iter() {
+                        reach(&mut grads, *from, grad.clone());
+                    }
+                }
+                (Node::Concat(from), _) => {
+                    let mut start = 0;
+                    for from in from.iter() {
+                        let channels = self.shapes[*from][self.shapes[*from].len() - 1];
+                        start += channels;
+                        let part = grad.slice(s![.., .., start - channels..start]).to_owned();
+                        reach(&mut grads, *from, part);
+                    }
+                }
+                (Node::Layer { .. }, None) => return Err(ManifoldError::Unwoven),
+            }
+        }
+        Ok(())
+    }
+
+    fn gradients_mut(&mut self) -> Vec<LayerGradients<'_>> {
+        self.web
+            .iter_mut()
+            .filter_map(|layer| layer.gradients_mut())
+            .collect()
+    }
+
+    fn shift(&mut self, learning_rate: f64) -> Result<(), ManifoldError> {
+        for layer in self.web.iter_mut().rev() {
+            layer.shift(&self.substrate, learning_rate)?;
+
+            match self.gradient_retention {
+                GradientRetention::Zero => {
+                    if let Some((grad_w, grad_b)) = layer.gradients_mut() {
+                        grad_w.fill(0.);
+                        grad_b.fill(0.);
+                    }
+                }
+                GradientRetention::Roll => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn bindings(&self) -> Vec<LayerBindings> {
+        if !S::LINKED {
+            return vec![];
+        }
+
+        self.web
+            .iter()
+            .filter_map(|layer| layer.gradient_bindings())
+            .collect()
+    }
+
+    // Bindings only cover layers with links, in node order.
+    fn bind(&mut self, bindings: Vec<LayerBindings>) -> Result<(), ManifoldError> {
+        let linked = self
+            .web
+            .iter_mut()
+            .filter(|layer| layer.gradient_bindings().is_some());
+
+        for (layer, (wi, bi)) in linked.zip(bindings.iter()) {
+            layer.assign_wi(wi);
+            layer.assign_bi(bi);
+            layer.gather(&self.substrate)?;
+        }
+        Ok(())
+    }
+
+    fn touched(&self) -> Vec<LayerRows> {
+        self.web
+            .iter()
+            .filter_map(|layer| layer.touched())
+            .collect()
+    }
+
+    // Rows only cover layers that track them, in order.
+    fn touch(&mut self, rows: Vec<LayerRows>) {
+        let tracking = self
+            .web
+            .iter_mut()
+            .filter(|layer| layer.touched().is_some());
+
+        for (layer, rows) in tracking.zip(rows.iter()) {
+            layer.touch(rows);
+        }
+    }
+
+    fn statistics(&self) -> Vec<LayerStatistics> {
+        self.web
+            .iter()
+            .filter_map(|layer| layer.statistics())
+            .collect()
+    }
+
+    // Statistics only cover layers keeping them, in order.
+    fn set_statistics(&mut self, statistics: Vec<LayerStatistics>) -> Result<(), ManifoldError> {
+        let keeping = self
+            .web
+            .iter_mut()
+            .filter(|layer| layer.statistics().is_some());
+
+        for (layer, statistics) in keeping.zip(statistics.iter()) {
+            layer.set_statistics(statistics)?;
+        }
+        Ok(())
+    }
+
+    fn get_loss_fn(&mut self) -> Arc<dyn Loss> {
+        Arc::new(MultiTask::new(&self.tasks()))
+    }
+
+    fn get_substrate(&self) -> Option<Arc<Substrate>> {
+        S::LINKED.then(|| self.substrate.clone())
+    }
+
+    fn set_substrate(&mut self, substrate: Arc<Substrate>) -> &mut Self {
+        self.substrate = substrate;
+        self
+    }
+
+    fn set_training(&mut self, training: bool) -> &mut Self {
+        self.inference = !training;
+        for layer in self.web.iter_mut() {
+            layer.set_training(training);
+        }
+        self
+    }
+
+    fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self.reseed();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array2, Array3, Axis};
+    use ndarray_rand::rand_distr::StandardNormal;
+    use ndarray_rand::RandomExt;
+
+    use super::Graph;
+    use crate::activation::Activations;
+    use crate::layers::types::Layers;
+    use crate::layers::Isolated;
+    use crate::manifold::types::Manifold;
+
+    const EPSILON: f64 = 1e-6;
+
+    fn objective(graph: &mut Graph<Isolated>, x: &Array3<f64>, y: &Array2<f64>) -> f64 {
+        let pred = graph.forward(x.clone()).unwrap().remove_axis(Axis(1));
+        graph
+            .get_loss_fn()
+            .a(pred, y.clone())
+            .unwrap()
+            .mean()
+            .unwrap()
+    }
+
+    // The residual layer only hears from the loss through the add, the side
+    // branch only through the concat.
+    #[test]
+    fn residual_and_concat_gradients_match_finite_differences() {
+        let mut graph = Graph::isolated();
+        let input = graph.input(vec![4]);
+        let residual = graph.layer(input, 4, Activations::Tanh, Layers::Dense);
+        let added = graph.add(&[input, residual]);
+        let main = graph.layer(added, 3, Activations::Sigmoid, Layers::Dense);
+        let side = graph.layer(input, 2, Activations::Tanh, Layers::Dense);
+        let joined = graph.concat(&[main, side]);
+        graph.head(joined, 2);
+        graph.weave().unwrap();
+
+        let x = Array3::random((5, 1, 4), StandardNormal);
+        let y = Array2::random((5, 2), StandardNormal);
+        let pred = graph.forward(x.clone()).unwrap().remove_axis(Axis(1));
+        let loss = graph.get_loss_fn();
+        graph.accumulate(pred, y.clone(), loss).unwrap();
+
+        // Gradients are descent directions of the mean loss.
+        let mut worst: f64 = 0.;
+        for slot in 0..graph.web.len() {
+            let (grad_w, _) = graph.web[slot].gradients().unwrap();
+            let w = graph.web[slot].weights().unwrap().values().0.clone();
+
+            for (ix, value) in w.indexed_iter() {
+                let mut at = |delta: f64| {
+                    graph.web[slot].weights_mut().unwrap().values_mut().0[ix] = value + delta;
+                    objective(&mut graph, &x, &y)
+                };
+                let numeric = (at(EPSILON) - at(-EPSILON)) / (2. * EPSILON);
+                at(0.);
+
+                let analytic = -grad_w[ix];
+                worst = worst.max((analytic - numeric).abs() / numeric.abs().max(1e-3));
+            }
+        }
+
+        assert!(worst < 1e-5, "worst relative error {}", worst);
+    }
+}
@@ -1,8 +1,12 @@
-mod composable;
 mod dnn;
-mod dnn_iso;
+mod ensemble;
+mod graph;
+mod sequential;
+mod summary;
 pub mod types;
 
-pub use composable::Composable;
-pub use dnn::DNN;
-pub use dnn_iso::DNNIsolated;
+pub use dnn::{DNNIsolated, Feedforward, DNN};
+pub use ensemble::{Combine, Ensemble, Member};
+pub use graph::{ComposableGraph, ComposableGraphIsolated, Graph, Node, NodeId};
+pub use sequential::{Composable, ComposableIsolated, Sequential};
+pub use summary::Summary;
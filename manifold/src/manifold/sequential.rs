@@ -0,0 +1,499 @@
+use std::error::Error;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use ndarray::{Array2, Array3, Axis};
+
+use serde::{self, Deserialize, Serialize};
+
+use crate::activation::Activations;
+use crate::error::{expect_definition, expect_shape, ManifoldError};
+use crate::layers::types::{Layer, Layers};
+use crate::layers::{Init, Isolated, Linked, Penalty, Regularization, Storage};
+use crate::loss::{Head, Loss, Losses, MultiTask};
+use crate::substrate::Substrate;
+
+use super::summary::Summary;
+use super::types::{
+    GradientRetention, LayerBindings, LayerGradients, LayerRows, LayerStatistics, Manifold,
+};
+
+pub type LayerDefinition = (usize, Activations, Layers, Regularization, Init);
+pub type Web = Vec<Box<dyn Layer>>;
+
+/// Layers stacked one after another, their weights kept by `S`.
+pub type Composable = Sequential<Linked>;
+pub type ComposableIsolated = Sequential<Isolated>;
+
+// Layers are trait objects, so each one is stored alongside its kind and
+// rebuilt through Layers::load with the container's storage.
+pub(super) mod frozen_web {
+    use serde::de::Error as DeError;
+    use serde::ser::Error as SerError;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Web;
+    use crate::layers::types::Layers;
+    use crate::layers::Storage;
+
+    pub fn serialize<S: Serializer>(web: &Web, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut frozen: Vec<(Layers, Vec<u8>)> = Vec::with_capacity(web.len());
+        for layer in web.iter() {
+            frozen.push((layer.kind(), layer.dump().map_err(S::Error::custom)?));
+        }
+        frozen.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Storage>(
+        deserializer: D,
+    ) -> Result<Web, D::Error> {
+        let frozen = Vec::<(Layers, Vec<u8>)>::deserialize(deserializer)?;
+        let mut web = Web::with_capacity(frozen.len());
+        for (kind, serialized) in frozen.iter() {
+            web.push(Layers::load::<T>(*kind, serialized).map_err(D::Error::custom)?);
+        }
+        Ok(web)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(bound = "")]
+pub struct Sequential<S: Storage> {
+    #[serde(skip)]
+    substrate: Arc<Substrate>,
+    d_in: usize,
+    d_out: usize,
+    #[serde(
+        serialize_with = "frozen_web::serialize",
+        deserialize_with = "frozen_web::deserialize::<_, S>"
+    )]
+    web: Web,
+    pub(super) layers: Vec<LayerDefinition>,
+    input: Option<Vec<usize>>,
+    head: Regularization,
+    output: Option<Activations>,
+    head_init: Init,
+    verbose: bool,
+    gradient_retention: GradientRetention,
+    pub loss: Losses,
+    heads: Vec<Head>,
+    seed: Option<u64>,
+    inference: bool,
+    #[serde(skip)]
+    storage: PhantomData<S>,
+}
+
+impl<S: Storage> Sequential<S> {
+    pub fn new(substrate: Arc<Substrate>, d_in: usize, d_out: usize) -> Sequential<S> {
+        Sequential {
+            substrate,
+            d_in,
+            d_out,
+            layers: Vec::new(),
+            input: None,
+            head: Regularization::default(),
+            output: Some(Activations::Identity),
+            head_init: Init::default(),
+            web: Web::new(),
+            verbose: false,
+            loss: Losses::MeanSquaredError,
+            heads: Vec::new(),
+            gradient_retention: GradientRetention::Zero,
+            seed: None,
+            inference: false,
+            storage: PhantomData,
+        }
+    }
+
+    pub fn layer(&mut self, size: usize, activation: Activations, layer: Layers) -> &mut Self {
+        let ld: LayerDefinition = (
+            size,
+            activation,
+            layer,
+            Regularization::default(),
+            Init::default(),
+        );
+        self.layers.push(ld);
+        self
+    }
+
+    /// Add a layer sized by its input: pooling, Flatten or dropout, or a
+    /// BatchNorm or LayerNorm normalizing the most recently added layer.
+    pub fn weightless(&mut self, layer: Layers) -> &mut Self {
+        self.layer(0, Activations::Identity, layer)
+    }
+
+    /// Per sample input shape for spatial layers, channels last. Samples
+    /// still arrive as (batch, positions, channels) tensors, positions being
+    /// the product of the leading axes. Defaults to `[d_in]`.
+    pub fn set_input_shape(&mut self, shape: Vec<usize>) -> &mut Self {
+        self.input = Some(shape);
+        self
+    }
+
+    /// Channels of every input position.
+    pub fn d_in(&self) -> usize {
+        self.d_in
+    }
+
+    /// Channels of the output position.
+    pub fn d_out(&self) -> usize {
+        self.d_out
+    }
+
+    fn input_shape(&self) -> Vec<usize> {
+        match &self.input {
+            Some(shape) if !shape.is_empty() => shape.clone(),
+            _ => vec![self.d_in],
+        }
+    }
+
+    /// Penalize the links of the most recently added layer.
+    pub fn penalize(&mut self, penalty: Penalty) -> &mut Self {
+        if let Some(ld) = self.layers.last_mut() {
+            ld.3.penalty = Some(penalty);
+        }
+        self
+    }
+
+    /// Dropout on the activations of the most recently added layer.
+    pub fn dropout(&mut self, rate: f64) -> &mut Self {
+        if let Some(ld) = self.layers.last_mut() {
+            ld.3.dropout = rate;
+        }
+        self
+    }
+
+    /// Initialize the weights of the most recently added layer with `init`.
+    pub fn initialize(&mut self, init: Init) -> &mut Self {
+        if let Some(ld) = self.layers.last_mut() {
+            ld.4 = init;
+        }
+        self
+    }
+
+    /// Regularization of the output layer woven after the defined layers.
+    pub fn regularize_head(&mut self, regularization: Regularization) -> &mut Self {
+        self.head = regularization;
+        self
+    }
+
+    /// Initialization of the output layer woven after the defined layers.
+    pub fn initialize_head(&mut self, init: Init) -> &mut Self {
+        self.head_init = init;
+        self
+    }
+
+    /// Activation of the output layer woven after the defined layers, an
+    /// Identity by default. With none, no output layer is woven and the last
+    /// defined layer must give one position of `d_out` channels per sample.
+    pub fn set_output(&mut self, activation: Option<Activations>) -> &mut Self {
+        self.output = activation;
+        self
+    }
+
+    /// Same as `Manifold::set_substrate`, for callers without the trait in
+    /// scope.
+    pub fn set_substrate(&mut self, substrate: Arc<Substrate>) -> &mut Self {
+        Manifold::set_substrate(self, substrate)
+    }
+
+    pub fn set_loss(&mut self, loss: Losses) -> &mut Self {
+        self.loss = loss;
+        self
+    }
+
+    /// Split the output into heads with losses and weights of their own,
+    /// their sizes adding up to `d_out`. Replaces the single loss.
+    pub fn set_heads(&mut self, heads: Vec<Head>) -> &mut Self {
+        self.heads = heads;
+        self
+    }
+
+    pub fn set_gradient_retention(&mut self, method: GradientRetention) -> &mut Self {
+        self.gradient_retention = method;
+        self
+    }
+
+    // Bring every woven layer to the network's mode.
+    pub(super) fn attune(&mut self) {
+        for layer in self.web.iter_mut() {
+            layer.set_training(!self.inference);
+        }
+    }
+
+    // Restart every layer's dropout stream from the network's seed.
+    fn reseed(&mut self) {
+        if let Some(seed) = self.seed {
+            for (ix, layer) in self.web.iter_mut().enumerate() {
+                layer.seed(seed.wrapping_add(ix as u64));
+            }
+        }
+    }
+
+    pub fn gather(&mut self) -> Result<&mut Self, ManifoldError> {
+        for layer in self.web.iter_mut() {
+            layer.gather(&self.substrate)?;
+        }
+        Ok(self)
+    }
+
+    pub fn summary(&self) -> Result<Summary, ManifoldError> {
+        if self.web.is_empty() {
+            return Err(ManifoldError::Unwoven);
+        }
+        Ok(Summary::new(
+            self.web.iter().map(|layer| layer.as_ref()),
+            S::LINKED.then_some(self.substrate.as_ref()),
+        ))
+    }
+
+    pub fn dump(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    /// Layers come back in the network's mode, dropout streams picking up
+    /// where they were dumped.
+    pub fn load(serialized: &[u8]) -> Result<Sequential<S>, ManifoldError> {
+        let mut nn = bincode::deserialize::<Sequential<S>>(serialized)?;
+        nn.attune();
+        Ok(nn)
+    }
+}
+
+impl Sequential<Isolated> {
+    pub fn isolated(d_in: usize, d_out: usize) -> Sequential<Isolated> {
+        Sequential::new(Arc::new(Substrate::blank()), d_in, d_out)
+    }
+}
+
+impl<S: Storage> Manifold for Sequential<S> {
+    fn weave(&mut self) -> Result<&mut Self, ManifoldError> {
+        // Only some layers have units to check.
+        let sizes = self
+            .layers
+            .iter()
+            .map(|ld| if ld.2.sized() { ld.0 } else { 1 })
+            .collect::<Vec<usize>>();
+        expect_definition(
+            S::LINKED.then_some(self.substrate.as_ref()),
+            self.d_in,
+            &sizes,
+            self.d_out,
+        )?;
+
+        let mut shape = self.input_shape();
+        expect_shape(&[self.d_in], &shape[shape.len() - 1..])?;
+        if !self.heads.is_empty() {
+            let sizes = self.heads.iter().map(|head| head.size).sum();
+            expect_shape(&[self.d_out], &[sizes])?;
+        }
+
+        // Built aside so a failed weave leaves the network as it was.
+        let mut web = Web::new();
+
+        for layer_definition in self.layers.iter() {
+            let (size, activation, layer, regularization, init) = layer_definition;
+
+            let (mut woken, output) =
+                Layers::wake::<S>(*layer, self.substrate.size, &shape, *size, *activation)?;
+            woken.regularize(*regularization);
+            woken.initialize(&self.substrate, *init)?;
+
+            web.push(woken);
+            shape = output;
+        }
+
+        // The head reads one vector per sample, as does the loss without one.
+        if shape[..shape.len() - 1].iter().product::<usize>() != 1 {
+            return Err(ManifoldError::InvalidLayer(format!(
+                "Output layer needs one position per sample, found {:?}",
+                shape
+            )));
+        }
+
+        match self.output {
+            Some(activation) => {
+                let (mut output, _) = Layers::wake::<S>(
+                    Layers::Dense,
+                    self.substrate.size,
+                    &shape,
+                    self.d_out,
+                    activation,
+                )?;
+                output.regularize(self.head);
+                output.initialize(&self.substrate, self.head_init)?;
+                web.push(output);
+            }
+            None => expect_shape(&[self.d_out], &shape[shape.len() - 1..])?,
+        }
+
+        self.web = web;
+        self.attune();
+        self.reseed();
+
+        Ok(self)
+    }
+
+    fn forward(&mut self, mut x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        if self.web.is_empty() {
+            return Err(ManifoldError::Unwoven);
+        }
+
+        for layer in self.web.iter_mut() {
+            x = layer.forward(x)?;
+        }
+        Ok(x)
+    }
+
+    fn infer(&self, mut x: Array3<f64>) -> Result<Array3<f64>, ManifoldError> {
+        if self.web.is_empty() {
+            return Err(ManifoldError::Unwoven);
+        }
+
+        for layer in self.web.iter() {
+            x = layer.infer(x)?;
+        }
+        Ok(x)
+    }
+
+    fn accumulate(
+        &mut self,
+        y_pred: Array2<f64>,
+        y: Array2<f64>,
+        loss: Arc<dyn Loss>,
+    ) -> Result<(), ManifoldError> {
+        if self.web.is_empty() {
+            return Err(ManifoldError::Unwoven);
+        }
+        expect_shape(y.shape(), y_pred.shape())?;
+        let grad_output_i = loss.d(y_pred, y)?;
+
+        let mut grad_output = grad_output_i.insert_axis(Axis(1));
+
+        for layer in self.web.iter_mut().rev() {
+            grad_output = layer.backward(grad_output)?;
+            layer.penalize(&self.substrate);
+        }
+        Ok(())
+    }
+
+    fn gradients_mut(&mut self) -> Vec<LayerGradients<'_>> {
+        self.web
+            .iter_mut()
+            .filter_map(|layer| layer.gradients_mut())
+            .collect()
+    }
+
+    fn shift(&mut self, learning_rate: f64) -> Result<(), ManifoldError> {
+        for layer in self.web.iter_mut().rev() {
+            layer.shift(&self.substrate, learning_rate)?;
+
+            match self.gradient_retention {
+                GradientRetention::Zero => {
+                    if let Some((grad_w, grad_b)) = layer.gradients_mut() {
+                        grad_w.fill(0.);
+                        grad_b.fill(0.);
+                    }
+                }
+                GradientRetention::Roll => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn bindings(&self) -> Vec<LayerBindings> {
+        if !S::LINKED {
+            return vec![];
+        }
+
+        self.web
+            .iter()
+            .filter_map(|layer| layer.gradient_bindings())
+            .collect()
+    }
+
+    // Bindings only cover layers with links, in order.
+    fn bind(&mut self, bindings: Vec<LayerBindings>) -> Result<(), ManifoldError> {
+        let linked = self
+            .web
+            .iter_mut()
+            .filter(|layer| layer.gradient_bindings().is_some());
+
+        for (layer, (wi, bi)) in linked.zip(bindings.iter()) {
+            layer.assign_wi(wi);
+            layer.assign_bi(bi);
+            layer.gather(&self.substrate)?;
+        }
+        Ok(())
+    }
+
+    fn touched(&self) -> Vec<LayerRows> {
+        self.web
+            .iter()
+            .filter_map(|layer| layer.touched())
+            .collect()
+    }
+
+    // Rows only cover layers that track them, in order.
+    fn touch(&mut self, rows: Vec<LayerRows>) {
+        let tracking = self
+            .web
+            .iter_mut()
+            .filter(|layer| layer.touched().is_some());
+
+        for (layer, rows) in tracking.zip(rows.iter()) {
+            layer.touch(rows);
+        }
+    }
+
+    fn statistics(&self) -> Vec<LayerStatistics> {
+        self.web
+            .iter()
+            .filter_map(|layer| layer.statistics())
+            .collect()
+    }
+
+    // Statistics only cover layers keeping them, in order.
+    fn set_statistics(&mut self, statistics: Vec<LayerStatistics>) -> Result<(), ManifoldError> {
+        let keeping = self
+            .web
+            .iter_mut()
+            .filter(|layer| layer.statistics().is_some());
+
+        for (layer, statistics) in keeping.zip(statistics.iter()) {
+            layer.set_statistics(statistics)?;
+        }
+        Ok(())
+    }
+
+    fn get_loss_fn(&mut self) -> Arc<dyn Loss> {
+        match self.heads.is_empty() {
+            true => self.loss.wake(),
+            false => Arc::new(MultiTask::new(&self.heads)),
+        }
+    }
+
+    fn get_substrate(&self) -> Option<Arc<Substrate>> {
+        S::LINKED.then(|| self.substrate.clone())
+    }
+
+    fn set_substrate(&mut self, substrate: Arc<Substrate>) -> &mut Self {
+        self.substrate = substrate;
+        self
+    }
+
+    fn set_training(&mut self, training: bool) -> &mut Self {
+        self.inference = !training;
+        for layer in self.web.iter_mut() {
+            layer.set_training(training);
+        }
+        self
+    }
+
+    fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self.reseed();
+        self
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+
+use crate::layers::types::{Layer, LayerSummary};
+use crate::substrate::Substrate;
+
+/// Per layer shapes, activations and link usage of a woven network.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub layers: Vec<LayerSummary>,
+    pub links: usize,
+    /// Substrate indices used anywhere in the network. Layers can share
+    /// indices, so this is at most the sum of the per layer counts.
+    pub distinct: usize,
+    pub bytes: usize,
+    pub substrate_size: usize,
+    pub substrate_bytes: usize,
+}
+
+impl Summary {
+    pub(crate) fn new<'a>(
+        web: impl Iterator<Item = &'a dyn Layer>,
+        substrate: Option<&Substrate>,
+    ) -> Summary {
+        let mut layers = vec![];
+        let mut indices = HashSet::new();
+
+        for layer in web {
+            if let Some((wi, bi)) = layer.gradient_bindings() {
+                indices.extend(wi.iter().chain(bi.iter()).copied());
+            }
+            layers.push(layer.describe());
+        }
+
+        Summary {
+            links: layers.iter().map(|l| l.links).sum(),
+            distinct: indices.len(),
+            bytes: layers.iter().map(|l| l.bytes).sum(),
+            substrate_size: substrate.map_or(0, |s| s.size),
+            substrate_bytes: substrate.map_or(0, |s| s.bytes()),
+            layers,
+        }
+    }
+}
+
+fn bytes(n: usize) -> String {
+    match n {
+        n if n >= 1 << 20 => format!("{:.1} MiB", n as f64 / (1 << 20) as f64),
+        n if n >= 1 << 10 => format!("{:.1} KiB", n as f64 / (1 << 10) as f64),
+        n => format!("{} B", n),
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<6}{:<14}{:<12}{:<12}{:<12}{:>10}{:>10}{:>12}",
+            "Layer", "Kind", "Input", "Output", "Activation", "Links", "Distinct", "Memory"
+        )?;
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            writeln!(
+                f,
+                "{:<6}{:<14}{:<12}{:<12}{:<12}{:>10}{:>10}{:>12}",
+                i,
+                layer.kind.name(),
+                format!("{:?}", layer.input),
+                format!("{:?}", layer.output),
+                format!("{:?}", layer.activation),
+                layer.links,
+                layer.distinct,
+                bytes(layer.bytes)
+            )?;
+        }
+
+        if self.substrate_bytes == 0 {
+            return write!(f, "Memory: {} network, weights isolated", bytes(self.bytes));
+        }
+
+        writeln!(
+            f,
+            "Links: {} ({} distinct of {} in the substrate)",
+            self.links,
+            self.distinct,
+            self.substrate_size + 1
+        )?;
+        write!(
+            f,
+            "Memory: {} network, {} substrate",
+            bytes(self.bytes),
+            bytes(self.substrate_bytes)
+        )
+    }
+}
@@ -1,9 +1,14 @@
-use std::rc::Rc;
+use std::collections::BTreeSet;
+use std::sync::Arc;
 
-use ndarray::{Array2, Array3};
+use ndarray::{Array1, Array2, Array3, ArrayView2, ArrayView3, Axis};
 use serde::{Deserialize, Serialize};
 
+use crate::activation::{Activation, Softmax};
+use crate::error::{expect_shape, ManifoldError};
 use crate::loss::Loss;
+use crate::substrate::Substrate;
+use crate::util::tensor;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub enum GradientRetention {
@@ -11,15 +16,112 @@ pub enum GradientRetention {
     Zero,
 }
 
+pub type LayerGradients<'a> = (&'a mut Array2<f64>, &'a mut Array1<f64>);
+pub type LayerBindings = (Array2<usize>, Array1<usize>);
+pub type LayerRows = BTreeSet<usize>;
+pub type LayerStatistics = (Array1<f64>, Array1<f64>);
+
 pub trait Manifold {
-    fn weave(&mut self) -> &mut Self;
-    fn forward(&mut self, x: Array3<f64>) -> Array3<f64>;
+    fn weave(&mut self) -> Result<&mut Self, ManifoldError>;
+    fn forward(&mut self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError>;
+    fn infer(&self, x: Array3<f64>) -> Result<Array3<f64>, ManifoldError>;
+    fn accumulate(
+        &mut self,
+        pred: Array2<f64>,
+        target: Array2<f64>,
+        loss: Arc<dyn Loss>,
+    ) -> Result<(), ManifoldError>;
+    fn gradients_mut(&mut self) -> Vec<LayerGradients<'_>>;
+    fn shift(&mut self, learning_rate: f64) -> Result<(), ManifoldError>;
+    fn bindings(&self) -> Vec<LayerBindings>;
+    fn bind(&mut self, bindings: Vec<LayerBindings>) -> Result<(), ManifoldError>;
+    /// Rows each layer that shifts only some rows, like Embedding, has
+    /// gradients for since the last shift. Trainers reducing replica
+    /// gradients into the network pass them on with touch.
+    fn touched(&self) -> Vec<LayerRows>;
+    fn touch(&mut self, rows: Vec<LayerRows>);
+    /// Running statistics of every layer keeping some, like BatchNorm, for
+    /// trainers to merge back from replicas with set_statistics.
+    fn statistics(&self) -> Vec<LayerStatistics>;
+    fn set_statistics(&mut self, statistics: Vec<LayerStatistics>) -> Result<(), ManifoldError>;
+    fn get_loss_fn(&mut self) -> Arc<dyn Loss>;
+    fn get_substrate(&self) -> Option<Arc<Substrate>>;
+    fn set_substrate(&mut self, substrate: Arc<Substrate>) -> &mut Self;
+    /// Out of training forward drops nothing and BatchNorm reads its running
+    /// statistics, as infer does. Networks start out training.
+    fn set_training(&mut self, training: bool) -> &mut Self;
+    /// Dropout masks are drawn from this seed, every layer getting its own
+    /// stream of it.
+    fn set_seed(&mut self, seed: u64) -> &mut Self;
+
     fn backwards(
         &mut self,
         pred: Array2<f64>,
         target: Array2<f64>,
-        loss: Rc<dyn Loss>,
+        loss: Arc<dyn Loss>,
         learning_rate: f64,
-    );
-    fn get_loss_fn(&mut self) -> Rc<dyn Loss>;
+    ) -> Result<(), ManifoldError> {
+        self.accumulate(pred, target, loss)?;
+        self.shift(learning_rate)
+    }
+
+    /// Rows of x are samples of a single position. Unlike forward nothing is
+    /// cached and dropout is off, so a network can be shared between threads
+    /// for inference.
+    fn predict(&self, x: ArrayView2<f64>) -> Result<Array2<f64>, ManifoldError> {
+        self.predict_tensor(x.insert_axis(Axis(1)))
+    }
+
+    /// Samples of (positions, channels), for sequences and spatial inputs.
+    /// The network has to bring every sample down to one output position,
+    /// a shape error says otherwise.
+    fn predict_tensor(&self, x: ArrayView3<f64>) -> Result<Array2<f64>, ManifoldError> {
+        let y = self.infer(x.to_owned())?;
+        let (batch, _, channels) = y.dim();
+        expect_shape(&[batch, 1, channels], y.shape())?;
+
+        Ok(y.remove_axis(Axis(1)))
+    }
+
+    fn predict_batch(&self, x: Vec<Vec<f64>>) -> Result<Vec<Vec<f64>>, ManifoldError> {
+        if x.is_empty() {
+            return Ok(vec![]);
+        }
+
+        Ok(self
+            .predict_tensor(tensor(x)?.view())?
+            .rows()
+            .into_iter()
+            .map(|row| row.to_vec())
+            .collect())
+    }
+
+    /// Softmax over the outputs of a multi column head. A single column is
+    /// read as a binary score and clamped to 0..1.
+    fn predict_proba(&self, x: ArrayView2<f64>) -> Result<Array2<f64>, ManifoldError> {
+        let pred = self.predict(x)?;
+
+        Ok(match pred.ncols() {
+            1 => pred.mapv(|v| v.clamp(0., 1.)),
+            _ => Softmax.a(pred),
+        })
+    }
+
+    /// Argmax of a multi column head, or a single column thresholded at 0.5.
+    fn predict_class(&self, x: ArrayView2<f64>) -> Result<Vec<usize>, ManifoldError> {
+        let pred = self.predict(x)?;
+
+        Ok(match pred.ncols() {
+            1 => pred
+                .column(0)
+                .iter()
+                .map(|v| (*v >= 0.5) as usize)
+                .collect(),
+            _ => pred
+                .rows()
+                .into_iter()
+                .map(|row| crate::f::argmax(&row.to_vec()))
+                .collect(),
+        })
+    }
 }
@@ -0,0 +1,416 @@
+use std::fmt::Debug;
+
+use ndarray::{Array2, Axis, Zip};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{expect_shape, ManifoldError};
+
+pub trait Metric: Send + Sync {
+    fn name(&self) -> String;
+    fn score(&self, pred: &Array2<f64>, target: &Array2<f64>) -> Result<f64, ManifoldError>;
+}
+
+impl Debug for dyn Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Metric({})", self.name())
+    }
+}
+
+// Rows are samples. Several columns are read as one-hot/logits and reduced
+// with argmax, a single column is a binary score thresholded at 0.5.
+fn classes(x: &Array2<f64>) -> Vec<usize> {
+    if x.ncols() == 1 {
+        return x.column(0).iter().map(|v| (*v >= 0.5) as usize).collect();
+    }
+
+    x.rows()
+        .into_iter()
+        .map(|row| crate::f::argmax(&row.to_vec()))
+        .collect()
+}
+
+// Metrics reading classes only need a prediction for every target row.
+fn expect_rows(pred: &Array2<f64>, target: &Array2<f64>) -> Result<(), ManifoldError> {
+    expect_shape(&[target.nrows()], &[pred.nrows()])
+}
+
+fn class_count(target: &Array2<f64>) -> usize {
+    target.ncols().max(2)
+}
+
+pub struct ConfusionMatrix {
+    /// counts[[actual, predicted]]
+    pub counts: Array2<usize>,
+}
+
+impl ConfusionMatrix {
+    /// Predictions and targets must have the same shape.
+    pub fn new(pred: &Array2<f64>, target: &Array2<f64>) -> Result<ConfusionMatrix, ManifoldError> {
+        expect_shape(target.shape(), pred.shape())?;
+        let n = class_count(target);
+        let mut counts = Array2::zeros((n, n));
+
+        for (p, a) in classes(pred).into_iter().zip(classes(target)) {
+            counts[[a, p]] += 1;
+        }
+
+        Ok(ConfusionMatrix { counts })
+    }
+
+    pub fn precision(&self, class: usize) -> f64 {
+        let predicted = self.counts.column(class).sum();
+        if predicted == 0 {
+            return 0.;
+        }
+        self.counts[[class, class]] as f64 / predicted as f64
+    }
+
+    pub fn recall(&self, class: usize) -> f64 {
+        let actual = self.counts.row(class).sum();
+        if actual == 0 {
+            return 0.;
+        }
+        self.counts[[class, class]] as f64 / actual as f64
+    }
+
+    pub fn f1(&self, class: usize) -> f64 {
+        let (p, r) = (self.precision(class), self.recall(class));
+        if p + r == 0. {
+            return 0.;
+        }
+        2. * p * r / (p + r)
+    }
+
+    // Binary problems report on the positive class, others macro average
+    // over every class.
+    fn reduce(&self, per_class: impl Fn(usize) -> f64) -> f64 {
+        let n = self.counts.nrows();
+        if n == 2 {
+            return per_class(1);
+        }
+        (0..n).map(per_class).sum::<f64>() / n as f64
+    }
+}
+
+pub struct Accuracy;
+
+impl Metric for Accuracy {
+    fn name(&self) -> String {
+        "accuracy".to_string()
+    }
+
+    fn score(&self, pred: &Array2<f64>, target: &Array2<f64>) -> Result<f64, ManifoldError> {
+        expect_rows(pred, target)?;
+        Ok(crate::f::accuracy(&classes(pred), &classes(target)) / 100.)
+    }
+}
+
+pub struct TopK(pub usize);
+
+impl Metric for TopK {
+    fn name(&self) -> String {
+        format!("top_{}_accuracy", self.0)
+    }
+
+    // A single column is a binary score with two classes to rank, the
+    // thresholded class being first and the other second. A class beyond
+    // the predicted columns is never ranked.
+    fn score(&self, pred: &Array2<f64>, target: &Array2<f64>) -> Result<f64, ManifoldError> {
+        expect_rows(pred, target)?;
+        let actual = classes(target);
+        let hits = match pred.ncols() {
+            1 => classes(pred)
+                .iter()
+                .zip(actual.iter())
+                .filter(|(p, a)| p == a || self.0 >= 2)
+                .count(),
+            _ => pred
+                .rows()
+                .into_iter()
+                .zip(actual.iter())
+                .filter(|(row, a)| match row.get(**a) {
+                    Some(score) => row.iter().filter(|v| *v > score).count() < self.0,
+                    None => false,
+                })
+                .count(),
+        };
+
+        Ok(hits as f64 / actual.len().max(1) as f64)
+    }
+}
+
+pub struct Precision;
+
+impl Metric for Precision {
+    fn name(&self) -> String {
+        "precision".to_string()
+    }
+
+    fn score(&self, pred: &Array2<f64>, target: &Array2<f64>) -> Result<f64, ManifoldError> {
+        let cm = ConfusionMatrix::new(pred, target)?;
+        Ok(cm.reduce(|c| cm.precision(c)))
+    }
+}
+
+pub struct Recall;
+
+impl Metric for Recall {
+    fn name(&self) -> String {
+        "recall".to_string()
+    }
+
+    fn score(&self, pred: &Array2<f64>, target: &Array2<f64>) -> Result<f64, ManifoldError> {
+        let cm = ConfusionMatrix::new(pred, target)?;
+        Ok(cm.reduce(|c| cm.recall(c)))
+    }
+}
+
+pub struct F1;
+
+impl Metric for F1 {
+    fn name(&self) -> String {
+        "f1".to_string()
+    }
+
+    fn score(&self, pred: &Array2<f64>, target: &Array2<f64>) -> Result<f64, ManifoldError> {
+        let cm = ConfusionMatrix::new(pred, target)?;
+        Ok(cm.reduce(|c| cm.f1(c)))
+    }
+}
+
+pub struct RocAuc;
+
+impl RocAuc {
+    // Mann-Whitney U over scores, ties share their average rank.
+    fn auc(scores: &[f64], positive: &[bool]) -> Option<f64> {
+        let n_pos = positive.iter().filter(|p| **p).count();
+        let n_neg = positive.len() - n_pos;
+        if n_pos == 0 || n_neg == 0 {
+            return None;
+        }
+
+        let mut order = (0..scores.len()).collect::<Vec<usize>>();
+        order.sort_by(|a, b| scores[*a].total_cmp(&scores[*b]));
+
+        let mut rank_sum = 0.;
+        let mut i = 0;
+        while i < order.len() {
+            let mut j = i;
+            while j + 1 < order.len() && scores[order[j + 1]] == scores[order[i]] {
+                j += 1;
+            }
+
+            let rank = (i + j) as f64 / 2. + 1.;
+            for ix in order[i..=j].iter() {
+                if positive[*ix] {
+                    rank_sum += rank;
+                }
+            }
+            i = j + 1;
+        }
+
+        let u = rank_sum - (n_pos * (n_pos + 1)) as f64 / 2.;
+        Some(u / (n_pos * n_neg) as f64)
+    }
+}
+
+impl Metric for RocAuc {
+    fn name(&self) -> String {
+        "roc_auc".to_string()
+    }
+
+    // One-vs-rest per output column, macro averaged over the columns where
+    // both positives and negatives are present.
+    fn score(&self, pred: &Array2<f64>, target: &Array2<f64>) -> Result<f64, ManifoldError> {
+        expect_shape(target.shape(), pred.shape())?;
+        let aucs = (0..target.ncols())
+            .filter_map(|c| {
+                let scores = pred.column(c).to_vec();
+                let positive = target
+                    .column(c)
+                    .iter()
+                    .map(|v| *v >= 0.5)
+                    .collect::<Vec<_>>();
+                RocAuc::auc(&scores, &positive)
+            })
+            .collect::<Vec<f64>>();
+
+        if aucs.is_empty() {
+            return Ok(0.5);
+        }
+        Ok(aucs.iter().sum::<f64>() / aucs.len() as f64)
+    }
+}
+
+pub struct MeanAbsoluteError;
+
+impl Metric for MeanAbsoluteError {
+    fn name(&self) -> String {
+        "mae".to_string()
+    }
+
+    fn score(&self, pred: &Array2<f64>, target: &Array2<f64>) -> Result<f64, ManifoldError> {
+        expect_shape(target.shape(), pred.shape())?;
+        Ok((pred - target).mapv(f64::abs).mean().unwrap_or(0.))
+    }
+}
+
+pub struct RootMeanSquaredError;
+
+impl Metric for RootMeanSquaredError {
+    fn name(&self) -> String {
+        "rmse".to_string()
+    }
+
+    fn score(&self, pred: &Array2<f64>, target: &Array2<f64>) -> Result<f64, ManifoldError> {
+        expect_shape(target.shape(), pred.shape())?;
+        Ok((pred - target)
+            .mapv(|x| x.powi(2))
+            .mean()
+            .unwrap_or(0.)
+            .sqrt())
+    }
+}
+
+pub struct RSquared;
+
+impl Metric for RSquared {
+    fn name(&self) -> String {
+        "r2".to_string()
+    }
+
+    // Averaged over output columns.
+    fn score(&self, pred: &Array2<f64>, target: &Array2<f64>) -> Result<f64, ManifoldError> {
+        expect_shape(target.shape(), pred.shape())?;
+        let means = target.mean_axis(Axis(0));
+        let columns = target.ncols().max(1);
+        let mut total = 0.;
+
+        for c in 0..target.ncols() {
+            let mean = means.as_ref().map(|m| m[c]).unwrap_or(0.);
+            let mut ss_res = 0.;
+            let mut ss_tot = 0.;
+
+            Zip::from(pred.column(c))
+                .and(target.column(c))
+                .for_each(|p, t| {
+                    ss_res += (t - p).powi(2);
+                    ss_tot += (t - mean).powi(2);
+                });
+
+            total += if ss_tot == 0. {
+                0.
+            } else {
+                1. - ss_res / ss_tot
+            };
+        }
+
+        Ok(total / columns as f64)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum Metrics {
+    Accuracy,
+    TopK(usize),
+    Precision,
+    Recall,
+    F1,
+    RocAuc,
+    MeanAbsoluteError,
+    RootMeanSquaredError,
+    RSquared,
+}
+
+impl Metrics {
+    pub fn wake(&self) -> Box<dyn Metric> {
+        match self {
+            Metrics::Accuracy => Box::new(Accuracy),
+            Metrics::TopK(k) => Box::new(TopK(*k)),
+            Metrics::Precision => Box::new(Precision),
+            Metrics::Recall => Box::new(Recall),
+            Metrics::F1 => Box::new(F1),
+            Metrics::RocAuc => Box::new(RocAuc),
+            Metrics::MeanAbsoluteError => Box::new(MeanAbsoluteError),
+            Metrics::RootMeanSquaredError => Box::new(RootMeanSquaredError),
+            Metrics::RSquared => Box::new(RSquared),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array2};
+
+    use super::*;
+
+    fn one_hot(classes: &[usize], n: usize) -> Array2<f64> {
+        Array2::from_shape_fn((classes.len(), n), |(r, c)| (classes[r] == c) as u8 as f64)
+    }
+
+    #[test]
+    fn top_k_ranks_a_binary_column() {
+        let pred = array![[0.9], [0.2], [0.7]];
+        let target = array![[1.], [1.], [0.]];
+
+        assert!((TopK(1).score(&pred, &target).unwrap() - 1. / 3.).abs() < 1e-12);
+        assert_eq!(TopK(2).score(&pred, &target).unwrap(), 1.);
+    }
+
+    #[test]
+    fn top_k_counts_classes_ranked_within_k() {
+        let pred = array![[0.1, 0.3, 0.6], [0.2, 0.5, 0.3]];
+        let target = one_hot(&[0, 2], 3);
+
+        assert_eq!(TopK(1).score(&pred, &target).unwrap(), 0.);
+        assert_eq!(TopK(2).score(&pred, &target).unwrap(), 0.5);
+        assert_eq!(TopK(3).score(&pred, &target).unwrap(), 1.);
+    }
+
+    #[test]
+    fn top_k_misses_classes_beyond_the_prediction() {
+        let pred = array![[0.4, 0.6], [0.7, 0.3]];
+        let target = one_hot(&[2, 2], 3);
+
+        assert_eq!(TopK(5).score(&pred, &target).unwrap(), 0.);
+    }
+
+    #[test]
+    fn confusion_matrix_counts_actual_by_predicted() {
+        let pred = one_hot(&[0, 1, 1, 2, 2, 0], 3);
+        let target = one_hot(&[0, 1, 2, 2, 2, 1], 3);
+        let cm = ConfusionMatrix::new(&pred, &target).unwrap();
+
+        assert_eq!(cm.counts, array![[1, 0, 0], [1, 1, 0], [0, 1, 2]]);
+        assert_eq!(cm.precision(1), 0.5);
+        assert!((cm.recall(2) - 2. / 3.).abs() < 1e-12);
+        assert!((cm.f1(2) - 0.8).abs() < 1e-12);
+        assert!((Precision.score(&pred, &target).unwrap() - 2. / 3.).abs() < 1e-12);
+        assert!((Recall.score(&pred, &target).unwrap() - (1. + 0.5 + 2. / 3.) / 3.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn binary_scores_report_the_positive_class() {
+        let pred = array![[0.8], [0.6], [0.1], [0.3]];
+        let target = array![[1.], [0.], [1.], [0.]];
+
+        assert_eq!(Precision.score(&pred, &target).unwrap(), 0.5);
+        assert_eq!(Recall.score(&pred, &target).unwrap(), 0.5);
+        assert_eq!(Accuracy.score(&pred, &target).unwrap(), 0.5);
+    }
+
+    #[test]
+    fn mismatched_shapes_are_errors() {
+        let pred = Array2::zeros((4, 3));
+        let target = one_hot(&[0, 1, 0, 1], 2);
+
+        assert!(ConfusionMatrix::new(&pred, &target).is_err());
+        assert!(F1.score(&pred, &target).is_err());
+        assert!(RocAuc.score(&target, &pred).is_err());
+        assert!(MeanAbsoluteError.score(&pred, &target).is_err());
+        assert!(RSquared.score(&pred, &target).is_err());
+        assert!(Accuracy
+            .score(&pred.slice(ndarray::s![..3, ..]).to_owned(), &target)
+            .is_err());
+    }
+}
@@ -5,6 +5,7 @@ use std::sync::Arc;
 use zmq::{poll, Context, PULL, PUSH, SUB};
 
 use super::data::TrainChunk;
+use crate::nn::types::Manifold;
 use crate::nn::DNN;
 use crate::optimizers::{Hyper, MiniBatchGradientDescent};
 use crate::util::as_tensor;
@@ -69,6 +70,16 @@ pub fn worker(
             }
         };
 
+        // Architectures arrive unwoven, link them into this worker's substrate.
+        let woven = manifold
+            .set_substrate(substrate.clone())
+            .weave()
+            .and_then(|nn| nn.gather());
+        if let Err(e) = woven {
+            eprintln!("[{}] Failed to weave architecture: {}", name, e);
+            continue;
+        }
+
         println!("[🔨 {}] Received an architecture.", name);
 
         state = "awaiting_data";
@@ -113,12 +124,20 @@ pub fn worker(
             while let Some(chunk) = chunks.pop_front() {
                 let x_data = chunk.0;
                 let y_data = chunk.1;
-                let (x, y) = as_tensor(x_data, y_data);
+                let (x, y) = match as_tensor(x_data, y_data) {
+                    Ok(tensors) => tensors,
+                    Err(e) => {
+                        eprintln!("[{}] Received malformed train chunk: {}", name, e);
+                        continue;
+                    }
+                };
 
                 let nn = manifold.set_substrate(substrate.clone());
                 let mut trainer = MiniBatchGradientDescent::new(nn);
 
-                trainer.override_hyper((*hyper).clone()).train(&x, &y);
+                if let Err(e) = trainer.override_hyper((*hyper).clone()).train(&x, &y) {
+                    eprintln!("[{}] Failed to train on chunk: {}", name, e);
+                }
 
                 worker_losses.extend(trainer.losses.drain(..));
                 consumed_chunks += 1;
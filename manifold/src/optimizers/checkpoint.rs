@@ -0,0 +1,82 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{Hyper, MetricHistory};
+use crate::substrate::Substrate;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubstrateRef {
+    pub size: usize,
+    pub fingerprint: u64,
+}
+
+impl SubstrateRef {
+    pub fn of(substrate: &Substrate) -> SubstrateRef {
+        SubstrateRef {
+            size: substrate.size,
+            fingerprint: substrate.fingerprint(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RngState {
+    seed: [u8; 32],
+    stream: u64,
+    word_pos: u128,
+}
+
+impl RngState {
+    pub fn capture(rng: &ChaCha8Rng) -> RngState {
+        RngState {
+            seed: rng.get_seed(),
+            stream: rng.get_stream(),
+            word_pos: rng.get_word_pos(),
+        }
+    }
+
+    pub fn restore(&self) -> ChaCha8Rng {
+        let mut rng: ChaCha8Rng = rand::SeedableRng::from_seed(self.seed);
+        rng.set_stream(self.stream);
+        rng.set_word_pos(self.word_pos);
+        rng
+    }
+}
+
+// The pool itself is not stored, only a reference to it. Substrates are
+// shared between many networks and persisted on their own with Substrate::dump.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub manifold: Vec<u8>,
+    pub substrate: Option<SubstrateRef>,
+    pub hyper: Hyper,
+    pub epoch: usize,
+    pub losses: Vec<f64>,
+    pub validation_losses: Vec<f64>,
+    pub metrics: Vec<MetricHistory>,
+    pub rng: RngState,
+}
+
+impl Checkpoint {
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let serialized = bincode::serialize(self)?;
+
+        // Write beside the target and rename so an interrupted write never
+        // clobbers the last good checkpoint.
+        let path = path.as_ref();
+        let staging = path.with_extension("partial");
+        fs::write(&staging, serialized)?;
+        fs::rename(&staging, path)?;
+
+        Ok(())
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Checkpoint, Box<dyn Error>> {
+        let serialized = fs::read(path)?;
+        Ok(bincode::deserialize(&serialized)?)
+    }
+}
@@ -0,0 +1,180 @@
+use ndarray_rand::rand_distr::{Distribution, Normal};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::manifold::types::LayerGradients;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum ClipScope {
+    Global,
+    Layer,
+}
+
+/// Bound gradients before they are turned into link steps, so one bad batch
+/// can't fling links across the pool and pin them at its edges.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum GradientClip {
+    Value { max: f64 },
+    Norm { max: f64, scope: ClipScope },
+}
+
+impl GradientClip {
+    pub fn apply(&self, gradients: &mut [LayerGradients]) {
+        match *self {
+            GradientClip::Value { max } => {
+                for (grad_w, grad_b) in gradients.iter_mut() {
+                    grad_w.mapv_inplace(|x| x.clamp(-max, max));
+                    grad_b.mapv_inplace(|x| x.clamp(-max, max));
+                }
+            }
+            GradientClip::Norm {
+                max,
+                scope: ClipScope::Global,
+            } => {
+                let norm = gradients
+                    .iter()
+                    .map(|(grad_w, grad_b)| squared_sum(grad_w.iter()) + squared_sum(grad_b.iter()))
+                    .sum::<f64>()
+                    .sqrt();
+
+                if norm > max {
+                    let scale = max / norm;
+                    for (grad_w, grad_b) in gradients.iter_mut() {
+                        grad_w.mapv_inplace(|x| x * scale);
+                        grad_b.mapv_inplace(|x| x * scale);
+                    }
+                }
+            }
+            GradientClip::Norm {
+                max,
+                scope: ClipScope::Layer,
+            } => {
+                for (grad_w, grad_b) in gradients.iter_mut() {
+                    let norm = (squared_sum(grad_w.iter()) + squared_sum(grad_b.iter())).sqrt();
+
+                    if norm > max {
+                        let scale = max / norm;
+                        grad_w.mapv_inplace(|x| x * scale);
+                        grad_b.mapv_inplace(|x| x * scale);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Annealed Gaussian gradient noise, variance = eta / (1 + t)^gamma.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct GradientNoise {
+    pub eta: f64,
+    pub gamma: f64,
+}
+
+impl GradientNoise {
+    pub fn new(eta: f64) -> GradientNoise {
+        GradientNoise { eta, gamma: 0.55 }
+    }
+
+    pub fn std_dev(&self, t: usize) -> f64 {
+        (self.eta / (1. + t as f64).powf(self.gamma)).sqrt()
+    }
+
+    pub fn apply(&self, gradients: &mut [LayerGradients], t: usize, rng: &mut impl Rng) {
+        let normal = match Normal::new(0., self.std_dev(t)) {
+            Ok(normal) => normal,
+            Err(_) => return,
+        };
+
+        for (grad_w, grad_b) in gradients.iter_mut() {
+            grad_w.mapv_inplace(|x| x + normal.sample(rng));
+            grad_b.mapv_inplace(|x| x + normal.sample(rng));
+        }
+    }
+}
+
+fn squared_sum<'a>(values: impl Iterator<Item = &'a f64>) -> f64 {
+    values.map(|x| x.powi(2)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array1, Array2};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{squared_sum, ClipScope, GradientClip, GradientNoise};
+    use crate::manifold::types::LayerGradients;
+
+    fn layers() -> Vec<(Array2<f64>, Array1<f64>)> {
+        vec![
+            (array![[3., -4.], [0., 0.5]], array![0.]),
+            (array![[0.1, -0.2]], array![0.2]),
+        ]
+    }
+
+    fn clip(clip: GradientClip) -> Vec<(Array2<f64>, Array1<f64>)> {
+        let mut layers = layers();
+        let mut gradients: Vec<LayerGradients> = layers.iter_mut().map(|(w, b)| (w, b)).collect();
+        clip.apply(&mut gradients);
+        layers
+    }
+
+    fn norm((w, b): &(Array2<f64>, Array1<f64>)) -> f64 {
+        (squared_sum(w.iter()) + squared_sum(b.iter())).sqrt()
+    }
+
+    #[test]
+    fn value_clip_clamps_every_gradient() {
+        let clipped = clip(GradientClip::Value { max: 1. });
+
+        assert_eq!(clipped[0].0, array![[1., -1.], [0., 0.5]]);
+        assert_eq!(clipped[1], layers()[1]);
+    }
+
+    #[test]
+    fn global_norm_clip_scales_every_layer_alike() {
+        let clipped = clip(GradientClip::Norm {
+            max: 1.,
+            scope: ClipScope::Global,
+        });
+        let total = clipped.iter().map(|l| norm(l).powi(2)).sum::<f64>().sqrt();
+        let scale = clipped[1].1[0] / layers()[1].1[0];
+
+        assert!((total - 1.).abs() < 1e-12);
+        assert!((clipped[0].0[[0, 0]] / 3. - scale).abs() < 1e-12);
+    }
+
+    #[test]
+    fn layer_norm_clip_leaves_small_layers_alone() {
+        let clipped = clip(GradientClip::Norm {
+            max: 1.,
+            scope: ClipScope::Layer,
+        });
+
+        assert!((norm(&clipped[0]) - 1.).abs() < 1e-12);
+        assert_eq!(clipped[1], layers()[1]);
+    }
+
+    #[test]
+    fn noise_anneals_and_follows_the_rng() {
+        let noise = GradientNoise::new(1.);
+        assert_eq!(noise.std_dev(0), 1.);
+        assert!(noise.std_dev(100) < noise.std_dev(10));
+
+        let noised = |seed: u64| {
+            let (mut w, mut b) = (Array2::zeros((100, 100)), Array1::zeros(1));
+            noise.apply(
+                &mut [(&mut w, &mut b)],
+                0,
+                &mut ChaCha8Rng::seed_from_u64(seed),
+            );
+            w
+        };
+        let w = noised(1);
+        let deviation = (squared_sum(w.iter()) / w.len() as f64).sqrt();
+
+        assert!((deviation - 1.).abs() < 0.05);
+        assert_eq!(w, noised(1));
+        assert_ne!(w, noised(2));
+    }
+}
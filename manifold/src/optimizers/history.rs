@@ -0,0 +1,289 @@
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use plotly::common::{Mode, Title};
+use plotly::{Layout, Plot, Scatter};
+use serde::{Deserialize, Serialize};
+
+use super::MetricHistory;
+
+const PALETTE: [&str; 6] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b",
+];
+
+pub struct Series {
+    pub name: String,
+    pub values: Vec<f64>,
+}
+
+/// Exponential moving average, weight 0 leaves the series untouched and
+/// weights close to 1 flatten it.
+pub fn smooth(values: &[f64], weight: f64) -> Vec<f64> {
+    let weight = weight.clamp(0., 0.999);
+    let mut last = match values.first() {
+        Some(v) => *v,
+        None => return vec![],
+    };
+
+    values
+        .iter()
+        .map(|v| {
+            last = last * weight + v * (1. - weight);
+            last
+        })
+        .collect()
+}
+
+/// Everything a trainer recorded, one entry per epoch.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct History {
+    pub losses: Vec<f64>,
+    pub validation_losses: Vec<f64>,
+    pub metrics: Vec<MetricHistory>,
+}
+
+impl History {
+    pub fn loss_series(&self) -> Vec<Series> {
+        let mut series = vec![Series {
+            name: "train".to_string(),
+            values: self.losses.clone(),
+        }];
+
+        if !self.validation_losses.is_empty() {
+            series.push(Series {
+                name: "validation".to_string(),
+                values: self.validation_losses.clone(),
+            });
+        }
+
+        series
+    }
+
+    pub fn metric_series(&self) -> Vec<Series> {
+        let mut series = vec![];
+
+        for history in self.metrics.iter() {
+            let name = history.metric.wake().name();
+            series.push(Series {
+                name: format!("{} train", name),
+                values: history.train.clone(),
+            });
+
+            if !history.validation.is_empty() {
+                series.push(Series {
+                    name: format!("{} validation", name),
+                    values: history.validation.clone(),
+                });
+            }
+        }
+
+        series
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut header = vec!["epoch".to_string(), "loss".to_string()];
+        let mut columns: Vec<&Vec<f64>> = vec![&self.losses];
+
+        if !self.validation_losses.is_empty() {
+            header.push("validation_loss".to_string());
+            columns.push(&self.validation_losses);
+        }
+
+        for history in self.metrics.iter() {
+            let name = history.metric.wake().name();
+            header.push(format!("{}_train", name));
+            columns.push(&history.train);
+
+            if !history.validation.is_empty() {
+                header.push(format!("{}_validation", name));
+                columns.push(&history.validation);
+            }
+        }
+
+        let rows = columns.iter().map(|c| c.len()).max().unwrap_or(0);
+        let mut csv = header.join(",");
+        csv.push('\n');
+
+        for row in 0..rows {
+            let _ = write!(csv, "{}", row);
+            for column in columns.iter() {
+                match column.get(row) {
+                    Some(v) => {
+                        let _ = write!(csv, ",{}", v);
+                    }
+                    None => csv.push(','),
+                }
+            }
+            csv.push('\n');
+        }
+
+        csv
+    }
+
+    /// Writes CSV or JSON depending on the extension of `path`.
+    pub fn export(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+
+        match extension(path).as_str() {
+            "csv" => fs::write(path, self.to_csv())?,
+            "json" => fs::write(path, serde_json::to_string_pretty(self)?)?,
+            other => {
+                return Err(
+                    format!("Can't export history as '{}', use .csv or .json", other).into(),
+                )
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+pub fn line_plot(title: &str, series: &[Series], smoothing: f64) -> Plot {
+    let mut plot = Plot::new();
+
+    for s in series.iter() {
+        let x = (0..s.values.len()).collect::<Vec<usize>>();
+        let trace = Scatter::new(x, smooth(&s.values, smoothing))
+            .name(s.name.as_str())
+            .mode(Mode::Lines);
+        plot.add_trace(trace);
+    }
+
+    plot.set_layout(Layout::new().title(Title::new(title)));
+    plot
+}
+
+/// Renders a line chart to `path`, as standalone HTML or a plain SVG
+/// depending on the extension. Neither needs a browser or display.
+pub fn write_plot(
+    path: impl AsRef<Path>,
+    title: &str,
+    series: &[Series],
+    smoothing: f64,
+) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+
+    match extension(path).as_str() {
+        "html" | "htm" => fs::write(path, line_plot(title, series, smoothing).to_html())?,
+        "svg" => fs::write(path, svg(title, series, smoothing))?,
+        other => return Err(format!("Can't plot to '{}', use .html or .svg", other).into()),
+    }
+
+    Ok(())
+}
+
+fn svg(title: &str, series: &[Series], smoothing: f64) -> String {
+    let (width, height) = (800., 400.);
+    let (left, right, top, bottom) = (60., 160., 40., 40.);
+    let (plot_w, plot_h) = (width - left - right, height - top - bottom);
+
+    let smoothed = series
+        .iter()
+        .map(|s| smooth(&s.values, smoothing))
+        .collect::<Vec<Vec<f64>>>();
+
+    let finite = smoothed.iter().flatten().filter(|v| v.is_finite());
+    let (mut lo, mut hi) =
+        finite.fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(*v), hi.max(*v)));
+    if lo > hi {
+        (lo, hi) = (0., 1.);
+    }
+    if (hi - lo).abs() < f64::EPSILON {
+        (lo, hi) = (lo - 0.5, hi + 0.5);
+    }
+    let span = smoothed.iter().map(|s| s.len()).max().unwrap_or(0).max(2) - 1;
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="12">"#,
+        width, height
+    );
+    let _ = writeln!(out, r#"<rect width="100%" height="100%" fill="white"/>"#);
+    let _ = writeln!(
+        out,
+        r#"<text x="{}" y="24" font-size="16">{}</text>"#,
+        left,
+        escape(title)
+    );
+    let _ = writeln!(
+        out,
+        r#"<path d="M{l} {t} V{b} H{r}" stroke="black" fill="none"/>"#,
+        l = left,
+        t = top,
+        b = top + plot_h,
+        r = left + plot_w
+    );
+    let _ = writeln!(
+        out,
+        r#"<text x="{}" y="{}" text-anchor="end">{:.4}</text><text x="{}" y="{}" text-anchor="end">{:.4}</text>"#,
+        left - 4.,
+        top + 4.,
+        hi,
+        left - 4.,
+        top + plot_h,
+        lo
+    );
+    let _ = writeln!(
+        out,
+        r#"<text x="{}" y="{}">0</text><text x="{}" y="{}" text-anchor="end">{}</text>"#,
+        left,
+        top + plot_h + 16.,
+        left + plot_w,
+        top + plot_h + 16.,
+        span
+    );
+
+    for (i, (s, values)) in series.iter().zip(smoothed.iter()).enumerate() {
+        let color = PALETTE[i % PALETTE.len()];
+        let points = values
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.is_finite())
+            .map(|(x, v)| {
+                format!(
+                    "{:.2},{:.2}",
+                    left + plot_w * x as f64 / span as f64,
+                    top + plot_h * (hi - v) / (hi - lo)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        let _ = writeln!(
+            out,
+            r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="1.5"/>"#,
+            points, color
+        );
+
+        let legend_y = top + 16. * i as f64;
+        let _ = writeln!(
+            out,
+            r#"<rect x="{}" y="{}" width="12" height="3" fill="{}"/><text x="{}" y="{}">{}</text>"#,
+            left + plot_w + 12.,
+            legend_y + 4.,
+            color,
+            left + plot_w + 30.,
+            legend_y + 8.,
+            escape(&s.name)
+        );
+    }
+
+    out.push_str("</svg>\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,391 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, available_parallelism};
+
+use ndarray::{stack, Array1, Array2, Array3, Axis};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use super::Hyper;
+use crate::error::{expect_shape, panic_message, ManifoldError};
+use crate::manifold::types::{LayerBindings, LayerStatistics, Manifold};
+
+// Loss history of one thread with the running statistics its replica kept.
+type Replica = (Vec<(usize, f64)>, Vec<LayerStatistics>);
+
+// Links of every layer flattened into atomics. Workers read whatever is there
+// and add their deltas without locking, so concurrent updates can interleave
+// but never tear.
+struct SharedLinks {
+    layers: Vec<(Vec<AtomicUsize>, Vec<AtomicUsize>)>,
+    shapes: Vec<((usize, usize), usize)>,
+    size: usize,
+}
+
+impl SharedLinks {
+    fn new(bindings: Vec<LayerBindings>, size: usize) -> SharedLinks {
+        let shapes = bindings
+            .iter()
+            .map(|(wi, bi)| (wi.dim(), bi.len()))
+            .collect();
+        let layers = bindings
+            .into_iter()
+            .map(|(wi, bi)| {
+                (
+                    wi.iter().map(|l| AtomicUsize::new(*l)).collect(),
+                    bi.iter().map(|l| AtomicUsize::new(*l)).collect(),
+                )
+            })
+            .collect();
+
+        SharedLinks {
+            layers,
+            shapes,
+            size,
+        }
+    }
+
+    fn load(&self) -> Vec<LayerBindings> {
+        self.layers
+            .iter()
+            .zip(self.shapes.iter())
+            .map(|((wi, bi), (w_shape, b_shape))| {
+                let wi = wi.iter().map(|l| l.load(Ordering::Relaxed)).collect();
+                let bi = bi.iter().map(|l| l.load(Ordering::Relaxed)).collect();
+                (
+                    Array2::from_shape_vec(*w_shape, wi).unwrap(),
+                    Array1::from_shape_vec(*b_shape, bi).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn push(&self, before: &[LayerBindings], after: &[LayerBindings]) {
+        for ((wi, bi), ((wi_before, bi_before), (wi_after, bi_after))) in
+            self.layers.iter().zip(before.iter().zip(after.iter()))
+        {
+            self.add(wi, wi_before.iter().zip(wi_after.iter()));
+            self.add(bi, bi_before.iter().zip(bi_after.iter()));
+        }
+    }
+
+    fn add<'a>(&self, links: &[AtomicUsize], moves: impl Iterator<Item = (&'a usize, &'a usize)>) {
+        let size = self.size as i64;
+
+        for (link, (before, after)) in links.iter().zip(moves) {
+            if before == after {
+                continue;
+            }
+
+            let delta = *after as i64 - *before as i64;
+            let _ = link.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |l| {
+                Some((l as i64 + delta).clamp(0, size) as usize)
+            });
+        }
+    }
+}
+
+/// Asynchronous trainer in the style of Hogwild!. Every thread trains its own
+/// replica of the network, but all replicas pull links from and push link
+/// moves to one shared, lock free set of links over the same substrate.
+pub struct Hogwild<'a, T: Manifold + Clone + Send> {
+    manifold: &'a mut T,
+    hyper: Hyper,
+    threads: usize,
+    seed: Option<u64>,
+    verbose: bool,
+    pub losses: Vec<f64>,
+}
+
+impl<'a, T: Manifold + Clone + Send> Hogwild<'a, T> {
+    pub fn new(manifold: &'a mut T) -> Hogwild<'a, T> {
+        Hogwild {
+            manifold,
+            hyper: Hyper::new(),
+            threads: available_parallelism().map(|c| c.into()).unwrap_or(1),
+            seed: None,
+            verbose: false,
+            losses: vec![],
+        }
+    }
+
+    pub fn override_hyper(&mut self, hyper: Hyper) -> &mut Self {
+        self.hyper = hyper;
+        self
+    }
+
+    pub fn verbose(&mut self) -> &mut Self {
+        self.verbose = true;
+        self
+    }
+
+    pub fn set_learning_rate(&mut self, rate: f64) -> &mut Self {
+        self.hyper.learning_rate = rate;
+        self
+    }
+
+    pub fn set_decay(&mut self, decay: f64) -> &mut Self {
+        self.hyper.decay = decay;
+        self
+    }
+
+    /// Total updates across all threads.
+    pub fn set_epochs(&mut self, epochs: usize) -> &mut Self {
+        self.hyper.epochs = epochs;
+        self
+    }
+
+    pub fn set_sample_size(&mut self, sample_size: usize) -> &mut Self {
+        self.hyper.sample_size = sample_size;
+        self
+    }
+
+    pub fn set_threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Seeds each thread's batch sampling. Thread scheduling still decides the
+    /// order updates land in, so runs are not bit for bit reproducible.
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn train(&mut self, x: &Array3<f64>, y: &Array3<f64>) -> Result<&mut Self, ManifoldError> {
+        expect_shape(&[x.shape()[0]], &[y.shape()[0]])?;
+
+        let size = match self.manifold.get_substrate() {
+            Some(substrate) => substrate.size,
+            None => {
+                return Err(ManifoldError::InvalidLayer(
+                    "Hogwild needs a substrate-linked network".to_string(),
+                ))
+            }
+        };
+
+        // Only links are shared, weights kept as plain floats would train
+        // apart in every replica and be thrown away with it.
+        if self.manifold.gradients_mut().len() > self.manifold.bindings().len() {
+            return Err(ManifoldError::InvalidLayer(
+                "Hogwild only shares links, the network has isolated weights".to_string(),
+            ));
+        }
+
+        let shared = SharedLinks::new(self.manifold.bindings(), size);
+        let epoch = AtomicUsize::new(0);
+        let hyper = &self.hyper;
+        let verbose = self.verbose;
+
+        let replicas: Result<Vec<Replica>, ManifoldError> = thread::scope(|scope| {
+            let handles = (0..self.threads)
+                .map(|thread| {
+                    let mut replica = self.manifold.clone();
+                    let mut rng = match self.seed {
+                        Some(seed) => ChaCha8Rng::seed_from_u64(seed.wrapping_add(thread as u64)),
+                        None => ChaCha8Rng::from_entropy(),
+                    };
+                    replica.set_seed(rng.gen());
+                    let shared = &shared;
+                    let epoch = &epoch;
+
+                    scope.spawn(move || {
+                        let mut history: Vec<(usize, f64)> = vec![];
+
+                        let mut run = || -> Result<(), ManifoldError> {
+                            loop {
+                                let t = epoch.fetch_add(1, Ordering::Relaxed);
+                                if t >= hyper.epochs {
+                                    return Ok(());
+                                }
+
+                                replica.bind(shared.load())?;
+
+                                let indices = (0..hyper.sample_size)
+                                    .map(|_| rng.gen_range(0..x.shape()[0]))
+                                    .collect::<Vec<usize>>();
+                                let batch_x: Array3<f64> = stack(
+                                    Axis(0),
+                                    &indices
+                                        .iter()
+                                        .map(|ix| x.index_axis(Axis(0), *ix))
+                                        .collect::<Vec<_>>(),
+                                )
+                                .unwrap();
+                                let batch_y: Array3<f64> = stack(
+                                    Axis(0),
+                                    &indices
+                                        .iter()
+                                        .map(|ix| y.index_axis(Axis(0), *ix))
+                                        .collect::<Vec<_>>(),
+                                )
+                                .unwrap();
+
+                                let y_pred = replica.forward(batch_x)?.remove_axis(Axis(1));
+                                let y_batch = batch_y.remove_axis(Axis(1));
+                                expect_shape(y_batch.shape(), y_pred.shape())?;
+
+                                let loss = replica.get_loss_fn();
+                                let a_loss = loss.a(y_pred.clone(), y_batch.clone())?;
+                                let batch_loss = a_loss.sum() / a_loss.len() as f64;
+                                replica.accumulate(y_pred, y_batch, loss)?;
+
+                                {
+                                    let mut gradients = replica.gradients_mut();
+                                    if let Some(noise) = &hyper.noise {
+                                        noise.apply(&mut gradients, t, &mut rng);
+                                    }
+                                    if let Some(clip) = &hyper.clip {
+                                        clip.apply(&mut gradients);
+                                    }
+                                }
+
+                                // Shift the replica as usual, rows it looked up
+                                // included, then publish how far each link moved
+                                // rather than where it landed.
+                                let before = replica.bindings();
+                                replica.shift(hyper.learning_rate * hyper.decay.powi(t as i32))?;
+                                shared.push(&before, &replica.bindings());
+
+                                if verbose {
+                                    println!(
+                                        "[thread {}] ({}/{}) Loss = {}",
+                                        thread, t, hyper.epochs, batch_loss
+                                    );
+                                }
+
+                                history.push((t, batch_loss));
+                            }
+                        };
+
+                        // A failing thread drains the epoch counter so the
+                        // others stop too.
+                        match run() {
+                            Ok(()) => Ok((history, replica.statistics())),
+                            Err(e) => {
+                                epoch.store(hyper.epochs, Ordering::Relaxed);
+                                Err(e)
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .enumerate()
+                .map(|(thread, handle)| match handle.join() {
+                    Ok(replica) => replica,
+                    Err(panic) => Err(ManifoldError::WorkerPanicked(format!(
+                        "thread {}, {}",
+                        thread,
+                        panic_message(panic.as_ref())
+                    ))),
+                })
+                .collect()
+        });
+
+        self.manifold.bind(shared.load())?;
+
+        let (histories, statistics): (Vec<_>, Vec<_>) = replicas?.into_iter().unzip();
+
+        // Running statistics each replica kept over its own batches are
+        // averaged into the network.
+        let mut merged = self.manifold.statistics();
+        for (mean, var) in merged.iter_mut() {
+            mean.fill(0.);
+            var.fill(0.);
+        }
+        for replica in statistics.iter() {
+            let weight = 1. / statistics.len() as f64;
+
+            for ((mean, var), (replica_mean, replica_var)) in merged.iter_mut().zip(replica.iter())
+            {
+                mean.scaled_add(weight, replica_mean);
+                var.scaled_add(weight, replica_var);
+            }
+        }
+        if !statistics.is_empty() {
+            self.manifold.set_statistics(merged)?;
+        }
+
+        let mut history = histories.into_iter().flatten().collect::<Vec<_>>();
+        history.sort_by_key(|(t, _)| *t);
+        self.losses
+            .extend(history.into_iter().map(|(_, loss)| loss));
+        self.hyper.learning_rate *= self.hyper.decay.powi(self.hyper.epochs as i32);
+
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array3, Axis};
+    use ndarray_rand::rand_distr::StandardNormal;
+    use ndarray_rand::RandomExt;
+
+    use super::Hogwild;
+    use crate::activation::Activations;
+    use crate::error::ManifoldError;
+    use crate::layers::types::Layers;
+    use crate::manifold::types::Manifold;
+    use crate::manifold::{Composable, DNNIsolated, DNN};
+    use crate::substrate::Substrate;
+
+    fn mean(losses: &[f64]) -> f64 {
+        losses.iter().sum::<f64>() / losses.len() as f64
+    }
+
+    #[test]
+    fn threads_sharing_links_bring_the_loss_down() {
+        let x = Array3::random((256, 1, 4), StandardNormal);
+        let y = (x.sum_axis(Axis(2)) * 0.5).insert_axis(Axis(2));
+
+        let mut nn = DNN::new(Substrate::new(10000, -1.0..1.0).share(), 4, 1, vec![]);
+        nn.weave().unwrap().gather().unwrap();
+
+        let mut hogwild = Hogwild::new(&mut nn);
+        hogwild
+            .set_learning_rate(0.5)
+            .set_epochs(600)
+            .set_sample_size(32)
+            .set_threads(3)
+            .set_seed(5)
+            .train(&x, &y)
+            .unwrap();
+
+        assert_eq!(hogwild.losses.len(), 600);
+        let (first, last) = (mean(&hogwild.losses[..50]), mean(&hogwild.losses[550..]));
+        assert!(last < first / 2., "loss went from {} to {}", first, last);
+    }
+
+    #[test]
+    fn isolated_weights_are_rejected() {
+        let x = Array3::random((8, 1, 4), StandardNormal);
+        let y = Array3::random((8, 1, 1), StandardNormal);
+
+        let mut isolated = DNNIsolated::new(4, 1, vec![3]);
+        isolated.weave().unwrap();
+        assert!(matches!(
+            Hogwild::new(&mut isolated).train(&x, &y),
+            Err(ManifoldError::InvalidLayer(_))
+        ));
+
+        // Linked dense weights next to a norm keeping plain float gains.
+        let mut mixed = Composable::new(Substrate::new(1000, -1.0..1.0).share(), 4, 1);
+        mixed
+            .layer(3, Activations::Tanh, Layers::Dense)
+            .weightless(Layers::BatchNorm {
+                momentum: 0.9,
+                linked: false,
+            });
+        mixed.weave().unwrap().gather().unwrap();
+        let bindings = mixed.bindings();
+
+        match Hogwild::new(&mut mixed).train(&x, &y) {
+            Err(ManifoldError::InvalidLayer(e)) => assert!(e.contains("isolated weights")),
+            _ => panic!("isolated weights were trained"),
+        }
+        assert_eq!(mixed.bindings(), bindings);
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+/// Loss recorded against an exponentially growing learning rate, as produced
+/// by MiniBatchGradientDescent::find_learning_rate.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LearningRateSweep {
+    pub rates: Vec<f64>,
+    pub losses: Vec<f64>,
+    pub smoothed: Vec<f64>,
+    pub suggestion: f64,
+}
+
+impl LearningRateSweep {
+    pub fn rate(min: f64, max: f64, step: usize, steps: usize) -> f64 {
+        let t = step as f64 / (steps.max(2) - 1) as f64;
+        min * (max / min).powf(t)
+    }
+
+    pub fn new(rates: Vec<f64>, losses: Vec<f64>) -> LearningRateSweep {
+        // Bias corrected moving average, the raw per batch loss is too noisy
+        // to take a slope from.
+        let beta: f64 = 0.98;
+        let mut average = 0.;
+        let smoothed = losses
+            .iter()
+            .enumerate()
+            .map(|(i, loss)| {
+                average = beta * average + (1. - beta) * loss;
+                average / (1. - beta.powi(i as i32 + 1))
+            })
+            .collect::<Vec<f64>>();
+
+        let suggestion = LearningRateSweep::suggest(&rates, &smoothed);
+
+        LearningRateSweep {
+            rates,
+            losses,
+            smoothed,
+            suggestion,
+        }
+    }
+
+    // Rate where the smoothed loss falls fastest against log(rate). With too
+    // few points to take a slope, fall back a decade below the minimum.
+    fn suggest(rates: &[f64], smoothed: &[f64]) -> f64 {
+        let best = smoothed
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.is_finite())
+            .min_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(i, _)| i);
+
+        let best = match best {
+            Some(best) => best,
+            None => return rates.first().copied().unwrap_or(0.),
+        };
+
+        let steepest = (1..=best)
+            .map(|i| {
+                let slope = (smoothed[i] - smoothed[i - 1]) / (rates[i].ln() - rates[i - 1].ln());
+                (i, slope)
+            })
+            .filter(|(_, slope)| slope.is_finite())
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        match steepest {
+            Some((i, _)) => rates[i],
+            None => rates[best] / 10.,
+        }
+    }
+}
@@ -1,19 +1,56 @@
-use ndarray::{stack, Array1, Array2, Array3, Axis};
-use plotly::{Bar, Plot};
-use rand::{prelude::*, thread_rng};
-
-use super::Hyper;
-use crate::manifold::types::Manifold;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::thread::available_parallelism;
+
+use ndarray::{concatenate, s, stack, Array1, Array2, Array3, Axis};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::checkpoint::{Checkpoint, RngState, SubstrateRef};
+use super::gradients::{GradientClip, GradientNoise};
+use super::history::{line_plot, write_plot, History};
+use super::lr_range::LearningRateSweep;
+use super::{Hyper, MetricHistory};
+use crate::error::{expect_shape, ManifoldError};
+use crate::f::distributed;
+use crate::manifold::types::{LayerRows, LayerStatistics, Manifold};
+use crate::metric::{ConfusionMatrix, Metrics};
+use crate::util::as_tensor;
+
+// Mean batch loss, predictions and targets of one training step.
+type Step = (f64, Array2<f64>, Array2<f64>);
+type StepFn<S> = fn(&mut S, Array3<f64>, Array3<f64>) -> Result<Step, ManifoldError>;
+
+struct Shard {
+    start: usize,
+    gradients: Vec<(Array2<f64>, Array1<f64>)>,
+    touched: Vec<LayerRows>,
+    statistics: Vec<LayerStatistics>,
+    loss: f64,
+    len: usize,
+    pred: Array2<f64>,
+    target: Array2<f64>,
+}
 
-pub struct MiniBatchGradientDescent<'a, T: Manifold> {
+pub struct MiniBatchGradientDescent<'a, T: Manifold + Serialize + DeserializeOwned> {
     manifold: &'a mut T,
     hyper: Hyper,
     early_terminate: Box<dyn Fn(&Vec<f64>) -> bool>,
     verbose: bool,
+    rng: ChaCha8Rng,
+    epoch: usize,
+    threads: usize,
+    checkpoint: Option<(PathBuf, usize)>,
+    validation: Option<(Array3<f64>, Array3<f64>)>,
+    smoothing: f64,
     pub losses: Vec<f64>,
+    pub validation_losses: Vec<f64>,
+    pub metrics: Vec<MetricHistory>,
 }
 
-impl<'a, T: Manifold> MiniBatchGradientDescent<'a, T> {
+impl<'a, T: Manifold + Serialize + DeserializeOwned> MiniBatchGradientDescent<'a, T> {
     pub fn new(manifold: &mut T) -> MiniBatchGradientDescent<T> {
         MiniBatchGradientDescent {
             manifold,
@@ -21,6 +58,14 @@ impl<'a, T: Manifold> MiniBatchGradientDescent<'a, T> {
             early_terminate: Box::new(|_| false),
             losses: vec![],
             verbose: false,
+            rng: ChaCha8Rng::from_entropy(),
+            epoch: 0,
+            threads: available_parallelism().map(|c| c.into()).unwrap_or(1),
+            checkpoint: None,
+            validation: None,
+            smoothing: 0.,
+            validation_losses: vec![],
+            metrics: vec![],
         }
     }
 
@@ -107,70 +152,246 @@ impl<'a, T: Manifold> MiniBatchGradientDescent<'a, T> {
         self
     }
 
-    pub fn prepare(x: Vec<Vec<f64>>, y: Vec<Vec<f64>>) -> (Array3<f64>, Array3<f64>) {
-        let x_a2 = x
-            .into_iter()
-            .map(|xv| Array1::from(xv).insert_axis(Axis(0)))
-            .collect::<Vec<Array2<f64>>>();
-        let y_a2 = y
-            .into_iter()
-            .map(|yv| Array1::from(yv).insert_axis(Axis(0)))
-            .collect::<Vec<Array2<f64>>>();
+    pub fn set_gradient_clip(&mut self, clip: GradientClip) -> &mut Self {
+        self.hyper.clip = Some(clip);
+        self
+    }
 
-        let x_3 = stack(
-            Axis(0),
-            &x_a2.iter().map(|ar| ar.view()).collect::<Vec<_>>(),
-        )
-        .unwrap();
-        let y_3 = stack(
-            Axis(0),
-            &y_a2.iter().map(|ar| ar.view()).collect::<Vec<_>>(),
-        )
-        .unwrap();
+    pub fn set_gradient_noise(&mut self, noise: GradientNoise) -> &mut Self {
+        self.hyper.noise = Some(noise);
+        self
+    }
 
-        (x_3, y_3)
+    /// Metrics recorded every epoch, on the training batch and on the
+    /// validation set when one is given.
+    pub fn set_metrics(&mut self, metrics: &[Metrics]) -> &mut Self {
+        self.metrics = metrics.iter().map(|m| MetricHistory::new(*m)).collect();
+        self
     }
 
-    pub fn train(&mut self, x: &Array3<f64>, y: &Array3<f64>) -> &mut Self {
-        assert_eq!(
-            x.shape(),
-            y.shape(),
-            "X and Y must be of the same shape for training."
-        );
+    /// Held out set scored after every epoch into validation_losses and the
+    /// validation side of each metric history.
+    pub fn set_validation(&mut self, x: &Array3<f64>, y: &Array3<f64>) -> &mut Self {
+        self.validation = Some((x.to_owned(), y.to_owned()));
+        self
+    }
 
-        for epoch in 0..self.hyper.epochs {
-            let mut indices: Vec<usize> = Vec::with_capacity(self.hyper.sample_size);
-            let mut rng = thread_rng();
-            for _ in 0..self.hyper.sample_size {
-                indices.push(rng.gen_range(0..x.shape()[0]));
+    /// Shards train_parallel splits every minibatch into, one per core by
+    /// default.
+    pub fn set_threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Seeds sampling, noise and, through the network, dropout masks.
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.manifold.set_seed(self.rng.gen());
+        self
+    }
+
+    /// Write a checkpoint to `path` every `every` epochs while training.
+    pub fn set_checkpoint(&mut self, path: impl AsRef<Path>, every: usize) -> &mut Self {
+        self.checkpoint = Some((path.as_ref().to_path_buf(), every.max(1)));
+        self
+    }
+
+    pub fn checkpoint(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let checkpoint = Checkpoint {
+            manifold: bincode::serialize(&*self.manifold)?,
+            substrate: self
+                .manifold
+                .get_substrate()
+                .map(|substrate| SubstrateRef::of(&substrate)),
+            hyper: self.hyper.clone(),
+            epoch: self.epoch,
+            losses: self.losses.clone(),
+            validation_losses: self.validation_losses.clone(),
+            metrics: self.metrics.clone(),
+            rng: RngState::capture(&self.rng),
+        };
+
+        checkpoint.write(path)
+    }
+
+    /// Restore the network, its dropout streams included, hyperparameters,
+    /// loss history and rng from a checkpoint. The network keeps the
+    /// substrate it currently holds, which must be the one the checkpoint was
+    /// trained against. The next call to train picks up at the checkpointed
+    /// epoch.
+    pub fn resume_from(&mut self, path: impl AsRef<Path>) -> Result<&mut Self, Box<dyn Error>> {
+        let checkpoint = Checkpoint::read(path)?;
+        let mut manifold: T = bincode::deserialize(&checkpoint.manifold)?;
+
+        if let Some(expected) = checkpoint.substrate {
+            let substrate = self
+                .manifold
+                .get_substrate()
+                .ok_or("Checkpoint references a substrate but the network has none.")?;
+
+            if SubstrateRef::of(&substrate) != expected {
+                return Err(format!(
+                    "Checkpoint was trained against Substrate[0..{}] ({:x}), network holds Substrate[0..{}] ({:x}).",
+                    expected.size,
+                    expected.fingerprint,
+                    substrate.size,
+                    substrate.fingerprint()
+                )
+                .into());
             }
 
-            let batch_x_vec = indices
-                .iter()
-                .map(|ix| x.index_axis(Axis(0), *ix))
-                .collect::<Vec<_>>();
-            let batch_y_vec = indices
-                .iter()
-                .map(|ix| y.index_axis(Axis(0), *ix))
-                .collect::<Vec<_>>();
+            manifold.set_substrate(substrate);
+        }
+
+        *self.manifold = manifold;
+        self.hyper = checkpoint.hyper;
+        self.epoch = checkpoint.epoch;
+        self.losses = checkpoint.losses;
+        self.validation_losses = checkpoint.validation_losses;
+        self.metrics = checkpoint.metrics;
+        self.rng = checkpoint.rng.restore();
+
+        Ok(self)
+    }
+
+    pub fn prepare(
+        x: Vec<Vec<f64>>,
+        y: Vec<Vec<f64>>,
+    ) -> Result<(Array3<f64>, Array3<f64>), ManifoldError> {
+        as_tensor(x, y)
+    }
+
+    fn regulate(&mut self, t: usize) {
+        if self.hyper.clip.is_none() && self.hyper.noise.is_none() {
+            return;
+        }
+
+        let mut gradients = self.manifold.gradients_mut();
+
+        if let Some(noise) = &self.hyper.noise {
+            noise.apply(&mut gradients, t, &mut self.rng);
+        }
+
+        if let Some(clip) = &self.hyper.clip {
+            clip.apply(&mut gradients);
+        }
+    }
 
-            let batch_x: Array3<f64> = stack(Axis(0), &batch_x_vec).unwrap();
-            let batch_y: Array3<f64> = stack(Axis(0), &batch_y_vec).unwrap();
+    /// Train from the current epoch up to the total given by set_epochs. A
+    /// trainer that already reached it trains no further until the total is
+    /// raised, which continues the same run, noise annealing included.
+    pub fn train(&mut self, x: &Array3<f64>, y: &Array3<f64>) -> Result<&mut Self, ManifoldError> {
+        self.run(x, y, Self::step)
+    }
 
-            let y_pred = self.manifold.forward(batch_x);
-            let y_pred_reshaped = y_pred.remove_axis(Axis(1));
+    pub fn confusion_matrix(
+        &mut self,
+        x: &Array3<f64>,
+        y: &Array3<f64>,
+    ) -> Result<ConfusionMatrix, ManifoldError> {
+        let y_pred = self.manifold.infer(x.to_owned())?.remove_axis(Axis(1));
+        let y = y.to_owned().remove_axis(Axis(1));
+        expect_shape(y.shape(), y_pred.shape())?;
+
+        ConfusionMatrix::new(&y_pred, &y)
+    }
 
-            let y_reshaped = batch_y.remove_axis(Axis(1));
+    // Forward a batch and accumulate its gradients.
+    fn step(&mut self, batch_x: Array3<f64>, batch_y: Array3<f64>) -> Result<Step, ManifoldError> {
+        let y_pred = self.manifold.forward(batch_x)?;
+        let y_pred_reshaped = y_pred.remove_axis(Axis(1));
+
+        let y_reshaped = batch_y.remove_axis(Axis(1));
+        expect_shape(y_reshaped.shape(), y_pred_reshaped.shape())?;
+
+        let loss = self.manifold.get_loss_fn();
+        let a_loss = loss.a(y_pred_reshaped.clone(), y_reshaped.clone())?;
+        let sum_batch_loss = a_loss.sum() / a_loss.len() as f64;
+
+        self.manifold
+            .accumulate(y_pred_reshaped.clone(), y_reshaped.clone(), loss)?;
+
+        Ok((sum_batch_loss, y_pred_reshaped, y_reshaped))
+    }
+
+    fn record(&mut self, pred: &Array2<f64>, target: &Array2<f64>) -> Result<(), ManifoldError> {
+        for history in self.metrics.iter_mut() {
+            history
+                .train
+                .push(history.metric.wake().score(pred, target)?);
+        }
+
+        if let Some((x, y)) = &self.validation {
+            let y_pred = self.manifold.infer(x.clone())?.remove_axis(Axis(1));
+            let y = y.clone().remove_axis(Axis(1));
+            expect_shape(y.shape(), y_pred.shape())?;
 
             let loss = self.manifold.get_loss_fn();
-            let a_loss = loss.a(y_pred_reshaped.clone(), y_reshaped.clone());
-            let sum_batch_loss = a_loss.sum() / a_loss.len() as f64;
+            let a_loss = loss.a(y_pred.clone(), y.clone())?;
+            self.validation_losses
+                .push(a_loss.sum() / a_loss.len() as f64);
+
+            for history in self.metrics.iter_mut() {
+                history
+                    .validation
+                    .push(history.metric.wake().score(&y_pred, &y)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sample(&mut self, x: &Array3<f64>, y: &Array3<f64>) -> (Array3<f64>, Array3<f64>) {
+        let mut indices: Vec<usize> = Vec::with_capacity(self.hyper.sample_size);
+        for _ in 0..self.hyper.sample_size {
+            indices.push(self.rng.gen_range(0..x.shape()[0]));
+        }
+
+        let batch_x_vec = indices
+            .iter()
+            .map(|ix| x.index_axis(Axis(0), *ix))
+            .collect::<Vec<_>>();
+        let batch_y_vec = indices
+            .iter()
+            .map(|ix| y.index_axis(Axis(0), *ix))
+            .collect::<Vec<_>>();
+
+        let batch_x: Array3<f64> = stack(Axis(0), &batch_x_vec).unwrap();
+        let batch_y: Array3<f64> = stack(Axis(0), &batch_y_vec).unwrap();
+
+        (batch_x, batch_y)
+    }
 
-            self.manifold
-                .backwards(y_pred_reshaped, y_reshaped, loss, self.hyper.learning_rate);
+    fn run(
+        &mut self,
+        x: &Array3<f64>,
+        y: &Array3<f64>,
+        step: StepFn<Self>,
+    ) -> Result<&mut Self, ManifoldError> {
+        expect_shape(&[x.shape()[0]], &[y.shape()[0]])?;
+        if let Some((x, y)) = &self.validation {
+            expect_shape(&[x.shape()[0]], &[y.shape()[0]])?;
+        }
+
+        for epoch in self.epoch..self.hyper.epochs {
+            let (batch_x, batch_y) = self.sample(x, y);
+            let (sum_batch_loss, pred, target) = step(self, batch_x, batch_y)?;
+
+            self.regulate(epoch);
+            self.manifold.shift(self.hyper.learning_rate)?;
+            self.record(&pred, &target)?;
 
             self.losses.push(sum_batch_loss);
             self.hyper.learning_rate *= self.hyper.decay;
+            self.epoch = epoch + 1;
+
+            if let Some((path, every)) = &self.checkpoint {
+                if self.epoch.is_multiple_of(*every) {
+                    if let Err(e) = self.checkpoint(path) {
+                        eprintln!("Failed to write checkpoint {:?}: {}", path, e);
+                    }
+                }
+            }
 
             if (&self.early_terminate)(&self.losses) {
                 println!("Early termination condition met, stopping.");
@@ -178,25 +399,454 @@ impl<'a, T: Manifold> MiniBatchGradientDescent<'a, T> {
             }
 
             if self.verbose {
-                println!(
-                    "({}/{}) Loss = {}",
-                    epoch, self.hyper.epochs, sum_batch_loss
-                );
+                match self.validation_losses.last() {
+                    Some(validation_loss) if self.validation.is_some() => println!(
+                        "({}/{}) Loss = {} Validation = {}",
+                        epoch, self.hyper.epochs, sum_batch_loss, validation_loss
+                    ),
+                    _ => println!(
+                        "({}/{}) Loss = {}",
+                        epoch, self.hyper.epochs, sum_batch_loss
+                    ),
+                }
             }
         }
 
+        Ok(self)
+    }
+
+    pub fn history(&self) -> History {
+        History {
+            losses: self.losses.clone(),
+            validation_losses: self.validation_losses.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    /// Exponential smoothing applied to plotted curves, 0 plots raw values.
+    pub fn set_smoothing(&mut self, weight: f64) -> &mut Self {
+        self.smoothing = weight;
         self
     }
 
+    /// Opens the loss curves in a browser, see write_loss_graph for headless
+    /// machines.
     pub fn loss_graph(&mut self) -> &mut Self {
-        let mut plot = Plot::new();
+        line_plot("Loss", &self.history().loss_series(), self.smoothing).show();
+        self
+    }
+
+    /// Train and validation loss as .html or .svg.
+    pub fn write_loss_graph(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        write_plot(path, "Loss", &self.history().loss_series(), self.smoothing)
+    }
 
-        let x = (0..self.losses.len()).collect();
+    /// Every recorded metric as .html or .svg.
+    pub fn write_metric_graph(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        write_plot(
+            path,
+            "Metrics",
+            &self.history().metric_series(),
+            self.smoothing,
+        )
+    }
 
-        let trace = Bar::new(x, self.losses.clone());
-        plot.add_trace(trace);
-        plot.show();
+    /// Losses and metrics as .csv or .json.
+    pub fn export_history(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        self.history().export(path)
+    }
+}
 
-        self
+impl<'a, T> MiniBatchGradientDescent<'a, T>
+where
+    T: Manifold + Serialize + DeserializeOwned + Clone,
+{
+    /// Learning rate range test. Trains for `steps` batches while the rate
+    /// grows exponentially from `min` to `max`, stopping early once the loss
+    /// blows up. The network, its retained gradients and the trainer's rng are
+    /// put back afterward, so only the returned sweep remains.
+    pub fn find_learning_rate(
+        &mut self,
+        x: &Array3<f64>,
+        y: &Array3<f64>,
+        min: f64,
+        max: f64,
+        steps: usize,
+    ) -> Result<LearningRateSweep, ManifoldError> {
+        expect_shape(&[x.shape()[0]], &[y.shape()[0]])?;
+        if !min.is_finite() || min <= 0. || !max.is_finite() || max <= min {
+            return Err(ManifoldError::InvalidHyper(format!(
+                "learning rate sweep needs 0 < min < max, got {}..{}",
+                min, max
+            )));
+        }
+        if steps < 2 {
+            return Err(ManifoldError::InvalidHyper(format!(
+                "learning rate sweep needs at least 2 steps, got {}",
+                steps
+            )));
+        }
+
+        let manifold = self.manifold.clone();
+        let rng = self.rng.clone();
+
+        // Restored whether the sweep finishes or fails part way.
+        let sweep = self.sweep(x, y, min, max, steps);
+        *self.manifold = manifold;
+        self.rng = rng;
+
+        sweep
+    }
+
+    fn sweep(
+        &mut self,
+        x: &Array3<f64>,
+        y: &Array3<f64>,
+        min: f64,
+        max: f64,
+        steps: usize,
+    ) -> Result<LearningRateSweep, ManifoldError> {
+        let mut rates = vec![];
+        let mut losses = vec![];
+        let mut best = f64::MAX;
+
+        for i in 0..steps {
+            let rate = LearningRateSweep::rate(min, max, i, steps);
+
+            let (batch_x, batch_y) = self.sample(x, y);
+            let (loss, _, _) = self.step(batch_x, batch_y)?;
+            self.regulate(i);
+            self.manifold.shift(rate)?;
+
+            rates.push(rate);
+            losses.push(loss);
+
+            if !loss.is_finite() || (i > steps / 10 && loss > 4. * best) {
+                break;
+            }
+            best = best.min(loss);
+
+            if self.verbose {
+                println!("({}/{}) Rate = {} Loss = {}", i, steps, rate, loss);
+            }
+        }
+
+        Ok(LearningRateSweep::new(rates, losses))
+    }
+}
+
+impl<'a, T> MiniBatchGradientDescent<'a, T>
+where
+    T: Manifold + Serialize + DeserializeOwned + Clone + Send + 'static,
+{
+    /// Train with each minibatch split across cores. Every shard runs on a
+    /// replica of the network, and the shard gradients are reduced into the
+    /// network before a single shift against the substrate.
+    pub fn train_parallel(
+        &mut self,
+        x: &Array3<f64>,
+        y: &Array3<f64>,
+    ) -> Result<&mut Self, ManifoldError> {
+        self.run(x, y, Self::parallel_step)
+    }
+
+    fn parallel_step(
+        &mut self,
+        batch_x: Array3<f64>,
+        batch_y: Array3<f64>,
+    ) -> Result<Step, ManifoldError> {
+        let batch_size = batch_x.shape()[0];
+        let shard_size = batch_size
+            .div_ceil(self.threads.clamp(1, batch_size.max(1)))
+            .max(1);
+
+        let mut tasks: Vec<Box<dyn (FnOnce() -> Result<Shard, ManifoldError>) + Send>> = vec![];
+
+        for start in (0..batch_size).step_by(shard_size) {
+            let end = (start + shard_size).min(batch_size);
+            let shard_x = batch_x.slice(s![start..end, .., ..]).to_owned();
+            let shard_y = batch_y.slice(s![start..end, .., ..]).to_owned();
+            // Replicas are fresh clones every step, so without a seed of
+            // their own they would all draw the same dropout masks.
+            let mut replica = self.manifold.clone();
+            replica.set_seed(self.rng.gen());
+
+            tasks.push(Box::new(move || {
+                // Replicas start from zero so only this shard's gradients are
+                // reduced, whatever the network is retaining.
+                for (grad_w, grad_b) in replica.gradients_mut() {
+                    grad_w.fill(0.);
+                    grad_b.fill(0.);
+                }
+
+                let y_pred = replica.forward(shard_x)?.remove_axis(Axis(1));
+                let y = shard_y.remove_axis(Axis(1));
+                expect_shape(y.shape(), y_pred.shape())?;
+
+                let loss = replica.get_loss_fn();
+                let shard_loss = loss.a(y_pred.clone(), y.clone())?.sum();
+                replica.accumulate(y_pred.clone(), y.clone(), loss)?;
+
+                let gradients = replica
+                    .gradients_mut()
+                    .into_iter()
+                    .map(|(grad_w, grad_b)| (grad_w.clone(), grad_b.clone()))
+                    .collect();
+
+                Ok(Shard {
+                    start,
+                    gradients,
+                    touched: replica.touched(),
+                    statistics: replica.statistics(),
+                    loss: shard_loss,
+                    len: end - start,
+                    pred: y_pred,
+                    target: y,
+                })
+            }));
+        }
+
+        let expected = tasks.len();
+        let mut shards = distributed(tasks)
+            .into_iter()
+            .collect::<Result<Vec<Shard>, ManifoldError>>()?;
+        // Shards come back grouped by the thread that ran them, and a thread
+        // that panicked returns none of its own.
+        if shards.len() != expected {
+            return Err(ManifoldError::WorkerPanicked(format!(
+                "{} of {} shards returned",
+                shards.len(),
+                expected
+            )));
+        }
+        shards.sort_by_key(|shard| shard.start);
+        let mut gradients = self.manifold.gradients_mut();
+        let mut batch_loss = 0.;
+
+        for shard in shards.iter() {
+            let weight = shard.len as f64 / batch_size as f64;
+
+            for ((grad_w, grad_b), (shard_w, shard_b)) in
+                gradients.iter_mut().zip(shard.gradients.iter())
+            {
+                grad_w.scaled_add(weight, shard_w);
+                grad_b.scaled_add(weight, shard_b);
+            }
+
+            batch_loss += shard.loss;
+        }
+
+        // Layers shifting only the rows they looked up need every shard's.
+        for shard in shards.iter() {
+            self.manifold.touch(shard.touched.clone());
+        }
+
+        // Running statistics are averaged over shards the way gradients are,
+        // leaving out how far the shard means spread.
+        let mut statistics = self.manifold.statistics();
+        for (mean, var) in statistics.iter_mut() {
+            mean.fill(0.);
+            var.fill(0.);
+        }
+        for shard in shards.iter() {
+            let weight = shard.len as f64 / batch_size as f64;
+
+            for ((mean, var), (shard_mean, shard_var)) in
+                statistics.iter_mut().zip(shard.statistics.iter())
+            {
+                mean.scaled_add(weight, shard_mean);
+                var.scaled_add(weight, shard_var);
+            }
+        }
+        self.manifold.set_statistics(statistics)?;
+
+        let pred = concatenate(
+            Axis(0),
+            &shards.iter().map(|s| s.pred.view()).collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let target = concatenate(
+            Axis(0),
+            &shards.iter().map(|s| s.target.view()).collect::<Vec<_>>(),
+        )
+        .unwrap();
+
+        Ok((batch_loss / batch_size as f64, pred, target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+
+    use ndarray::{concatenate, Array3, Axis};
+    use ndarray_rand::rand_distr::{StandardNormal, Uniform};
+    use ndarray_rand::RandomExt;
+
+    use super::MiniBatchGradientDescent;
+    use crate::activation::Activations;
+    use crate::layers::types::Layers;
+    use crate::manifold::types::Manifold;
+    use crate::manifold::Composable;
+    use crate::optimizers::GradientNoise;
+    use crate::substrate::Substrate;
+
+    fn network(substrate: Arc<Substrate>) -> Composable {
+        let mut nn = Composable::new(substrate, 4, 1);
+        nn.layer(8, Activations::Tanh, Layers::Dense)
+            .weightless(Layers::Dropout { rate: 0.25 });
+        nn.weave().unwrap().gather().unwrap();
+        nn.set_seed(7);
+        nn
+    }
+
+    fn trainer(nn: &mut Composable) -> MiniBatchGradientDescent<'_, Composable> {
+        let mut mbgd = MiniBatchGradientDescent::new(nn);
+        mbgd.set_sample_size(8)
+            .set_gradient_noise(GradientNoise::new(0.5))
+            .set_seed(3);
+        mbgd
+    }
+
+    // Stopping at a scheduled checkpoint and resuming from it trains exactly
+    // as if training had never stopped, dropout masks and noise included.
+    #[test]
+    fn resuming_a_checkpoint_continues_training() {
+        let path = std::env::temp_dir().join(format!("manifold-{}.checkpoint", std::process::id()));
+        let substrate = Substrate::new(10000, -1.0..1.0).share();
+        let x = Array3::random((32, 1, 4), StandardNormal);
+        let y = x.sum_axis(Axis(2)).insert_axis(Axis(2));
+
+        // Only epoch 4 is a multiple of 4, so the file stops there.
+        let mut uninterrupted = network(substrate.clone());
+        let mut mbgd = trainer(&mut uninterrupted);
+        mbgd.set_epochs(6).set_checkpoint(&path, 4);
+        mbgd.train(&x, &y).unwrap();
+        let losses = mbgd.losses.clone();
+
+        let mut resumed = network(substrate.clone());
+        let mut mbgd = MiniBatchGradientDescent::new(&mut resumed);
+        mbgd.resume_from(&path).unwrap();
+        assert_eq!(mbgd.losses.len(), 4);
+        mbgd.train(&x, &y).unwrap();
+        assert_eq!(mbgd.losses, losses);
+        assert_eq!(uninterrupted.bindings(), resumed.bindings());
+
+        // A checkpoint only resumes against the substrate it was trained on.
+        let mut elsewhere = network(Substrate::new(5000, -1.0..1.0).share());
+        assert!(MiniBatchGradientDescent::new(&mut elsewhere)
+            .resume_from(&path)
+            .is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Raising the epoch total after train returns continues the same run.
+    #[test]
+    fn training_again_continues_from_the_last_epoch() {
+        let substrate = Substrate::new(10000, -1.0..1.0).share();
+        let x = Array3::random((32, 1, 4), StandardNormal);
+        let y = x.sum_axis(Axis(2)).insert_axis(Axis(2));
+
+        let mut once = network(substrate);
+        let mut twice = once.clone();
+        let mut mbgd = trainer(&mut once);
+        mbgd.set_epochs(6).train(&x, &y).unwrap();
+        let losses = mbgd.losses.clone();
+
+        let mut mbgd = trainer(&mut twice);
+        mbgd.set_epochs(2).train(&x, &y).unwrap();
+        mbgd.train(&x, &y).unwrap();
+        assert_eq!(mbgd.losses.len(), 2);
+        mbgd.set_epochs(6).train(&x, &y).unwrap();
+
+        assert_eq!(mbgd.losses, losses);
+        assert_eq!(once.bindings(), twice.bindings());
+    }
+
+    // The sweep shifts links to find a rate but hands the network back as it
+    // found it, and bad ranges are refused before anything moves.
+    #[test]
+    fn sweeping_learning_rates_leaves_the_links_alone() {
+        let substrate = Substrate::new(10000, -1.0..1.0).share();
+        let x = Array3::random((32, 1, 4), StandardNormal);
+        let y = x.sum_axis(Axis(2)).insert_axis(Axis(2));
+
+        let mut nn = network(substrate);
+        let bindings = nn.bindings();
+        let mut mbgd = MiniBatchGradientDescent::new(&mut nn);
+        mbgd.set_sample_size(8).set_seed(3);
+
+        let sweep = mbgd.find_learning_rate(&x, &y, 1e-4, 1., 20).unwrap();
+        assert!(!sweep.rates.is_empty());
+
+        assert!(mbgd.find_learning_rate(&x, &y, 0., 1., 20).is_err());
+        assert!(mbgd.find_learning_rate(&x, &y, 1., 1e-4, 20).is_err());
+        assert!(mbgd.find_learning_rate(&x, &y, 1e-4, 1., 1).is_err());
+
+        assert_eq!(nn.bindings(), bindings);
+    }
+
+    // A batch of one block of samples repeated once per shard splits into
+    // shards that all hold the same block, so every shard sees the batch
+    // statistics of the whole batch and the reduction is exact.
+    #[test]
+    fn parallel_step_matches_a_serial_step() {
+        let block = Array3::random((4, 3, 1), Uniform::new(0, 8)).mapv(|id: usize| id as f64);
+        let x = concatenate(Axis(0), &[block.view(), block.view(), block.view()]).unwrap();
+        let y = Array3::random((12, 1, 2), StandardNormal);
+
+        let mut nn = Composable::new(Substrate::new(10000, -1.0..1.0).share(), 1, 2);
+        nn.set_input_shape(vec![3, 1])
+            .layer(
+                4,
+                Activations::Identity,
+                Layers::Embedding { vocabulary: 8 },
+            )
+            .weightless(Layers::BatchNorm {
+                momentum: 0.9,
+                linked: true,
+            })
+            .weightless(Layers::Flatten);
+        nn.weave().unwrap().gather().unwrap();
+
+        let mut serial = nn.clone();
+        let (loss, pred, _) = MiniBatchGradientDescent::new(&mut serial)
+            .step(x.clone(), y.clone())
+            .unwrap();
+        let mut parallel = nn.clone();
+        let (parallel_loss, parallel_pred, _) = MiniBatchGradientDescent::new(&mut parallel)
+            .set_threads(3)
+            .parallel_step(x, y)
+            .unwrap();
+
+        let close = |a: &[f64], b: &[f64]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-9);
+
+        assert!((loss - parallel_loss).abs() < 1e-9);
+        assert!(close(&pred.into_raw_vec(), &parallel_pred.into_raw_vec()));
+        assert!(serial.touched().iter().any(|rows| !rows.is_empty()));
+        assert_eq!(serial.touched(), parallel.touched());
+        for ((w, b), (parallel_w, parallel_b)) in serial
+            .gradients_mut()
+            .into_iter()
+            .zip(parallel.gradients_mut())
+        {
+            assert!(close(w.as_slice().unwrap(), parallel_w.as_slice().unwrap()));
+            assert!(close(b.as_slice().unwrap(), parallel_b.as_slice().unwrap()));
+        }
+        for ((mean, var), (parallel_mean, parallel_var)) in
+            serial.statistics().iter().zip(parallel.statistics().iter())
+        {
+            assert!(close(
+                mean.as_slice().unwrap(),
+                parallel_mean.as_slice().unwrap()
+            ));
+            assert!(close(
+                var.as_slice().unwrap(),
+                parallel_var.as_slice().unwrap()
+            ));
+        }
+        assert_eq!(serial.statistics().len(), 1);
     }
 }
@@ -1,5 +1,17 @@
+mod checkpoint;
+mod gradients;
+mod history;
+mod hogwild;
+mod lr_range;
 mod mbgd;
+mod search;
 mod types;
 
+pub use checkpoint::{Checkpoint, RngState, SubstrateRef};
+pub use gradients::{ClipScope, GradientClip, GradientNoise};
+pub use history::{smooth, History, Series};
+pub use hogwild::Hogwild;
+pub use lr_range::LearningRateSweep;
 pub use mbgd::MiniBatchGradientDescent;
-pub use types::Hyper;
+pub use search::{Configuration, Domain, HyperSearch, Leaderboard, Strategy, Trial};
+pub use types::{Hyper, MetricHistory};
@@ -0,0 +1,811 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, available_parallelism};
+
+use ndarray::Array3;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::{Hyper, MiniBatchGradientDescent};
+use crate::error::{panic_message, ManifoldError};
+use crate::manifold::types::Manifold;
+use crate::substrate::Substrate;
+
+/// Values a searched parameter may take. Continuous domains are sampled
+/// evenly when laid out on a grid.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Domain {
+    Choice(Vec<f64>),
+    Uniform(f64, f64),
+    LogUniform(f64, f64),
+}
+
+impl Domain {
+    /// Choices need at least one value, bounds need to be in order and log
+    /// bounds above zero.
+    pub fn validate(&self) -> Result<(), ManifoldError> {
+        let valid = match self {
+            Domain::Choice(values) => !values.is_empty(),
+            Domain::Uniform(lo, hi) => lo <= hi,
+            Domain::LogUniform(lo, hi) => 0. < *lo && lo <= hi,
+        };
+
+        match valid {
+            true => Ok(()),
+            false => Err(ManifoldError::InvalidSearch(format!(
+                "{:?} has nothing to sample",
+                self
+            ))),
+        }
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> Result<f64, ManifoldError> {
+        self.validate()?;
+
+        Ok(match self {
+            Domain::Choice(values) => values[rng.gen_range(0..values.len())],
+            Domain::Uniform(lo, hi) => rng.gen_range(*lo..=*hi),
+            Domain::LogUniform(lo, hi) => rng.gen_range(lo.ln()..=hi.ln()).exp(),
+        })
+    }
+
+    pub fn grid(&self, points: usize) -> Vec<f64> {
+        let points = points.max(1);
+        let t = |i: usize| match points {
+            1 => 0.5,
+            _ => i as f64 / (points - 1) as f64,
+        };
+
+        match self {
+            Domain::Choice(values) => values.clone(),
+            Domain::Uniform(lo, hi) => (0..points).map(|i| lo + (hi - lo) * t(i)).collect(),
+            Domain::LogUniform(lo, hi) => (0..points).map(|i| lo * (hi / lo).powf(t(i))).collect(),
+        }
+    }
+}
+
+pub enum Strategy {
+    /// Every combination of the space, continuous domains split into
+    /// `grid_points` values.
+    Grid,
+    /// Independent samples of the space, each trained for the full budget.
+    Random(usize),
+    /// Train `trials` samples for `min_epochs`, keep the best 1/eta and train
+    /// those eta times longer, until the full budget is reached.
+    SuccessiveHalving {
+        trials: usize,
+        eta: usize,
+        min_epochs: usize,
+    },
+    /// Successive halving brackets trading trial count against budget.
+    Hyperband { eta: usize, min_epochs: usize },
+}
+
+/// One point of the search space, handed to the network builder.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Configuration {
+    pub hyper: Hyper,
+    pub substrate_size: usize,
+    pub substrate_range: (f64, f64),
+    pub layers: Vec<usize>,
+}
+
+impl Display for Configuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "lr={:.6} decay={} batch={} substrate={}[{}..{}] layers={:?}",
+            self.hyper.learning_rate,
+            self.hyper.decay,
+            self.hyper.sample_size,
+            self.substrate_size,
+            self.substrate_range.0,
+            self.substrate_range.1,
+            self.layers
+        )
+    }
+}
+
+pub struct Trial<T> {
+    pub configuration: Configuration,
+    pub manifold: T,
+    pub epochs: usize,
+    pub score: f64,
+    pub losses: Vec<f64>,
+    pub validation_losses: Vec<f64>,
+    /// Why the trial stopped, when training it panicked. Failed trials score
+    /// infinity and are not trained any further.
+    pub failure: Option<ManifoldError>,
+}
+
+/// Trials ordered best first. Trials that made it further through successive
+/// halving rank ahead of those eliminated earlier, ties broken by score.
+pub struct Leaderboard<T> {
+    pub trials: Vec<Trial<T>>,
+}
+
+impl<T> Leaderboard<T> {
+    fn new(mut trials: Vec<Trial<T>>) -> Leaderboard<T> {
+        trials.sort_by(|a, b| b.epochs.cmp(&a.epochs).then(a.score.total_cmp(&b.score)));
+        Leaderboard { trials }
+    }
+
+    pub fn best(&self) -> Option<&Trial<T>> {
+        self.trials.first()
+    }
+}
+
+impl<T> Display for Leaderboard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>4}  {:>12}  {:>7}  configuration",
+            "rank", "score", "epochs"
+        )?;
+        for (i, trial) in self.trials.iter().enumerate() {
+            write!(
+                f,
+                "{:>4}  {:>12.6}  {:>7}  {}",
+                i + 1,
+                trial.score,
+                trial.epochs,
+                trial.configuration
+            )?;
+            match &trial.failure {
+                Some(e) => writeln!(f, "  ({})", e)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// A trial, the total epochs it should reach and the seed its trainer samples with.
+type Job<T> = (Trial<T>, usize, u64);
+
+/// Searches Hyper fields, substrate settings and layer schemas. Networks are
+/// built per trial by `build`, trained with MiniBatchGradientDescent across
+/// threads and ranked by their final validation loss.
+pub struct HyperSearch<T, F>
+where
+    T: Manifold + Serialize + DeserializeOwned + Send,
+    F: Fn(&Configuration, Arc<Substrate>) -> T + Sync,
+{
+    build: F,
+    hyper: Hyper,
+    strategy: Strategy,
+    learning_rate: Option<Domain>,
+    decay: Option<Domain>,
+    sample_size: Option<Domain>,
+    substrate_size: Domain,
+    substrate_range: Vec<(f64, f64)>,
+    layers: Vec<Vec<usize>>,
+    grid_points: usize,
+    threads: usize,
+    verbose: bool,
+    rng: ChaCha8Rng,
+    validation: Option<(Array3<f64>, Array3<f64>)>,
+    substrates: HashMap<(usize, u64, u64), Arc<Substrate>>,
+}
+
+impl<T, F> HyperSearch<T, F>
+where
+    T: Manifold + Serialize + DeserializeOwned + Send,
+    F: Fn(&Configuration, Arc<Substrate>) -> T + Sync,
+{
+    pub fn new(build: F) -> HyperSearch<T, F> {
+        HyperSearch {
+            build,
+            hyper: Hyper::new(),
+            strategy: Strategy::Random(10),
+            learning_rate: None,
+            decay: None,
+            sample_size: None,
+            substrate_size: Domain::Choice(vec![10000.]),
+            substrate_range: vec![(-1., 1.)],
+            layers: vec![vec![]],
+            grid_points: 3,
+            threads: available_parallelism().map(|n| n.into()).unwrap_or(1),
+            verbose: false,
+            rng: ChaCha8Rng::from_entropy(),
+            validation: None,
+            substrates: HashMap::new(),
+        }
+    }
+
+    /// Fields that are not searched are taken from here. Its epochs are the
+    /// full budget of a trial.
+    pub fn override_hyper(&mut self, hyper: Hyper) -> &mut Self {
+        self.hyper = hyper;
+        self
+    }
+
+    pub fn set_strategy(&mut self, strategy: Strategy) -> &mut Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn set_learning_rate(&mut self, domain: Domain) -> &mut Self {
+        self.learning_rate = Some(domain);
+        self
+    }
+
+    pub fn set_decay(&mut self, domain: Domain) -> &mut Self {
+        self.decay = Some(domain);
+        self
+    }
+
+    pub fn set_sample_size(&mut self, domain: Domain) -> &mut Self {
+        self.sample_size = Some(domain);
+        self
+    }
+
+    pub fn set_substrate_size(&mut self, domain: Domain) -> &mut Self {
+        self.substrate_size = domain;
+        self
+    }
+
+    pub fn set_substrate_range(&mut self, ranges: Vec<(f64, f64)>) -> &mut Self {
+        self.substrate_range = ranges;
+        self
+    }
+
+    pub fn set_layers(&mut self, layers: Vec<Vec<usize>>) -> &mut Self {
+        self.layers = layers;
+        self
+    }
+
+    pub fn set_grid_points(&mut self, points: usize) -> &mut Self {
+        self.grid_points = points;
+        self
+    }
+
+    pub fn set_threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn verbose(&mut self) -> &mut Self {
+        self.verbose = true;
+        self
+    }
+
+    /// Held out set trials are scored on. Without one trials are scored on
+    /// their trailing training loss.
+    pub fn set_validation(&mut self, x: &Array3<f64>, y: &Array3<f64>) -> &mut Self {
+        self.validation = Some((x.to_owned(), y.to_owned()));
+        self
+    }
+
+    pub fn run(
+        &mut self,
+        x: &Array3<f64>,
+        y: &Array3<f64>,
+    ) -> Result<Leaderboard<T>, ManifoldError> {
+        self.validate()?;
+        let budget = self.hyper.epochs;
+
+        let trials = match self.strategy {
+            Strategy::Grid => {
+                let configurations = self.grid();
+                self.rung(configurations, budget, x, y)?
+            }
+            Strategy::Random(n) => {
+                let configurations = (0..n).map(|_| self.sample()).collect::<Result<_, _>>()?;
+                self.rung(configurations, budget, x, y)?
+            }
+            Strategy::SuccessiveHalving {
+                trials,
+                eta,
+                min_epochs,
+            } => {
+                let eta = eta.max(2);
+                let rungs = HyperSearch::<T, F>::rungs(budget, min_epochs, eta);
+                let configurations = (0..trials)
+                    .map(|_| self.sample())
+                    .collect::<Result<_, _>>()?;
+                self.successive_halving(configurations, budget, rungs, eta, x, y)?
+            }
+            Strategy::Hyperband { eta, min_epochs } => {
+                let eta = eta.max(2);
+                let s_max = HyperSearch::<T, F>::rungs(budget, min_epochs, eta);
+                let mut trials = vec![];
+
+                for s in (0..=s_max).rev() {
+                    let brackets = (s_max + 1) as f64 / (s + 1) as f64;
+                    let n = (brackets * eta.saturating_pow(s as u32) as f64).ceil() as usize;
+                    if self.verbose {
+                        println!("Hyperband bracket s={}: {} trials", s, n);
+                    }
+
+                    let configurations = (0..n).map(|_| self.sample()).collect::<Result<_, _>>()?;
+                    trials.append(&mut self.successive_halving(
+                        configurations,
+                        budget,
+                        s,
+                        eta,
+                        x,
+                        y,
+                    )?);
+                }
+
+                trials
+            }
+        };
+
+        Ok(Leaderboard::new(trials))
+    }
+
+    // Every domain, substrate range and layer schema checked before a single
+    // trial is built.
+    fn validate(&self) -> Result<(), ManifoldError> {
+        for domain in [&self.learning_rate, &self.decay, &self.sample_size]
+            .into_iter()
+            .flatten()
+            .chain([&self.substrate_size])
+        {
+            domain.validate()?;
+        }
+
+        if self.substrate_range.is_empty() {
+            return Err(ManifoldError::InvalidSearch(
+                "No substrate range to search".to_string(),
+            ));
+        }
+        if let Some((lo, hi)) = self
+            .substrate_range
+            .iter()
+            .find(|(lo, hi)| lo >= hi || !lo.is_finite() || !hi.is_finite())
+        {
+            return Err(ManifoldError::InvalidSearch(format!(
+                "Substrate range {}..{} is empty or unbounded",
+                lo, hi
+            )));
+        }
+        if self.layers.is_empty() {
+            return Err(ManifoldError::InvalidSearch(
+                "No layer schema to search".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Number of times min_epochs can be multiplied by eta within the budget.
+    fn rungs(budget: usize, min_epochs: usize, eta: usize) -> usize {
+        let mut rungs = 0;
+        let mut epochs = min_epochs.max(1);
+        while let Some(next) = epochs.checked_mul(eta).filter(|next| *next <= budget) {
+            epochs = next;
+            rungs += 1;
+        }
+        rungs
+    }
+
+    fn successive_halving(
+        &mut self,
+        configurations: Vec<Configuration>,
+        budget: usize,
+        rungs: usize,
+        eta: usize,
+        x: &Array3<f64>,
+        y: &Array3<f64>,
+    ) -> Result<Vec<Trial<T>>, ManifoldError> {
+        let mut finished = vec![];
+        let mut alive = configurations
+            .into_iter()
+            .map(|configuration| self.spawn(configuration))
+            .collect::<Vec<Trial<T>>>();
+
+        for rung in 0..=rungs {
+            let epochs = budget / eta.saturating_pow((rungs - rung) as u32);
+            let jobs = alive
+                .drain(..)
+                .map(|trial| (trial, epochs.max(1), self.rng.gen()))
+                .collect();
+
+            alive = self.execute(jobs, x, y)?;
+            alive.sort_by(|a, b| a.score.total_cmp(&b.score));
+
+            if rung < rungs {
+                let keep = (alive.len() / eta).max(1);
+                finished.extend(alive.drain(keep..));
+            }
+        }
+
+        finished.append(&mut alive);
+        Ok(finished)
+    }
+
+    fn rung(
+        &mut self,
+        configurations: Vec<Configuration>,
+        epochs: usize,
+        x: &Array3<f64>,
+        y: &Array3<f64>,
+    ) -> Result<Vec<Trial<T>>, ManifoldError> {
+        let jobs = configurations
+            .into_iter()
+            .map(|configuration| (self.spawn(configuration), epochs, self.rng.gen()))
+            .collect();
+
+        self.execute(jobs, x, y)
+    }
+
+    fn substrate(&mut self, size: usize, range: (f64, f64)) -> Arc<Substrate> {
+        self.substrates
+            .entry((size, range.0.to_bits(), range.1.to_bits()))
+            .or_insert_with(|| Substrate::new(size, range.0..range.1).share())
+            .clone()
+    }
+
+    fn spawn(&mut self, configuration: Configuration) -> Trial<T> {
+        let substrate = self.substrate(configuration.substrate_size, configuration.substrate_range);
+        let manifold = (self.build)(&configuration, substrate);
+
+        Trial {
+            configuration,
+            manifold,
+            epochs: 0,
+            score: f64::INFINITY,
+            losses: vec![],
+            validation_losses: vec![],
+            failure: None,
+        }
+    }
+
+    fn configuration(
+        &self,
+        learning_rate: f64,
+        decay: f64,
+        sample_size: f64,
+        substrate_size: f64,
+        substrate_range: (f64, f64),
+        layers: Vec<usize>,
+    ) -> Configuration {
+        let mut hyper = self.hyper.clone();
+        hyper.learning_rate = learning_rate;
+        hyper.decay = decay;
+        hyper.sample_size = (sample_size.round() as usize).max(1);
+
+        Configuration {
+            hyper,
+            substrate_size: (substrate_size.round() as usize).max(2),
+            substrate_range,
+            layers,
+        }
+    }
+
+    fn sample(&mut self) -> Result<Configuration, ManifoldError> {
+        let rng = &mut self.rng;
+        let learning_rate = match &self.learning_rate {
+            Some(domain) => domain.sample(rng)?,
+            None => self.hyper.learning_rate,
+        };
+        let decay = match &self.decay {
+            Some(domain) => domain.sample(rng)?,
+            None => self.hyper.decay,
+        };
+        let sample_size = match &self.sample_size {
+            Some(domain) => domain.sample(rng)?,
+            None => self.hyper.sample_size as f64,
+        };
+        let substrate_size = self.substrate_size.sample(rng)?;
+        let substrate_range = *self.substrate_range.choose(rng).ok_or_else(|| {
+            ManifoldError::InvalidSearch("No substrate range to search".to_string())
+        })?;
+        let layers = self
+            .layers
+            .choose(rng)
+            .ok_or_else(|| ManifoldError::InvalidSearch("No layer schema to search".to_string()))?
+            .clone();
+
+        Ok(self.configuration(
+            learning_rate,
+            decay,
+            sample_size,
+            substrate_size,
+            substrate_range,
+            layers,
+        ))
+    }
+
+    fn grid(&self) -> Vec<Configuration> {
+        let axis = |domain: &Option<Domain>, fallback: f64| match domain {
+            Some(domain) => domain.grid(self.grid_points),
+            None => vec![fallback],
+        };
+
+        let learning_rates = axis(&self.learning_rate, self.hyper.learning_rate);
+        let decays = axis(&self.decay, self.hyper.decay);
+        let sample_sizes = axis(&self.sample_size, self.hyper.sample_size as f64);
+        let substrate_sizes = self.substrate_size.grid(self.grid_points);
+
+        let mut configurations = vec![];
+        for learning_rate in learning_rates.iter() {
+            for decay in decays.iter() {
+                for sample_size in sample_sizes.iter() {
+                    for substrate_size in substrate_sizes.iter() {
+                        for substrate_range in self.substrate_range.iter() {
+                            for layers in self.layers.iter() {
+                                configurations.push(self.configuration(
+                                    *learning_rate,
+                                    *decay,
+                                    *sample_size,
+                                    *substrate_size,
+                                    *substrate_range,
+                                    layers.clone(),
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        configurations
+    }
+
+    // Runs jobs on a pool of threads, each pulling the next job when free.
+    fn execute(
+        &self,
+        jobs: Vec<Job<T>>,
+        x: &Array3<f64>,
+        y: &Array3<f64>,
+    ) -> Result<Vec<Trial<T>>, ManifoldError> {
+        let queue = Mutex::new(jobs);
+        let done = Mutex::new(vec![]);
+
+        thread::scope(|s| {
+            for _ in 0..self.threads {
+                s.spawn(|| loop {
+                    let job = queue.lock().unwrap().pop();
+                    let (mut trial, epochs, seed) = match job {
+                        Some(job) => job,
+                        None => break,
+                    };
+
+                    // A trial that panics fails alone, the search goes on.
+                    let advanced = catch_unwind(AssertUnwindSafe(|| {
+                        self.advance(&mut trial, epochs, seed, x, y)
+                    }));
+                    match advanced {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => {
+                            done.lock().unwrap().push(Err(e));
+                            continue;
+                        }
+                        Err(panic) => {
+                            trial.score = f64::INFINITY;
+                            trial.failure =
+                                Some(ManifoldError::WorkerPanicked(panic_message(panic.as_ref())));
+                        }
+                    }
+                    if self.verbose {
+                        println!(
+                            "({} epochs) score = {} {}",
+                            trial.epochs, trial.score, trial.configuration
+                        );
+                    }
+                    done.lock().unwrap().push(Ok(trial));
+                });
+            }
+        });
+
+        done.into_inner().unwrap().into_iter().collect()
+    }
+
+    // Continue training a trial up to `epochs`, picking the decayed learning
+    // rate back up where the last rung left it.
+    fn advance(
+        &self,
+        trial: &mut Trial<T>,
+        epochs: usize,
+        seed: u64,
+        x: &Array3<f64>,
+        y: &Array3<f64>,
+    ) -> Result<(), ManifoldError> {
+        if epochs <= trial.epochs || trial.failure.is_some() {
+            return Ok(());
+        }
+
+        let mut hyper = trial.configuration.hyper.clone();
+        hyper.learning_rate *= hyper.decay.powi(trial.epochs as i32);
+        hyper.epochs = epochs - trial.epochs;
+
+        let mut trainer = MiniBatchGradientDescent::new(&mut trial.manifold);
+        trainer.override_hyper(hyper).set_seed(seed);
+        if let Some((vx, vy)) = &self.validation {
+            trainer.set_validation(vx, vy);
+        }
+        trainer.train(x, y)?;
+
+        let losses = std::mem::take(&mut trainer.losses);
+        let validation_losses = std::mem::take(&mut trainer.validation_losses);
+
+        trial.epochs = epochs;
+        trial.losses.extend(losses);
+        trial.validation_losses.extend(validation_losses);
+        trial.score = HyperSearch::<T, F>::score(trial);
+        Ok(())
+    }
+
+    // Final validation loss, or the mean of the last few batch losses when
+    // no validation set was given. Diverged trials sink to the bottom.
+    fn score(trial: &Trial<T>) -> f64 {
+        let score = match trial.validation_losses.last() {
+            Some(loss) => *loss,
+            None => {
+                let tail = &trial.losses[trial.losses.len().saturating_sub(10)..];
+                tail.iter().sum::<f64>() / tail.len().max(1) as f64
+            }
+        };
+
+        match score.is_finite() {
+            true => score,
+            false => f64::INFINITY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use ndarray::{Array3, Axis};
+    use ndarray_rand::rand_distr::StandardNormal;
+    use ndarray_rand::RandomExt;
+
+    use super::{Configuration, Domain, HyperSearch, Leaderboard, Strategy};
+    use crate::error::ManifoldError;
+    use crate::manifold::types::Manifold;
+    use crate::manifold::DNN;
+    use crate::optimizers::Hyper;
+    use crate::substrate::Substrate;
+
+    // The target is the sum of the inputs plus one, which a linear network
+    // drawing every weight and bias from (0.999, 1) already fits. The other
+    // ranges can't come close, so the winner is known up front.
+    const RANGES: [(f64, f64); 3] = [(-1., -0.999), (0., 0.001), (0.999, 1.)];
+
+    fn data(samples: usize) -> (Array3<f64>, Array3<f64>) {
+        let x = Array3::random((samples, 1, 4), StandardNormal);
+        let y = (x.sum_axis(Axis(2)) + 1.).insert_axis(Axis(2));
+        (x, y)
+    }
+
+    fn search(strategy: Strategy, x: &Array3<f64>, y: &Array3<f64>) -> Leaderboard<DNN> {
+        let mut hyper = Hyper::new();
+        hyper.epochs = 8;
+        hyper.sample_size = 16;
+
+        let build = |configuration: &Configuration, substrate: Arc<Substrate>| {
+            let mut nn = DNN::new(substrate, 4, 1, configuration.layers.clone());
+            nn.weave().unwrap().gather().unwrap();
+            nn
+        };
+
+        let mut search = HyperSearch::new(build);
+        search
+            .override_hyper(hyper)
+            .set_strategy(strategy)
+            .set_learning_rate(Domain::Choice(vec![0.001, 0.01]))
+            .set_substrate_size(Domain::Choice(vec![100.]))
+            .set_substrate_range(RANGES.to_vec())
+            .set_threads(2)
+            .set_seed(11);
+        if !x.is_empty() {
+            search.set_validation(x, y);
+        }
+        search.run(x, y).unwrap()
+    }
+
+    // How many trials stopped at each epoch count, every trial having
+    // recorded a loss per epoch it trained.
+    fn budgets(leaderboard: &Leaderboard<DNN>) -> BTreeMap<usize, usize> {
+        let mut budgets = BTreeMap::new();
+        for trial in leaderboard.trials.iter() {
+            assert_eq!(trial.losses.len(), trial.epochs);
+            *budgets.entry(trial.epochs).or_insert(0) += 1;
+        }
+        budgets
+    }
+
+    fn assert_winner(leaderboard: &Leaderboard<DNN>) {
+        let best = leaderboard.best().unwrap();
+        assert_eq!(best.epochs, 8);
+        assert!(best.failure.is_none());
+        assert_eq!(best.configuration.substrate_range, RANGES[2]);
+    }
+
+    #[test]
+    fn grid_trains_every_combination_for_the_full_budget() {
+        let (x, y) = data(64);
+        let leaderboard = search(Strategy::Grid, &x, &y);
+
+        assert_eq!(budgets(&leaderboard), BTreeMap::from([(8, 6)]));
+        assert_winner(&leaderboard);
+    }
+
+    #[test]
+    fn random_trains_every_sample_for_the_full_budget() {
+        let (x, y) = data(64);
+        let leaderboard = search(Strategy::Random(6), &x, &y);
+
+        assert_eq!(budgets(&leaderboard), BTreeMap::from([(8, 6)]));
+        assert_winner(&leaderboard);
+    }
+
+    // 6 trials at 2 epochs, the best 3 at 4 and the best one at 8.
+    #[test]
+    fn successive_halving_keeps_the_best_of_every_rung() {
+        let (x, y) = data(64);
+        let strategy = Strategy::SuccessiveHalving {
+            trials: 6,
+            eta: 2,
+            min_epochs: 2,
+        };
+        let leaderboard = search(strategy, &x, &y);
+
+        assert_eq!(
+            budgets(&leaderboard),
+            BTreeMap::from([(2, 3), (4, 2), (8, 1)])
+        );
+        assert_winner(&leaderboard);
+    }
+
+    // Brackets of 4 trials from 2 epochs, 3 from 4 and 3 at the full 8.
+    #[test]
+    fn hyperband_runs_every_bracket() {
+        let (x, y) = data(64);
+        let strategy = Strategy::Hyperband {
+            eta: 2,
+            min_epochs: 2,
+        };
+        let leaderboard = search(strategy, &x, &y);
+
+        assert_eq!(
+            budgets(&leaderboard),
+            BTreeMap::from([(2, 2), (4, 3), (8, 5)])
+        );
+        assert_winner(&leaderboard);
+    }
+
+    #[test]
+    fn huge_eta_leaves_a_single_full_budget_bracket() {
+        let (x, y) = data(64);
+        let strategy = Strategy::Hyperband {
+            eta: usize::MAX,
+            min_epochs: 2,
+        };
+        let leaderboard = search(strategy, &x, &y);
+
+        assert_eq!(budgets(&leaderboard), BTreeMap::from([(8, 1)]));
+    }
+
+    // Sampling a batch out of no samples panics inside the trainer, which
+    // fails each trial without ending the search.
+    #[test]
+    fn panicking_trials_are_reported_as_failed() {
+        let (x, y) = data(0);
+        let leaderboard = search(Strategy::Random(3), &x, &y);
+
+        assert_eq!(leaderboard.trials.len(), 3);
+        for trial in leaderboard.trials.iter() {
+            assert!(matches!(
+                trial.failure,
+                Some(ManifoldError::WorkerPanicked(_))
+            ));
+            assert_eq!(trial.score, f64::INFINITY);
+        }
+    }
+}
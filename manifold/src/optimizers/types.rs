@@ -1,4 +1,9 @@
-#[derive(Clone)]
+use serde::{Deserialize, Serialize};
+
+use super::gradients::{GradientClip, GradientNoise};
+use crate::metric::Metrics;
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Hyper {
     pub epochs: usize,
     pub sample_size: usize,
@@ -7,6 +12,8 @@ pub struct Hyper {
     pub patience: usize,
     pub min_delta: f64,
     pub early_stopping: bool,
+    pub clip: Option<GradientClip>,
+    pub noise: Option<GradientNoise>,
 }
 
 impl Hyper {
@@ -19,6 +26,25 @@ impl Hyper {
             patience: 0,
             min_delta: 0.,
             early_stopping: false,
+            clip: None,
+            noise: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetricHistory {
+    pub metric: Metrics,
+    pub train: Vec<f64>,
+    pub validation: Vec<f64>,
+}
+
+impl MetricHistory {
+    pub fn new(metric: Metrics) -> MetricHistory {
+        MetricHistory {
+            metric,
+            train: vec![],
+            validation: vec![],
         }
     }
 }
@@ -7,13 +7,15 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 
-use ndarray::Array2;
+use ndarray::{Array, Array2, Dimension};
 
 use rand::distributions::Uniform;
 use rand::prelude::*;
 use rand::thread_rng;
 use serde::{Deserialize, Serialize};
 
+use crate::error::ManifoldError;
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Substrate {
     weights: VecDeque<f64>,
@@ -51,15 +53,56 @@ impl Substrate {
         }
     }
 
-    pub fn get(&self, i: usize) -> f64 {
-        let w = self.weights.get(i).expect(
-            format!(
-                "Tried to access Substrate[{}] from Substrate[{}..{}]",
-                i, 0, self.size
-            )
-            .as_str(),
-        );
-        *w
+    pub fn get(&self, i: usize) -> Result<f64, ManifoldError> {
+        self.weights
+            .get(i)
+            .copied()
+            .ok_or(ManifoldError::SubstrateIndex {
+                index: i,
+                size: self.size,
+            })
+    }
+
+    // Values behind every link, failing on the first link outside the pool.
+    pub fn gather<D: Dimension>(
+        &self,
+        links: &Array<usize, D>,
+    ) -> Result<Array<f64, D>, ManifoldError> {
+        if let Some(index) = links.iter().find(|ix| **ix >= self.weights.len()) {
+            return Err(ManifoldError::SubstrateIndex {
+                index: *index,
+                size: self.size,
+            });
+        }
+
+        Ok(links.map(|ix| self.weights[*ix]))
+    }
+
+    /// Memory held by the pool itself.
+    pub fn bytes(&self) -> usize {
+        self.weights.len() * std::mem::size_of::<f64>()
+    }
+
+    pub fn step(&self) -> f64 {
+        self.size as f64 / 1000.
+    }
+
+    // Index of the first pooled value >= value, the pool is sorted.
+    pub fn nearest(&self, value: f64) -> usize {
+        self.weights.partition_point(|w| *w < value).min(self.size)
+    }
+
+    // FNV-1a over the pool, so a checkpoint can tell whether links still
+    // point into the same substrate they were trained against.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for w in self.weights.iter() {
+            for byte in w.to_bits().to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
     }
 
     pub fn share(self) -> Arc<Self> {
@@ -81,7 +124,7 @@ impl Substrate {
 
         // Assume a step is 1/1000 of the substrate
         // Define everything else in terms of step
-        let step = self.size as f64 / 1000.;
+        let step = self.step();
 
         // Combine highspeed rate with step for gradient element-wise influence on link.
         let mut gradient_steps = gradient.map(|x| step * learning_rate * x);
@@ -3,7 +3,9 @@ use std::{
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use ndarray::{stack, Array1, Array2, Array3, Axis};
+use ndarray::{Array1, Array3};
+
+use crate::error::{expect_shape, reshape, ManifoldError};
 
 pub fn timestamp() -> Result<u64, Box<dyn Error>> {
     let start = SystemTime::now();
@@ -17,26 +19,24 @@ pub fn timestamp() -> Result<u64, Box<dyn Error>> {
     Ok(in_ms)
 }
 
-pub fn as_tensor(x: Vec<Vec<f64>>, y: Vec<Vec<f64>>) -> (Array3<f64>, Array3<f64>) {
-    let x_a2 = x
-        .into_iter()
-        .map(|xv| Array1::from(xv).insert_axis(Axis(0)))
-        .collect::<Vec<Array2<f64>>>();
-    let y_a2 = y
-        .into_iter()
-        .map(|yv| Array1::from(yv).insert_axis(Axis(0)))
-        .collect::<Vec<Array2<f64>>>();
-
-    let x_3 = stack(
-        Axis(0),
-        &x_a2.iter().map(|ar| ar.view()).collect::<Vec<_>>(),
-    )
-    .unwrap();
-    let y_3 = stack(
-        Axis(0),
-        &y_a2.iter().map(|ar| ar.view()).collect::<Vec<_>>(),
-    )
-    .unwrap();
-
-    (x_3, y_3)
+// Rows become samples of shape (1, features), every row must be as wide as the first.
+pub fn tensor(rows: Vec<Vec<f64>>) -> Result<Array3<f64>, ManifoldError> {
+    let features = rows.first().map(|row| row.len()).unwrap_or(0);
+
+    if let Some(row) = rows.iter().find(|row| row.len() != features) {
+        return Err(ManifoldError::ShapeMismatch {
+            expected: vec![features],
+            found: vec![row.len()],
+        });
+    }
+
+    reshape(Array1::from(rows.concat()), (rows.len(), 1, features))
+}
+
+pub fn as_tensor(
+    x: Vec<Vec<f64>>,
+    y: Vec<Vec<f64>>,
+) -> Result<(Array3<f64>, Array3<f64>), ManifoldError> {
+    expect_shape(&[x.len()], &[y.len()])?;
+    Ok((tensor(x)?, tensor(y)?))
 }